@@ -161,15 +161,85 @@ fn sign2i(n: vec2<i32>) -> vec2<i32> {
     );
 }
 
+const COMMAND_MOVE: u32 = 1u;
+// Replace the selection with the geese inside select_region
+const COMMAND_SELECT: u32 = 2u;
+// Selected geese join control_group, the rest leave it
+const COMMAND_ASSIGN_GROUP: u32 = 3u;
+// Select the geese in control_group
+const COMMAND_RECALL_GROUP: u32 = 4u;
+const COMMAND_STOP: u32 = 5u;
+const COMMAND_HOLD: u32 = 6u;
+const COMMAND_ATTACK_MOVE: u32 = 7u;
+const COMMAND_PATROL: u32 = 8u;
+
+// Groups 1 to CONTROL_GROUPS, 0 is no group
+const CONTROL_GROUPS: u32 = 7u;
+
 struct UnitCommand {
-    select_region: vec4<u32>,
+    // Corners of a quad on the ground in order around it, xy and zw of each
+    select_region: array<vec4<f32>, 2>,
     dest: vec2<u32>,
     command: u32,
     delta_time: f32,
     upgrade_request: u32,
     unit_group: u32, //1 is hydra, 2 is units
+    match_frame: u32, // frames since the match started
+    selected_only: u32, // move orders only apply to selected geese
+    control_group: u32,
+    hydra: u32, // slot + 1 of the one hydra hydra orders go to, 0 for all of them
+    spawn_mix: u32, // new spawn_mix weights packed with pack_4x8_ and w 1, 0 keeps the old mix
+    tick_alpha: f32, // fraction of a tick since the last one, 0 when ticking every frame
+};
+
+// Bit of upgrade_request that hatches another hydra
+const HATCH_REQUEST: u32 = 8u;
+
+const MAX_TEAMS: u32 = 4u;
+
+// One command block per team, indexed by team - 1
+struct TeamCommands {
+    teams: array<UnitCommand, 4>,
+    seed: u32, // seed of the match
+};
+
+const MAX_PATHS: u32 = 4u;
+const MAX_WAYPOINTS: u32 = 8u;
+// Units this close to a waypoint head for the next one
+const WAYPOINT_RADIUS: f32 = 12.0;
+
+// Waypoints queued by one team, paths are matched by the unit's dest
+struct WaypointPaths {
+    // MAX_WAYPOINTS per path, packed with pack_2x16_, 4 per element
+    points: array<vec4<u32>, 8>,
+    lengths: vec4<u32>,
+    // Non zero if the path goes back to its first point after the last, used by patrols
+    looping: vec4<u32>,
+};
+
+// One set of paths per team, indexed by team - 1
+struct TeamWaypoints {
+    teams: array<WaypointPaths, 4>,
+};
+
+// Distance in every flow field of cells the nav grid blocks
+const FLOW_BLOCKED: u32 = 4294967295u;
+// Distance of cells a flow field hasn't spread to yet
+const FLOW_UNREACHED: u32 = 4294967294u;
+
+// Water shallower than this is waded through, see SHALLOW_WATER_DEPTH in nav_grid.rs
+const SHALLOW_WATER_DEPTH: f32 = 1.0;
+
+// Where each team's geese were last sent, the flow field in the team's channel leads there
+struct FlowFieldTargets {
+    // Packed with pack_2x16_, indexed by team - 1
+    dests: vec4<u32>,
+    // Bit per team, set once the team has a field
+    in_use: u32,
+    // Bit per team, set for the pass a field starts over from a new dest
+    reset: u32,
+    spare1_: u32,
     spare2_: u32,
-    spare3_: u32,
 };
 
 struct Unit {
@@ -180,6 +250,11 @@ struct Unit {
     mode: u32,
     team: u32,
     attacking_hydra: u32,
+    attacking_team: u32, // team of the hydra being attacked
+    order: u32, // UNIT_ORDER_*, unlike mode this lasts until the next order
+    unit_type: u32, // UNIT_TYPE_*
+    selected: bool,
+    group: u32, // control group
     id: u32,    
 }
 
@@ -193,20 +268,27 @@ struct LargeUnit {
     progress: f32,
     team: u32,
     dir_index: u32,
+    order: u32,
 }
 
+// Steps per cell of the 16 bit fixed point hydra destination, see LARGE_UNIT_DEST_SCALE in units.rs
+const LARGE_UNIT_DEST_SCALE: f32 = 32.0;
+
 fn unpack_large_unit(data: vec4<u32>, ufrag_coord: vec2<u32>) -> LargeUnit {
     var unit: LargeUnit;
 
     // f16 was not accurate enough for pos given a small enough delta time
     unit.pos = vec2(bitcast<f32>(data.x), bitcast<f32>(data.y)); 
-    unit.dest = unpack2x16float(data.z) * 0.01;
+    unit.dest = vec2<f32>(unpack_2x16_(data.z)) / LARGE_UNIT_DEST_SCALE;
     let d1 = unpack_2x16_(data.w);
     let d1b = unpack_4x8_(d1.y);
+    let mode_order = unpack_2x4_from_8(d1b.x);
     unit.health = d1.x;
-    unit.mode = d1b.x;
+    unit.mode = mode_order.x;
     unit.dir_index = d1b.y;
-    unit.team = select(1u, 2u, ufrag_coord.y == 1u);
+    // Only the low 16 bits are free, so the order shares a byte with the mode
+    unit.order = mode_order.y;
+    unit.team = ufrag_coord.y + 1u;
 
     return unit;
 }
@@ -218,10 +300,10 @@ fn pack_large_unit(unit: LargeUnit) -> vec4<u32> {
     // f16 was not accurate enough for pos given a small enough delta time
     data.x = bitcast<u32>(unit.pos.x);
     data.y = bitcast<u32>(unit.pos.y);
-    data.z = pack2x16float(unit.dest * 100.0);
+    data.z = pack_2x16_(vec2<u32>(clamp(unit.dest * LARGE_UNIT_DEST_SCALE + 0.5, vec2(0.0), vec2(65535.0))));
     data.w = pack_2x16_(vec2(
         unit.health,
-        pack_4x8_(vec4(unit.mode, unit.dir_index, 0u, 0u)),
+        pack_4x8_(vec4(pack_2x4_to_8(vec2(unit.mode, unit.order)), unit.dir_index, 0u, 0u)),
     ));
 
     return data;
@@ -235,17 +317,85 @@ const UNIT_MODE_MOVEING: u32 = 2u;
 const UNIT_MODE_ATTACK: u32 = 3u;
 const UNIT_MODE_ATTACK_HYDRA: u32 = 4u;
 
-const SPEED_MOVE: f32 = 5.0;
-const SPEED_ATTACK: f32 = 1.0;
-const SMALL_UNIT_SIZE: f32 = 1.0;
+// Idle units fight anything in range
+const UNIT_ORDER_NONE: u32 = 0u;
+// Ignore enemies until the end of the path
+const UNIT_ORDER_MOVE: u32 = 1u;
+// Fight but never move
+const UNIT_ORDER_HOLD: u32 = 2u;
+// Fight anything met on the way
+const UNIT_ORDER_ATTACK_MOVE: u32 = 3u;
+// Attack move around a looping path
+const UNIT_ORDER_PATROL: u32 = 4u;
+
+// Goose types, see UnitTypeStats
+const UNIT_TYPE_RANGED: u32 = 0u;
+const UNIT_TYPE_MELEE: u32 = 1u;
+const UNIT_TYPE_SUPPORT: u32 = 2u;
+const UNIT_TYPES: u32 = 3u;
+
+// The order a unit gets from a COMMAND_*, UNIT_ORDER_NONE if it isn't an order
+fn command_order(command: u32) -> u32 {
+    var order = UNIT_ORDER_NONE;
+    order = select(order, UNIT_ORDER_MOVE, command == COMMAND_MOVE);
+    order = select(order, UNIT_ORDER_HOLD, command == COMMAND_HOLD);
+    order = select(order, UNIT_ORDER_ATTACK_MOVE, command == COMMAND_ATTACK_MOVE);
+    order = select(order, UNIT_ORDER_PATROL, command == COMMAND_PATROL);
+    return order;
+}
 
-const LARGE_SPEED_MOVE: f32 = 5.0;
-const LARGE_SPEED_ATTACK: f32 = 1.0;
+const SMALL_UNIT_SIZE: f32 = 1.0;
 const LARGE_UNIT_SIZE: f32 = 4.0;  
-const HYDRA_INIT_HEALTH: u32 = 25000u;  
 
-const SPAWN_RADIUS: f32 = 8.0;
-const SPAWN_RATE: f32 = 0.6;
+struct TeamModifier {
+    health: f32,
+    attack_rate: f32,
+    spawn_rate: f32,
+    move_rate: f32,
+    upgrade_cost: f32,
+    spare1_: f32,
+    spare2_: f32,
+    spare3_: f32,
+};
+
+struct UnitTypeStats {
+    range: f32, // attack range in cells, at most ATTACK_RADIUS
+    damage: f32,
+    move_rate: f32,
+    health: f32,
+    texture_layer: u32, // added to the layer of SmallGoose.ktx2 geese are drawn with
+    spawn_weight: u32, // share of the geese hydras spawn until the team picks its own mix
+    spare1_: u32,
+    spare2_: u32,
+    tint: vec3<f32>, // multiplies the base color
+};
+
+// Loaded from assets/units.balance.ron, see BalanceConfig in balance.rs
+struct BalanceConfig {
+    speed_move: f32,
+    speed_attack: f32,
+    large_speed_move: f32,
+    large_speed_attack: f32,
+    spawn_radius: f32,
+    spawn_rate: f32,
+    hydra_init_health: u32,
+    upgrade_cost: u32,
+    land_move_rate: f32,
+    shallow_water_move_rate: f32,
+    deep_water_move_rate: f32,
+    hatch_cost: u32,
+    unit_types: array<UnitTypeStats, 3>, // indexed by UNIT_TYPE_*
+    teams: array<TeamModifier, 4>, // indexed by team - 1
+};
+
+// Set by the difficulty, see AiSettings in difficulty.rs
+struct AiSettings {
+    upgrade_order: vec4<u32>, // xyz: upgrade index, 0 movement, 1 attack, 2 spawn
+    income: f32,
+    attack_interval: u32, // only used by the CPU AI
+    hydra_aggro_radius: f32,
+    spare1_: u32,
+};
 
 struct UnitStats {
     move_rate: f32,
@@ -255,31 +405,230 @@ struct UnitStats {
     large_attack_rate: f32,
     spawn_radius: f32,
     spawn_rate: f32,
+    spawn_health: u32,
+    hydra_health: u32,
 }
 
 // Why can't I use #{LARGE_UNITS_DATA_WIDTH}u here?
-fn get_unit_stats(large_unit_tex: texture_2d<u32>, ludw: u32, team: u32) -> UnitStats {
+fn get_unit_stats(large_unit_tex: texture_2d<u32>, ludw: u32, team: u32, balance: BalanceConfig) -> UnitStats {
     var stats: UnitStats;
-    let team1_buff = select(1.0, 1.15, team == 1u);
+    let modifier = team_modifier(team, balance);
     let upgrades = sqrt(vec4<f32>(textureLoad(large_unit_tex, vec2(ludw + 1u, team - 1u), 0) + 1u));
-    stats.move_rate = upgrades.x * SPEED_MOVE;
-    stats.attack_rate = upgrades.y * SPEED_ATTACK * team1_buff;
+    stats.move_rate = upgrades.x * balance.speed_move * modifier.move_rate;
+    stats.attack_rate = upgrades.y * balance.speed_attack * modifier.attack_rate;
     stats.attack_mult = upgrades.y * 0.2;
-    stats.large_move_rate = upgrades.x * LARGE_SPEED_MOVE;
-    stats.large_attack_rate = upgrades.y * LARGE_SPEED_ATTACK;
-    stats.spawn_radius = upgrades.z * SPAWN_RADIUS;
-    stats.spawn_rate = upgrades.z * SPAWN_RATE * team1_buff;
+    stats.large_move_rate = upgrades.x * balance.large_speed_move * modifier.move_rate;
+    stats.large_attack_rate = upgrades.y * balance.large_speed_attack * modifier.attack_rate;
+    stats.spawn_radius = upgrades.z * balance.spawn_radius;
+    stats.spawn_rate = upgrades.z * balance.spawn_rate * modifier.spawn_rate;
+    stats.spawn_health = u32(min(255.0 * modifier.health, 255.0));
+    stats.hydra_health = u32(f32(balance.hydra_init_health) * modifier.health);
     return stats;
 }
 
+// Normalized, scale by the grid size. Teams 1 and 2 face off vertically, 3 and 4 horizontally.
+fn hydra_start(team: u32) -> vec2<f32> {
+    switch team {
+        case 2u: { return vec2(0.5, 0.5859375); }
+        case 3u: { return vec2(0.40234375, 0.48828125); }
+        case 4u: { return vec2(0.59765625, 0.48828125); }
+        default: { return vec2(0.5, 0.390625); }
+    }
+}
+
+fn team_color(team: u32) -> vec3<f32> {
+    switch team {
+        case 1u: { return vec3(0.02, 0.15, 0.02); }
+        case 2u: { return vec3(0.4, 0.02, 0.02); }
+        case 3u: { return vec3(0.02, 0.05, 0.4); }
+        case 4u: { return vec3(0.35, 0.25, 0.02); }
+        default: { return vec3(0.0); }
+    }
+}
+
+// Minimap texels have one channel per team: geese alive in the low bits, geese that died this
+// frame above them. Downsampling sums texels, sum across texels with minimap_sum.
+const MINIMAP_ALIVE_BITS: u32 = 17u;
+
+fn minimap_alive(data: vec4<u32>) -> vec4<u32> {
+    return data & vec4((1u << MINIMAP_ALIVE_BITS) - 1u);
+}
+
+fn minimap_died(data: vec4<u32>) -> vec4<u32> {
+    return data >> vec4(MINIMAP_ALIVE_BITS);
+}
+
+fn pack_minimap(alive: vec4<u32>, died: vec4<u32>) -> vec4<u32> {
+    return alive | (died << vec4(MINIMAP_ALIVE_BITS));
+}
+
+struct MinimapSum {
+    alive: vec4<u32>,
+    died: vec4<u32>,
+};
+
+fn minimap_sum(minimap_sm3_texture: texture_2d<u32>) -> MinimapSum {
+    var sum: MinimapSum;
+    let dimensions = vec2<i32>(textureDimensions(minimap_sm3_texture).xy);
+
+    for (var x = 0; x < dimensions.x; x += 1) {
+        for (var y = 0; y < dimensions.y; y += 1) {
+            let data = textureLoad(minimap_sm3_texture, vec2(x, y), 0);
+            sum.alive += minimap_alive(data);
+            sum.died += minimap_died(data);
+        }
+    }
+
+    return sum;
+}
+
+fn team_waypoints(waypoints: TeamWaypoints, team: u32) -> WaypointPaths {
+    // Arrays passed by value can only be indexed by a constant
+    var teams = waypoints.teams;
+    return teams[clamp(team, 1u, MAX_TEAMS) - 1u];
+}
+
+// The waypoint after dest in whichever path has dest in it, dest if there is none
+fn next_waypoint(paths: WaypointPaths, dest: vec2<f32>) -> vec2<f32> {
+    var points = paths.points;
+    for (var path = 0u; path < MAX_PATHS; path += 1u) {
+        let len = paths.lengths[path];
+        // Looping paths carry on from the last point back to the first
+        let count = select(max(len, 1u) - 1u, len, paths.looping[path] != 0u);
+        for (var i = 0u; i < count; i += 1u) {
+            let index = path * MAX_WAYPOINTS + i;
+            let point = vec2<f32>(unpack_2x16_(points[index / 4u][index % 4u]));
+            if distance(point, dest) < 1.0 {
+                let next = path * MAX_WAYPOINTS + (i + 1u) % len;
+                return vec2<f32>(unpack_2x16_(points[next / 4u][next % 4u]));
+            }
+        }
+    }
+    return dest;
+}
+
+// If the flow field of team's channel leads to dest
+fn has_flow_field(targets: FlowFieldTargets, team: u32, dest: vec2<u32>) -> bool {
+    let index = clamp(team, 1u, MAX_TEAMS) - 1u;
+    return ((targets.in_use >> index) & 1u) != 0u && targets.dests[index] == pack_2x16_(dest);
+}
+
+// The 8 cells around the center of a 3x3 square, i from 0 to 7
+fn neighbour_offset(i: u32) -> vec2<i32> {
+    let cell = i + u32(i >= 4u);
+    return vec2(i32(cell % 3u) - 1, i32(cell / 3u) - 1);
+}
+
+// If p is inside the quad of UnitCommand.select_region, the corners can go either way around
+fn in_select_region(region: array<vec4<f32>, 2>, p: vec2<f32>) -> bool {
+    var corners = array(region[0].xy, region[0].zw, region[1].xy, region[1].zw);
+    var positive = 0u;
+    var negative = 0u;
+    for (var i = 0u; i < 4u; i += 1u) {
+        let edge = corners[(i + 1u) % 4u] - corners[i];
+        let to_p = p - corners[i];
+        let side = edge.x * to_p.y - edge.y * to_p.x;
+        positive += u32(side > 0.0);
+        negative += u32(side < 0.0);
+    }
+    return positive == 0u || negative == 0u;
+}
+
+// Delta time of each simulation tick, the same for every team
+fn tick_delta(commands: TeamCommands) -> f32 {
+    return commands.teams[0].delta_time;
+}
+
+// Stands in for globals.frame_count in the simulation's random choices, so a match only depends
+// on its seed and the orders given and not on how many frames were rendered
+fn rng_frame(commands: TeamCommands) -> u32 {
+    return sampling::uhash(commands.seed, commands.teams[0].match_frame);
+}
+
+fn team_command(commands: TeamCommands, team: u32) -> UnitCommand {
+    // Arrays passed by value can only be indexed by a constant
+    var teams = commands.teams;
+    return teams[clamp(team, 1u, MAX_TEAMS) - 1u];
+}
+
+fn team_modifier(team: u32, balance: BalanceConfig) -> TeamModifier {
+    // Arrays passed by value can only be indexed by a constant
+    var teams = balance.teams;
+    return teams[clamp(team, 1u, MAX_TEAMS) - 1u];
+}
+
+fn unit_type_stats(unit_type: u32, balance: BalanceConfig) -> UnitTypeStats {
+    // Arrays passed by value can only be indexed by a constant
+    var unit_types = balance.unit_types;
+    return unit_types[min(unit_type, UNIT_TYPES - 1u)];
+}
+
+// Damage of each hit, attack_mult comes from the team's upgrades
+fn attack_damage(stats: UnitStats, unit_type: u32, balance: BalanceConfig) -> u32 {
+    let damage = unit_type_stats(unit_type, balance).damage;
+    return u32(ceil(f32(1u + u32(stats.attack_mult)) * damage));
+}
+
+// Weights of each UNIT_TYPE_* among the geese the team's hydras spawn. A mix the team picked is
+// kept in the w of its upgrade texel, until then it's the spawn_weight of each type.
+fn spawn_mix(large_unit_tex: texture_2d<u32>, ludw: u32, team: u32, balance: BalanceConfig) -> vec3<f32> {
+    let picked = unpack_4x8_(textureLoad(large_unit_tex, vec2(ludw + 1u, team - 1u), 0).w);
+    if picked.w != 0u {
+        return vec3<f32>(picked.xyz);
+    }
+    var unit_types = balance.unit_types;
+    return vec3(
+        f32(unit_types[0].spawn_weight),
+        f32(unit_types[1].spawn_weight),
+        f32(unit_types[2].spawn_weight),
+    );
+}
+
+// Picks a UNIT_TYPE_* by mix, rng is from 0 to 1
+fn pick_unit_type(mix: vec3<f32>, rng: f32) -> u32 {
+    let pick = rng * (mix.x + mix.y + mix.z);
+    if pick < mix.x {
+        return UNIT_TYPE_RANGED;
+    } else if pick < mix.x + mix.y {
+        return UNIT_TYPE_MELEE;
+    }
+    return select(UNIT_TYPE_RANGED, UNIT_TYPE_SUPPORT, mix.z > 0.0);
+}
+
+fn upgrade_cost(level: u32, team: u32, balance: BalanceConfig) -> u32 {
+    let cost = balance.upgrade_cost * u32(sqrt(f32(level + 1u)));
+    return u32(f32(cost) * team_modifier(team, balance).upgrade_cost);
+}
+
+// x: height units stand at, y: water depth, see NavGrid::terrain_texels in nav_grid.rs
+fn load_terrain(terrain: texture_2d<f32>, cell: vec2<i32>) -> vec2<f32> {
+    let dims = vec2<i32>(textureDimensions(terrain));
+    return textureLoad(terrain, clamp(cell, vec2(0), dims - 1), 0).xy;
+}
+
+// Move rate multiplier of geese at a cell, see load_terrain
+fn terrain_move_rate(water_depth: f32, balance: BalanceConfig) -> f32 {
+    if water_depth <= 0.0 {
+        return balance.land_move_rate;
+    } else if water_depth < SHALLOW_WATER_DEPTH {
+        return balance.shallow_water_move_rate;
+    }
+    return balance.deep_water_move_rate;
+}
+
 fn unpack_unit(data: vec4<u32>) -> Unit {
     var unit: Unit;
-    let a = unpack2x16float(data.x);
-    unit.progress = a.x;
-    unit.attacking_hydra = u32(a.y);
+    let a = unpack_2x16_(data.x);
+    unit.progress = unpack2x16float(a.x).x;
+    unit.attacking_hydra = a.y & 0xFFu;
+    unit.order = (a.y >> 8u) & 0xFu;
+    unit.unit_type = a.y >> 12u;
     let d = unpack_4x8_(data.y);
     unit.step_dir = vec2<i32>(unpack_2x4_from_8(d.x)) - 1;
-    // d.y is spare
+    let d_y = unpack_2x4_from_8(d.y);
+    unit.attacking_team = d_y.x;
+    unit.selected = (d_y.y & 1u) != 0u;
+    unit.group = d_y.y >> 1u;
     unit.health = d.z;
     let mode_team = unpack_2x4_from_8(d.w);
     unit.mode = mode_team.x; 
@@ -291,14 +640,16 @@ fn unpack_unit(data: vec4<u32>) -> Unit {
 
 fn pack_unit(unit: Unit) -> vec4<u32> {
     return vec4<u32>(
-        // TODO don't need 16 bits here for attacking_hydra, shouldn't use float
-        pack2x16float(vec2(unit.progress, f32(unit.attacking_hydra))), 
+        pack_2x16_(vec2(
+            pack2x16float(vec2(unit.progress, 0.0)),
+            unit.attacking_hydra | (unit.order << 8u) | (unit.unit_type << 12u),
+        )),
         pack_4x8_(vec4(
                 pack_2x4_to_8(vec2(
                     u32(unit.step_dir.x + 1),
                     u32(unit.step_dir.y + 1), 
                 )), 
-                0u, //spare
+                pack_2x4_to_8(vec2(unit.attacking_team, u32(unit.selected) | (unit.group << 1u))),
                 unit.health, 
                 pack_2x4_to_8(vec2(unit.mode, unit.team)),
         )),
@@ -0,0 +1,48 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+#import "shaders/common.wgsl" as com
+
+@group(0) @binding(101) var prev_flow: texture_2d<u32>;
+@group(0) @binding(102) var nav_grid: texture_2d<u32>;
+@group(0) @binding(103) var<uniform> targets: com::FlowFieldTargets;
+
+// One step of a breadth first search out from each team's dest, a channel per team. Every cell
+// takes the lowest distance around it plus the step there, so fields spread a cell per pass.
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
+    let ufrag_coord = vec2<u32>(in.position.xy);
+    let ifrag_coord = vec2<i32>(ufrag_coord);
+    let dims = vec2<i32>(textureDimensions(prev_flow));
+
+    if textureLoad(nav_grid, ifrag_coord, 0).x != 0u {
+        return vec4(com::FLOW_BLOCKED);
+    }
+
+    let bits = vec4(0u, 1u, 2u, 3u);
+    let in_use = ((vec4(targets.in_use) >> bits) & 1u) != vec4(0u);
+    let reset = ((vec4(targets.reset) >> bits) & 1u) != vec4(0u);
+    let at_dest = vec4(
+        all(ufrag_coord == com::unpack_2x16_(targets.dests.x)),
+        all(ufrag_coord == com::unpack_2x16_(targets.dests.y)),
+        all(ufrag_coord == com::unpack_2x16_(targets.dests.z)),
+        all(ufrag_coord == com::unpack_2x16_(targets.dests.w)),
+    );
+
+    let prev = textureLoad(prev_flow, ifrag_coord, 0);
+    var flow = select(min(prev, vec4(com::FLOW_UNREACHED)), vec4(com::FLOW_UNREACHED), reset);
+    for (var i = 0u; i < 8u; i += 1u) {
+        let offset = com::neighbour_offset(i);
+        let coord = ifrag_coord + offset;
+        if any(coord < vec2(0)) || any(coord >= dims) {
+            continue;
+        }
+        let neighbour = textureLoad(prev_flow, coord, 0);
+        // Same as FLOW_STEP_COST
+        let step = select(3u, 2u, offset.x == 0 || offset.y == 0);
+        let reached = neighbour < vec4(com::FLOW_UNREACHED) & !reset;
+        flow = select(flow, min(flow, neighbour + step), reached);
+    }
+    flow = select(flow, vec4(0u), at_dest);
+
+    return select(vec4(com::FLOW_UNREACHED), flow, in_use);
+}
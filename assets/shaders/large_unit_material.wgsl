@@ -20,7 +20,7 @@
 #import bevy_pbr::utils::PI
 
 @group(0) @binding(101) var data_texture: texture_2d<u32>;
-@group(0) @binding(102) var<uniform> commands: com::UnitCommand;
+@group(0) @binding(102) var<uniform> commands: com::TeamCommands;
 @group(0) @binding(103) var attack_texture: texture_2d<u32>;
 // Cursed, but work on both webgl2 and native
 // Discussion: https://discord.com/channels/691052431525675048/743663924229963868/1182466862190186627
@@ -29,6 +29,8 @@
 @group(0) @binding(106) var large_unit_tex: texture_2d<u32>;
 // Couldn't reuse unit_texture in WebGL2 for some reason. It would show small goose.
 @group(0) @binding(107) var big_goose_texture: texture_2d_array<f32>;
+@group(0) @binding(110) var<uniform> balance: com::BalanceConfig;
+@group(0) @binding(115) var terrain: texture_2d<f32>;
 
 struct VertexOutput {
     // this is `clip position` when the struct is used as a vertex stage output 
@@ -83,7 +85,17 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     ));
 
 
-    var center = vec3(f32(unit.pos.x), 1.9, f32(unit.pos.y));
+    // Carry on for the part of a tick since the last one, see large_unit_update.wgsl
+    var pos = unit.pos;
+    if unit.mode == com::UNIT_MODE_MOVEING && distance(unit.dest, unit.pos) > 0.1 {
+        let command = com::team_command(commands, unit.team);
+        let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team, balance);
+        let step = unit_stats.large_move_rate * command.tick_alpha * com::tick_delta(commands);
+        pos += clamp(normalize(unit.dest - unit.pos), vec2(-1.0), vec2(1.0)) * min(step, distance(unit.dest, unit.pos));
+    }
+
+    let surface = com::load_terrain(terrain, vec2<i32>(pos)).x;
+    var center = vec3(f32(pos.x), 1.9 + surface, f32(pos.y));
 
     //let center = vec3(2.0, 2.0, 0.0);
 
@@ -148,8 +160,7 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     pbr.material.base_color = vec4(vec3(0.1), 1.0);
     pbr.material.reflectance = 0.5;
     
-    var color = select(vec3(0.4, 0.02, 0.02), vec3(0.02, 0.15, 0.02), unit.team == 1u);
-    color = select(color, vec3(0.0, 0.0, 0.0), unit.team == 0u);
+    var color = com::team_color(unit.team);
     //pbr.material.base_color = vec4(color, 1.0);
 
 
@@ -161,7 +172,7 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
 
     if unit.mode == com::UNIT_MODE_MOVEING {
         let rng = sampling::hash_noise(in.udata_xy, 136521u);
-        index += (u32(globals.time * com::LARGE_SPEED_MOVE * 3.5 + rng * 3.0) % 3u) * 8u;
+        index += (u32(globals.time * balance.large_speed_move * 3.5 + rng * 3.0) % 3u) * 8u;
     }
 
     // Cursed, but work on both webgl2 and native
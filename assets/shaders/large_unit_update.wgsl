@@ -9,80 +9,112 @@
 
 
 @group(0) @binding(101) var data_texture: texture_2d<u32>;
-@group(0) @binding(102) var<uniform> command: com::UnitCommand;
+@group(0) @binding(102) var<uniform> commands: com::TeamCommands;
 @group(0) @binding(103) var attack_texture: texture_2d<u32>;
 @group(0) @binding(106) var large_unit_tex: texture_2d<u32>;
 @group(0) @binding(108) var minimap_sm_texture: texture_2d<u32>;
 @group(0) @binding(109) var minimap_sm3_texture: texture_2d<u32>;
+@group(0) @binding(110) var<uniform> balance: com::BalanceConfig;
+@group(0) @binding(111) var<uniform> ai: com::AiSettings;
+@group(0) @binding(112) var<uniform> waypoints: com::TeamWaypoints;
 
 
-fn get_minimap_sum() -> vec4<u32> {
-    var sum = vec4(0u);
+// What a team buys this frame, every pixel of its row works it out the same way
+struct Purchases {
+    upgrades: vec4<u32>, // levels bought, indexed like the upgrade column
+    credits: u32, // left over
+    hatch_slot: u32, // free slot the new hydra goes in, LARGE_UNITS_DATA_WIDTH if none hatches
+    hatch_pos: vec2<f32>, // of the hydra it hatches next to
+}
 
-    for (var x = 0; x < 2; x += 1) {
-        for (var y = 0; y < 2; y += 1) {
-            let offset = vec2(x, y);
-            let data = textureLoad(minimap_sm3_texture, offset, 0);
-            sum += data;
+// team is the row, 0 based
+fn team_purchases(team: u32) -> Purchases {
+    var out: Purchases;
+    let prev_tracker = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u, team), 0);
+    let prev_upgrade = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u + 1u, team), 0);
+    var credits = prev_tracker.y;
+    // The player buys in a fixed order, the AI in the order the difficulty picks
+    let upgrade_order = select(vec3(1u, 2u, 0u), ai.upgrade_order.xyz, team != 0u);
+    let command = commands.teams[team];
+    for (var i = 0u; i < 3u; i += 1u) {
+        let upgrade = upgrade_order[i];
+        let upgrade_request = (command.upgrade_request & (1u << upgrade)) > 0u;
+        let upgrade_cost = com::upgrade_cost(prev_upgrade[upgrade], team + 1u, balance);
+        if upgrade_request && credits > upgrade_cost {
+            credits -= upgrade_cost;
+            out.upgrades[upgrade] += 1u;
         }
     }
 
-    return sum;
+    // Hatches next to the hydra hydra orders go to if it's alive, the first living one otherwise
+    out.hatch_slot = #{LARGE_UNITS_DATA_WIDTH}u;
+    if (command.upgrade_request & com::HATCH_REQUEST) != 0u && credits > balance.hatch_cost {
+        var free_slot = #{LARGE_UNITS_DATA_WIDTH}u;
+        var parent_slot = #{LARGE_UNITS_DATA_WIDTH}u;
+        for (var slot = 0u; slot < #{LARGE_UNITS_DATA_WIDTH}u; slot += 1u) {
+            let data = textureLoad(large_unit_tex, vec2(slot, team), 0);
+            if com::unpack_large_unit(data, vec2(slot, team)).health == 0u {
+                free_slot = min(free_slot, slot);
+            } else if parent_slot == #{LARGE_UNITS_DATA_WIDTH}u || slot + 1u == command.hydra {
+                parent_slot = slot;
+            }
+        }
+        if free_slot < #{LARGE_UNITS_DATA_WIDTH}u && parent_slot < #{LARGE_UNITS_DATA_WIDTH}u {
+            credits -= balance.hatch_cost;
+            out.hatch_slot = free_slot;
+            let parent_data = textureLoad(large_unit_tex, vec2(parent_slot, team), 0);
+            out.hatch_pos = com::unpack_large_unit(parent_data, vec2(parent_slot, team)).pos;
+        }
+    }
+    out.credits = credits;
+    return out;
 }
 
+// Whether a goose attacking a hydra hits it this frame
+fn hydra_hit(progress: f32) -> bool {
+#ifdef DETERMINISTIC_ATTACKS
+    // Progress only reaches 1 on the last frame of each attack
+    return progress >= 1.0;
+#else
+    return progress > 0.9;
+#endif
+}
 
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
     let frag_coord = in.position.xy;
     let ufrag_coord = vec2<u32>(frag_coord);
     let ifrag_coord = vec2<i32>(ufrag_coord);
+    let frame = com::rng_frame(commands);
     
+    let purchases = team_purchases(ufrag_coord.y);
 
     if ufrag_coord.x >= #{LARGE_UNITS_DATA_WIDTH}u {
-        var out = vec4(0u);
         // Process players
-        let team = select(0u, 1u, ufrag_coord.y == 1u);
-        let other_team = select(1u, 0u, ufrag_coord.y == 1u);
-        var prev_tracker = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u, ufrag_coord.y), 0);
-        var prev_upgrade = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u + 1u, ufrag_coord.y), 0);
+        let team = ufrag_coord.y;
         if ufrag_coord.x == #{LARGE_UNITS_DATA_WIDTH}u + 1u {
-            out = prev_upgrade;
-        }
-        var credits = prev_tracker.y;
-        let upgrade_request_movment = (command.upgrade_request & 1u) > 0u || team == 1u; // AI just auto upgrades everything
-        let upgrade_request_attack = (command.upgrade_request & 2u) > 0u || team == 1u;
-        let upgrade_request_spawn = (command.upgrade_request & 4u) > 0u || team == 1u;
-        let upgrade_movment_cost = 100u * u32(sqrt(f32(prev_upgrade.x + 1u)));
-        let upgrade_attack_cost = 100u * u32(sqrt(f32(prev_upgrade.y + 1u)));
-        let upgrade_spawn_cost = 100u * u32(sqrt(f32(prev_upgrade.z + 1u)));
-        if upgrade_request_attack && credits > upgrade_attack_cost {
-            credits -= upgrade_attack_cost;
-            if ufrag_coord.x == #{LARGE_UNITS_DATA_WIDTH}u + 1u {
-                out.y += 1u;
-            }
-        }
-        if upgrade_request_spawn && credits > upgrade_spawn_cost {
-            credits -= upgrade_spawn_cost;
-            if ufrag_coord.x == #{LARGE_UNITS_DATA_WIDTH}u + 1u {
-                out.z += 1u;
-            }
-        }
-        // This is last so AI upgrades spawn and movment first
-        if upgrade_request_movment && credits > upgrade_movment_cost {
-            credits -= upgrade_movment_cost;
-            if ufrag_coord.x == #{LARGE_UNITS_DATA_WIDTH}u + 1u {
-                out.x += 1u;
-            }
+            let prev_upgrade = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u + 1u, ufrag_coord.y), 0);
+            var upgrade = prev_upgrade + purchases.upgrades;
+            // w is the spawn mix the team picked, see com::spawn_mix
+            let spawn_mix = commands.teams[team].spawn_mix;
+            upgrade.w = select(prev_upgrade.w, spawn_mix, spawn_mix != 0u);
+            return upgrade;
         }
-        if ufrag_coord.x == #{LARGE_UNITS_DATA_WIDTH}u {
-            let minimap_sum = get_minimap_sum(); 
-            // Died tracker
-            out.x = minimap_sum[team + 2u] + prev_tracker.x;
-
-            // Credits tracker
-            out.y = minimap_sum[other_team + 2u] * 3u + credits; 
-            
-        } 
+        var out = vec4(0u);
+        var prev_tracker = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u, ufrag_coord.y), 0);
+        let minimap_sum = com::minimap_sum(minimap_sm3_texture); 
+        // Died tracker, the minimap is rebuilt after every tick so each death is counted once
+        out.x = minimap_sum.died[team] + prev_tracker.x;
+
+        // Credits tracker, kills aren't tracked so each enemy death is split between the other teams
+        let died = minimap_sum.died;
+        let enemies_died = died.x + died.y + died.z + died.w - died[team];
+        let income = select(1.0, ai.income, team != 0u);
+        let earned = f32(enemies_died) * 3.0 / f32(#{TEAMS}u - 1u) * income + bitcast<f32>(prev_tracker.z);
+        let whole = floor(earned);
+        out.y = u32(whole) + purchases.credits;
+        // Fractions of a credit carry over to the next tick
+        out.z = bitcast<u32>(earned - whole);
         return out;
     }
 
@@ -92,54 +124,85 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
 
     let data = textureLoad(large_unit_tex, ifrag_coord, 0);
     var unit = com::unpack_large_unit(data, ufrag_coord);
-    let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team);
+    let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team, balance);
     
 
+    // --- Hatch ---
+    if ufrag_coord.x == purchases.hatch_slot {
+        unit = com::unpack_large_unit(vec4(0u), ufrag_coord);
+        unit.health = unit_stats.hydra_health;
+        let angle = sampling::hash_noise(ufrag_coord, frame + 28411u) * 6.2831853;
+        unit.pos = purchases.hatch_pos + vec2(cos(angle), sin(angle)) * com::LARGE_UNIT_SIZE;
+        unit.dest = unit.pos;
+        return com::pack_large_unit(unit);
+    }
+
     // --- Random spawn ---
-    let rng = sampling::hash_noise(ufrag_coord + globals.frame_count, globals.frame_count + 34121u);
+    let rng = sampling::hash_noise(ufrag_coord + frame, frame + 34121u);
     // if unit.health == 0u && distance(rng, 0.5) < 0.001 * globals.delta_time {
-    if unit.health == 0u && ufrag_coord.x == 0u && globals.frame_count < 5000u {
+    let command = com::team_command(commands, unit.team);
+    if unit.health == 0u && ufrag_coord.x == 0u && unit.team <= #{TEAMS}u && command.match_frame < 5000u {
         unit = com::unpack_large_unit(vec4(0u), ufrag_coord);
-        unit.health = com::HYDRA_INIT_HEALTH;
+        unit.health = unit_stats.hydra_health;
         var spawn = vec2(
-            sampling::hash_noise(ufrag_coord + globals.frame_count, globals.frame_count + 43567u),
-            sampling::hash_noise(ufrag_coord + globals.frame_count, globals.frame_count + 56423u),
+            sampling::hash_noise(ufrag_coord + frame, frame + 43567u),
+            sampling::hash_noise(ufrag_coord + frame, frame + 56423u),
         );
         spawn.x *= 0.25;
         spawn.x = select(spawn.x, spawn.x + 0.75, unit.team == 2u);
         spawn *= vec2(#{UNITS_DATA_WIDTH}.0, #{UNITS_DATA_HEIGHT}.0);
-        unit.pos = select(vec2(256.0, 200.0), vec2(256.0, 300.0), unit.team == 2u);
+        unit.pos = com::hydra_start(unit.team) * vec2(#{UNITS_DATA_WIDTH}.0, #{UNITS_DATA_HEIGHT}.0);
         unit.dest = unit.pos;
         return com::pack_large_unit(unit);
     }
 
-    if command.command > 0u && unit.health > 0u && unit.team == 1u && command.unit_group == 0u {
+    let ordered = unit.health > 0u && command.unit_group == 0u && 
+                  (command.hydra == 0u || command.hydra == ufrag_coord.x + 1u);
+    if ordered && (command.command == com::COMMAND_MOVE || 
+                   command.command == com::COMMAND_ATTACK_MOVE || 
+                   command.command == com::COMMAND_PATROL) {
         unit.dest = vec2<f32>(command.dest);
+        unit.order = com::command_order(command.command);
         if unit.mode != com::UNIT_MODE_MOVEING {
             unit.mode = com::UNIT_MODE_MOVEING;
             unit.progress = 0.0;
         }
     }
+    if ordered && (command.command == com::COMMAND_STOP || command.command == com::COMMAND_HOLD) {
+        unit.dest = unit.pos;
+        unit.order = com::command_order(command.command);
+        unit.mode = com::UNIT_MODE_IDLE;
+    }
 
     if unit.mode == com::UNIT_MODE_MOVEING {
-        // Will look funny at 1000FPS
+        // Will look funny at 1000FPS without a tick rate
         if distance(unit.dest, unit.pos) > 0.1 {
-            unit.pos += clamp(normalize(unit.dest - unit.pos), vec2(-1.0), vec2(1.0)) * unit_stats.large_move_rate * globals.delta_time;
+            unit.pos += clamp(normalize(unit.dest - unit.pos), vec2(-1.0), vec2(1.0)) * unit_stats.large_move_rate * com::tick_delta(commands);
         } else {
             unit.mode = com::UNIT_MODE_IDLE;
         }
     }
 
+    // Arrived, carry on along the queued path if there is one
+    if unit.health > 0u && unit.mode == com::UNIT_MODE_IDLE && unit.order != com::UNIT_ORDER_HOLD {
+        let next = com::next_waypoint(com::team_waypoints(waypoints, unit.team), unit.dest);
+        if any(next != unit.dest) {
+            unit.dest = next;
+            unit.mode = com::UNIT_MODE_MOVEING;
+        }
+    }
+
     // See if there's any other large units in close proximity and if so move away a bit
-    var other_rng = sampling::hash_noise(ufrag_coord, globals.frame_count + 45245u);
+    var other_rng = sampling::hash_noise(ufrag_coord, frame + 45245u);
     let other_unit_frag_coord = vec2(i32(other_rng * #{LARGE_UNITS_DATA_WIDTH}.0), ifrag_coord.y);
     let other_data = textureLoad(large_unit_tex, other_unit_frag_coord, 0);
     var other_unit = com::unpack_large_unit(other_data, vec2<u32>(other_unit_frag_coord));
-    if unit.mode == com::UNIT_MODE_IDLE {
+    // Holding hydras don't make way
+    if unit.mode == com::UNIT_MODE_IDLE && unit.order != com::UNIT_ORDER_HOLD {
         if other_unit.health > 0u && other_unit_frag_coord.x != ifrag_coord.x && distance(other_unit.pos, unit.pos) < com::LARGE_UNIT_SIZE {
             var roam_rng = vec2(
-                sampling::hash_noise(ufrag_coord, globals.frame_count + 67821u),
-                sampling::hash_noise(ufrag_coord, globals.frame_count + 15348u),
+                sampling::hash_noise(ufrag_coord, frame + 67821u),
+                sampling::hash_noise(ufrag_coord, frame + 15348u),
             ) * 2.0 - 1.0;
             unit.dest += roam_rng * com::LARGE_UNIT_SIZE;
             unit.mode = com::UNIT_MODE_MOVEING;
@@ -169,12 +232,12 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
 
             let other_data = textureLoad(data_texture, read_coord, 0);
             let other_unit = com::unpack_unit(other_data);
-            let other_unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, other_unit.team);
-            let attack_damage = 1u + u32(other_unit_stats.attack_mult);
+            let other_unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, other_unit.team, balance);
+            let attack_damage = com::attack_damage(other_unit_stats, other_unit.unit_type, balance);
 
             if attack_damage > 0u && other_unit.attacking_hydra > 0u && 
-               other_unit.attacking_hydra - 1u == ufrag_coord.x && other_unit.team != unit.team &&
-               other_unit.progress > 0.9 {
+               other_unit.attacking_hydra - 1u == ufrag_coord.x && other_unit.attacking_team == unit.team &&
+               hydra_hit(other_unit.progress) {
                 var health = i32(unit.health) - i32(attack_damage);
                 unit.health = u32(max(health, 0));
                 if unit.health == 0u {
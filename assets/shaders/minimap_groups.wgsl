@@ -0,0 +1,39 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_pbr::mesh_view_bindings::{view, globals}
+
+#import "shaders/common.wgsl" as com
+
+@group(0) @binding(101) var data_texture: texture_2d<u32>;
+
+// Counts the player's geese per control group, downsampled like the minimap.
+// Two 16 bit counts per channel: groups 1 to CONTROL_GROUPS, then the selected geese.
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
+    let frag_coord = in.position.xy;
+    let ifrag_coord = vec2<i32>(frag_coord) * #{MINIMAP_SCALE};
+
+    var counts = array<u32, 8>();
+
+    for (var x = 0; x < #{MINIMAP_SCALE}; x += 1) {
+        for (var y = 0; y < #{MINIMAP_SCALE}; y += 1) {
+            let offset = vec2(x, y);
+            let data = textureLoad(data_texture, ifrag_coord + offset, 0);
+            var unit = com::unpack_unit(data);
+            if unit.health > 0u && unit.id > 0u && unit.team == 1u {
+                if unit.group > 0u && unit.group <= com::CONTROL_GROUPS {
+                    counts[unit.group - 1u] += 1u;
+                }
+                if unit.selected {
+                    counts[com::CONTROL_GROUPS] += 1u;
+                }
+            }
+        }
+    }
+    
+    return vec4(
+        counts[0] | (counts[1] << 16u),
+        counts[2] | (counts[3] << 16u),
+        counts[4] | (counts[5] << 16u),
+        counts[6] | (counts[7] << 16u),
+    );
+}
@@ -17,31 +17,24 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
     let frag_coord = in.position.xy;
     let ifrag_coord = vec2<i32>(frag_coord) * #{MINIMAP_SCALE};
 
-    var out = vec4(0u);
+    var alive = vec4(0u);
+    var died = vec4(0u);
 
     for (var x = 0; x < #{MINIMAP_SCALE}; x += 1) {
         for (var y = 0; y < #{MINIMAP_SCALE}; y += 1) {
             let offset = vec2(x, y);
             let data = textureLoad(data_texture, ifrag_coord + offset, 0);
             var unit = com::unpack_unit(data);
-            if unit.health > 0u && unit.id > 0u {
-                if unit.team == 1u {
-                    out.x += 1u; // team 1 unit count
-                } else if unit.team == 2u {
-                    out.y += 1u; // team 2 unit count
-                }
+            if unit.health > 0u && unit.id > 0u && unit.team > 0u && unit.team <= com::MAX_TEAMS {
+                alive[unit.team - 1u] += 1u;
             }
-            if unit.health == 0u && unit.id > 0u {
-                if unit.id == 1u {
-                    out.z += 1u; // team 1 units died this round
-                } else if unit.id == 2u {
-                    out.w += 1u; // team 2 units died this round
-                }
+            // Dead units leave their team in the id for a frame
+            if unit.health == 0u && unit.id > 0u && unit.id <= com::MAX_TEAMS {
+                died[unit.id - 1u] += 1u;
             }
-
         }
     }
     
-    return out;
+    return com::pack_minimap(alive, died);
 }
 
@@ -9,20 +9,7 @@
 @group(0) @binding(103) var minimap_texture: texture_2d<u32>;
 @group(0) @binding(104) var minimap_sm3_texture: texture_2d<u32>;
 @group(0) @binding(105) var large_unit_tex: texture_2d<u32>;
-
-fn get_minimap_sum() -> vec4<u32> {
-    var sum = vec4(0u);
-
-    for (var x = 0; x < 2; x += 1) {
-        for (var y = 0; y < 2; y += 1) {
-            let offset = vec2(x, y);
-            let data = textureLoad(minimap_sm3_texture, offset, 0);
-            sum += data;
-        }
-    }
-
-    return sum;
-}
+@group(0) @binding(106) var<uniform> balance: com::BalanceConfig;
 
 fn print_value(
     frag_coord: vec2<f32>,
@@ -84,48 +71,58 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
         var mapping = vec2(minimap_dimensions.x - ucoord.x, minimap_dimensions.y - ucoord.y); //view.viewport.y / 720.0
         color = vec4(vec3(0.0), 1.0);
         let minimap = textureLoad(minimap_texture, mapping, 0);
-        let died = minimap.b + minimap.a;
-        color.r = f32(minimap.y) * 0.5;
-        color.g = f32(minimap.x) * 0.5;
-        color.b = f32(died) * 1000.0;
+        let alive = vec4<f32>(com::minimap_alive(minimap));
+        let died = com::minimap_died(minimap);
+        for (var team = 1u; team <= #{TEAMS}u; team += 1u) {
+            // Brightest channel of the team color at full strength
+            let team_color = com::team_color(team);
+            color = vec4(color.rgb + team_color / max(team_color.r, max(team_color.g, team_color.b)) * alive[team - 1u] * 0.5, 1.0);
+        }
+        color = vec4(color.rgb + f32(died.x + died.y + died.z + died.w) * 1000.0, 1.0);
     } else if ucoord.x < minimap_dimensions.x + 70u && ucoord.y < minimap_dimensions.y {
         color = vec4(vec3(0.0), 1.0);
     }
 
-    let minimap_sum = get_minimap_sum();
+    let minimap_sum = com::minimap_sum(minimap_sm3_texture);
 
-    
     let t1stats = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u, 0u), 0);
-    let t2stats = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u, 1u), 0);
     var t1upgrades = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u + 1u, 0u), 0);
-    var t2upgrades = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u + 1u, 1u), 0);
 
     let t1hydata = textureLoad(large_unit_tex, vec2(0u, 0u), 0);
     let t1hydra = com::unpack_large_unit(t1hydata, vec2(0u, 0u));
-    let t2hydata = textureLoad(large_unit_tex, vec2(0u, 1u), 0);
-    let t2hydra = com::unpack_large_unit(t2hydata, vec2(0u, 1u));
+
+    // Every other team is an enemy, show the healthiest hydra and the totals
+    var enemy_hydra_health = 0u;
+    var enemy_lost = 0u;
+    var enemy_alive = 0u;
+    for (var row = 1u; row < #{TEAMS}u; row += 1u) {
+        let stats = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u, row), 0);
+        let hydra = com::unpack_large_unit(textureLoad(large_unit_tex, vec2(0u, row), 0), vec2(0u, row));
+        enemy_hydra_health = max(enemy_hydra_health, hydra.health);
+        enemy_lost += stats.x;
+        enemy_alive += minimap_sum.alive[row];
+    }
 
     let left_align = 180.0;
 
-    let t1_alive = minimap_sum.x;
-    let t2_alive = minimap_sum.y;
+    let t1_alive = minimap_sum.alive.x;
     let t1_lost = t1stats.x;
-    let t2_lost = t2stats.x;
     let t1_credits = t1stats.y / 100u;
 
     var print_color = vec4(1.0);
     
-    let upgrade_movment_cost = u32(sqrt(f32(t1upgrades.x + 1u)));
-    let upgrade_attack_cost = u32(sqrt(f32(t1upgrades.y + 1u)));
-    let upgrade_spawn_cost = u32(sqrt(f32(t1upgrades.z + 1u)));
+    // Credits are shown in hundreds
+    let upgrade_movment_cost = com::upgrade_cost(t1upgrades.x, 1u, balance) / 100u;
+    let upgrade_attack_cost = com::upgrade_cost(t1upgrades.y, 1u, balance) / 100u;
+    let upgrade_spawn_cost = com::upgrade_cost(t1upgrades.z, 1u, balance) / 100u;
     print_color = select(vec4(1.0), vec4(1.0, 0.1, 0.1, 1.0), t1hydra.health < 5000u);
     color = print_value_with_color(fcoord.xy - vec2(left_align, 21.0), color, print_color, 5, t1hydra.health);
-    print_color = select(vec4(1.0), vec4(1.0, 0.1, 0.1, 1.0), t2hydra.health < 5000u);
-    color = print_value_with_color(fcoord.xy - vec2(left_align, 21.0), color, print_color, 6, t2hydra.health);
+    print_color = select(vec4(1.0), vec4(1.0, 0.1, 0.1, 1.0), enemy_hydra_health < 5000u);
+    color = print_value_with_color(fcoord.xy - vec2(left_align, 21.0), color, print_color, 6, enemy_hydra_health);
     color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 7, t1_alive);
     color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 8, t1_lost);
-    color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 9, t2_lost);
-    color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 10, t2_alive);
+    color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 9, enemy_lost);
+    color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 10, enemy_alive);
     color = print_value(fcoord.xy - vec2(left_align, 21.0), color, 11, t1_credits);
     color = print_value(fcoord.xy - vec2(left_align - 70.0, 21.0), color, 14, upgrade_movment_cost);
     color = print_value(fcoord.xy - vec2(left_align - 70.0, 21.0), color, 15, upgrade_attack_cost);
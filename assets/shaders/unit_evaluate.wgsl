@@ -11,9 +11,70 @@
 
 
 @group(0) @binding(101) var data_texture: texture_2d<u32>;
-@group(0) @binding(102) var<uniform> command: com::UnitCommand;
+@group(0) @binding(102) var<uniform> commands: com::TeamCommands;
 @group(0) @binding(103) var prev_attack: texture_2d<u32>;
 @group(0) @binding(106) var large_unit_tex: texture_2d<u32>;
+@group(0) @binding(110) var<uniform> balance: com::BalanceConfig;
+@group(0) @binding(111) var<uniform> ai: com::AiSettings;
+@group(0) @binding(112) var<uniform> waypoints: com::TeamWaypoints;
+@group(0) @binding(113) var flow_field: texture_2d<u32>;
+@group(0) @binding(114) var<uniform> flow_targets: com::FlowFieldTargets;
+@group(0) @binding(115) var terrain: texture_2d<f32>;
+
+// Closest enemy goose within range, ties go to the lowest id
+struct Target {
+    offset: vec2<i32>,
+    found: bool,
+}
+
+fn nearest_enemy(unit: com::Unit, ifrag_coord: vec2<i32>, range: f32) -> Target {
+    var out: Target;
+    var best_dist = 0;
+    var best_id = 0u;
+    let radius = #{ATTACK_RADIUS};
+    for (var x = -radius; x <= radius; x += 1) {
+        for (var y = -radius; y <= radius; y += 1) {
+            let offset = vec2(x, y);
+            let dist = x * x + y * y;
+            if f32(dist) > range * range {
+                continue;
+            }
+            let other_unit = com::unpack_unit(textureLoad(data_texture, ifrag_coord + offset, 0));
+            if other_unit.id == unit.id || other_unit.health == 0u || other_unit.team == 0u || other_unit.team == unit.team {
+                continue;
+            }
+            if !out.found || dist < best_dist || (dist == best_dist && other_unit.id < best_id) {
+                out.offset = offset;
+                out.found = true;
+                best_dist = dist;
+                best_id = other_unit.id;
+            }
+        }
+    }
+    return out;
+}
+
+// Closest living enemy hydra within range - 1 of its center, x is the slot + 1 and y the team.
+// Zero if there is none.
+fn nearest_enemy_hydra(unit: com::Unit, pos: vec2<f32>, range: f32) -> vec2<u32> {
+    var out = vec2(0u);
+    var best_dist = range - 1.0;
+    for (var team = 1u; team <= #{TEAMS}u; team += 1u) {
+        if team == unit.team {
+            continue;
+        }
+        for (var slot = 0u; slot < #{LARGE_UNITS_DATA_WIDTH}u; slot += 1u) {
+            let coord = vec2(slot, team - 1u);
+            let large_unit = com::unpack_large_unit(textureLoad(large_unit_tex, coord, 0), coord);
+            let dist = distance(large_unit.pos, pos);
+            if large_unit.health > 0u && dist < best_dist {
+                out = vec2(slot + 1u, team);
+                best_dist = dist;
+            }
+        }
+    }
+    return out;
+}
 
 struct FragmentOutput {
     @location(0) unit_data: vec4<u32>,
@@ -27,6 +88,7 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     let frag_coord = in.position.xy;
     let ufrag_coord = vec2<u32>(frag_coord);
     let ifrag_coord = vec2<i32>(ufrag_coord);
+    let frame = com::rng_frame(commands);
 
     let system_index = ufrag_coord.y;
 
@@ -35,24 +97,28 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     let data = textureLoad(data_texture, ifrag_coord, 0);
     var unit = com::unpack_unit(data);
     
-    let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team);
+    let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team, balance);
+    let type_stats = com::unit_type_stats(unit.unit_type, balance);
 
     if unit.progress >= 1.0 {
         unit.mode = com::UNIT_MODE_IDLE;
         unit.attacking_hydra = 0u;
+        unit.attacking_team = 0u;
     }
 
     
+    // Progress the attack had at the start of the frame, see DETERMINISTIC_ATTACKS below
+    let attack_start = select(-1.0, unit.progress, unit.mode == com::UNIT_MODE_ATTACK);
+
     var step_mult = 0.0;
     if unit.mode == com::UNIT_MODE_MOVEING {
-        step_mult = unit_stats.move_rate;
+        let water_depth = com::load_terrain(terrain, ifrag_coord).y;
+        step_mult = unit_stats.move_rate * type_stats.move_rate * com::terrain_move_rate(water_depth, balance);
     } else if unit.mode == com::UNIT_MODE_ATTACK || unit.mode == com::UNIT_MODE_ATTACK_HYDRA {
         step_mult = unit_stats.attack_rate;
-    } else if unit.mode == com::UNIT_MODE_ATTACK_HYDRA {
-        step_mult = unit_stats.attack_rate;
     }
 
-    unit.progress += globals.delta_time * step_mult;
+    unit.progress += com::tick_delta(commands) * step_mult;
 
     // if there is living unit in this cell check if it moved to another cell last frame
     if unit.health != 0u {
@@ -76,21 +142,27 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
     }    
 
     // --- Spawn around large ---
-    var large_rng = sampling::hash_noise(ufrag_coord, globals.frame_count + 45245u);
-    var team_rng = u32(round(sampling::hash_noise(ufrag_coord, globals.frame_count + 647132u))) + 1u;
+    var large_rng = sampling::hash_noise(ufrag_coord, frame + 45245u);
+    var team_rng = min(u32(sampling::hash_noise(ufrag_coord, frame + 647132u) * #{TEAMS}.0), #{TEAMS}u - 1u) + 1u;
     let large_unit_frag_coord = vec2(i32(large_rng * #{LARGE_UNITS_DATA_WIDTH}.0), i32(team_rng - 1u));
     let large_data = textureLoad(large_unit_tex, large_unit_frag_coord, 0);
     var large_unit = com::unpack_large_unit(large_data, vec2<u32>(large_unit_frag_coord));
 
-    let spawn_unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, team_rng);
+    let spawn_unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, team_rng, balance);
 
-    let rng = sampling::hash_noise(ufrag_coord + globals.frame_count, globals.frame_count + 34121u);
+    let rng = sampling::hash_noise(ufrag_coord + frame, frame + 34121u);
 
-    if large_unit.health > 0u && distance(large_unit.pos.xy, frag_coord.xy) < spawn_unit_stats.spawn_radius {
-        if unit.health == 0u && distance(rng, 0.5) < spawn_unit_stats.spawn_rate * globals.delta_time { 
+    // Nothing spawns inside buildings
+    let spawn_blocked = textureLoad(flow_field, ifrag_coord, 0).x == com::FLOW_BLOCKED;
+    if large_unit.health > 0u && !spawn_blocked && distance(large_unit.pos.xy, frag_coord.xy) < spawn_unit_stats.spawn_radius {
+        if unit.health == 0u && distance(rng, 0.5) < spawn_unit_stats.spawn_rate * com::tick_delta(commands) { 
+            let mix = com::spawn_mix(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, team_rng, balance);
+            let type_rng = sampling::hash_noise(ufrag_coord, frame + 81233u);
             unit = com::unpack_unit(vec4(0u));
-            unit.health = 255u;
-            unit.id = u32(sampling::hash_noise(ufrag_coord, globals.frame_count + 96421u) * f32(sampling::U32_MAX - 10u)) + 5u;
+            unit.unit_type = com::pick_unit_type(mix, type_rng);
+            let spawn_health = f32(spawn_unit_stats.spawn_health) * com::unit_type_stats(unit.unit_type, balance).health;
+            unit.health = clamp(u32(spawn_health), 1u, 255u);
+            unit.id = u32(sampling::hash_noise(ufrag_coord, frame + 96421u) * f32(sampling::U32_MAX - 10u)) + 5u;
             unit.dest = ufrag_coord;
             unit.team = team_rng;
             out.unit_data = com::pack_unit(unit);
@@ -108,38 +180,112 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
         out.attack_data = vec4(0u);
         return out;
     }
-    if unit.team == 1u {
-        if command.command > 0u && command.unit_group == 1u {
-            unit.dest = command.dest;
-            if unit.mode != com::UNIT_MODE_MOVEING {
-                unit.mode = com::UNIT_MODE_MOVE;
-                unit.progress = 0.0;
-            }
+    let command = com::team_command(commands, unit.team);
+    if command.command == com::COMMAND_SELECT {
+        unit.selected = com::in_select_region(command.select_region, vec2<f32>(ufrag_coord));
+    }
+    if command.command == com::COMMAND_ASSIGN_GROUP {
+        if unit.selected {
+            unit.group = command.control_group;
+        } else if unit.group == command.control_group {
+            unit.group = 0u;
+        }
+    }
+    if command.command == com::COMMAND_RECALL_GROUP {
+        unit.selected = unit.group == command.control_group;
+    }
+    let ordered = command.unit_group == 1u && (command.selected_only == 0u || unit.selected);
+    if ordered && (command.command == com::COMMAND_MOVE || 
+                   command.command == com::COMMAND_ATTACK_MOVE || 
+                   command.command == com::COMMAND_PATROL) {
+        unit.dest = command.dest;
+        unit.order = com::command_order(command.command);
+        if unit.mode != com::UNIT_MODE_MOVEING {
+            unit.mode = com::UNIT_MODE_MOVE;
+            unit.progress = 0.0;
+        }
+    }
+    if ordered && (command.command == com::COMMAND_STOP || command.command == com::COMMAND_HOLD) {
+        unit.dest = ufrag_coord;
+        unit.order = com::command_order(command.command);
+        if unit.mode == com::UNIT_MODE_MOVE {
+            unit.mode = com::UNIT_MODE_IDLE;
+        }
+    }
+    // Once close enough to a queued waypoint head for the next one
+    if unit.order != com::UNIT_ORDER_HOLD && 
+       distance(vec2<f32>(ufrag_coord), vec2<f32>(unit.dest)) < com::WAYPOINT_RADIUS {
+        let paths = com::team_waypoints(waypoints, unit.team);
+        let next = vec2<u32>(com::next_waypoint(paths, vec2<f32>(unit.dest)));
+        if all(next == unit.dest) && unit.order != com::UNIT_ORDER_PATROL {
+            // End of the path
+            unit.order = com::UNIT_ORDER_NONE;
         }
-    } else if unit.team == 2u {
-        var t2upgrades = textureLoad(large_unit_tex, vec2(#{LARGE_UNITS_DATA_WIDTH}u + 1u, 1u), 0);
-        if t2upgrades.y > 0u && t2upgrades.y % 10u == 0u {
-            let large_data = textureLoad(large_unit_tex, vec2(0), 0);
-            var large_unit = com::unpack_large_unit(large_data, vec2<u32>(large_unit_frag_coord));
-            unit.dest = vec2<u32>(large_unit.pos);
+        unit.dest = next;
+    }
+    if unit.team != 1u {
+        let large_data = textureLoad(large_unit_tex, vec2(0), 0);
+        var player_hydra = com::unpack_large_unit(large_data, vec2(0u));
+        // Geese that get close enough go for the hydra
+        if player_hydra.health > 0u && distance(player_hydra.pos, frag_coord) < ai.hydra_aggro_radius {
+            unit.dest = vec2<u32>(player_hydra.pos);
         }
     }
 
         
 
-    
+#ifdef DETERMINISTIC_ATTACKS
+    // Geese go for the nearest enemy goose in range and hydras once there are none. The target is
+    // picked again every frame, each point of damage lands as the attack passes it so the damage
+    // dealt follows delta_time.
+    var clear_attack_data = true;
+    if unit.order != com::UNIT_ORDER_MOVE && (unit.mode == com::UNIT_MODE_IDLE || unit.mode == com::UNIT_MODE_ATTACK) {
+        let enemy = nearest_enemy(unit, ifrag_coord, type_stats.range);
+        if enemy.found {
+            let damage = f32(com::attack_damage(unit_stats, unit.unit_type, balance));
+            var hit = 0u;
+            if attack_start >= 0.0 {
+                // As stored, so the next frame starts where this one ended
+                let end = unpack2x16float(pack2x16float(vec2(unit.progress, 0.0))).x;
+                hit = u32(floor(damage * min(end, 1.0))) - u32(floor(damage * attack_start));
+                // Chain straight into the next attack
+                if end >= 1.0 {
+                    unit.progress = fract(end);
+                    hit += u32(floor(damage * unit.progress));
+                }
+            } else {
+                unit.mode = com::UNIT_MODE_ATTACK;
+                unit.progress = 0.0;
+            }
+            out.attack_data = vec4(vec2<u32>(enemy.offset + #{ATTACK_RADIUS}), min(hit, 255u), 0u);
+            clear_attack_data = false;
+        } else if unit.mode == com::UNIT_MODE_IDLE {
+            let hydra = nearest_enemy_hydra(unit, vec2<f32>(ufrag_coord), type_stats.range);
+            if hydra.x > 0u {
+                unit.attacking_hydra = hydra.x;
+                unit.attacking_team = hydra.y;
+                unit.mode = com::UNIT_MODE_ATTACK_HYDRA;
+                unit.progress = 0.0;
+            }
+        } else {
+            unit.mode = com::UNIT_MODE_IDLE;
+        }
+    }
+#else
     if unit.health > 0u && 
        // unit.mode == com::UNIT_MODE_IDLE && not working
+       unit.order != com::UNIT_ORDER_MOVE &&
        large_unit.health > 0u && 
        large_unit.team != unit.team &&
-       distance(large_unit.pos, vec2<f32>(ufrag_coord)) < #{ATTACK_RADIUS}.0 - 1.0 {
+       distance(large_unit.pos, vec2<f32>(ufrag_coord)) < type_stats.range - 1.0 {
         unit.attacking_hydra = u32(large_unit_frag_coord.x) + 1u;
+        unit.attacking_team = large_unit.team;
         unit.mode = com::UNIT_MODE_ATTACK_HYDRA;
         unit.progress = 0.0;
     }
     
     var clear_attack_data = true;
-    if unit.mode == com::UNIT_MODE_IDLE {
+    if unit.mode == com::UNIT_MODE_IDLE && unit.order != com::UNIT_ORDER_MOVE {
         // First check if the unit we were shooting at is still there and use that one first otherwise find a new one
         let prev_attack_data = textureLoad(prev_attack, ifrag_coord, 0);
         let prev_attack_vector = vec2<i32>(prev_attack_data.xy) - #{ATTACK_RADIUS};
@@ -153,15 +299,15 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
             clear_attack_data = false;
         } else {
             let noise = vec2(
-                sampling::hash_noise(ufrag_coord + globals.frame_count, globals.frame_count + 4563u),
-                sampling::hash_noise(ufrag_coord + globals.frame_count, globals.frame_count + 2564u),
+                sampling::hash_noise(ufrag_coord + frame, frame + 4563u),
+                sampling::hash_noise(ufrag_coord + frame, frame + 2564u),
             ) * 2.0 - 1.0;
-            let attack_offset = clamp(vec2<i32>(noise * #{ATTACK_RADIUS}.0), vec2(-#{ATTACK_RADIUS}), vec2(#{ATTACK_RADIUS}));
+            let attack_offset = clamp(vec2<i32>(noise * type_stats.range), vec2(-#{ATTACK_RADIUS}), vec2(#{ATTACK_RADIUS}));
             let attack_coord = attack_offset + ifrag_coord;
 
             other_data = textureLoad(data_texture, attack_coord, 0);
             other_unit = com::unpack_unit(other_data);
-            let attack_damage = 1u + u32(unit_stats.attack_mult);
+            let attack_damage = com::attack_damage(unit_stats, unit.unit_type, balance);
 
             if other_unit.id != unit.id && other_unit.health > 0u && other_unit.team > 0u && unit.team != other_unit.team {
                 out.attack_data = vec4(vec2<u32>(attack_offset + #{ATTACK_RADIUS}), attack_damage, 0u);
@@ -171,22 +317,49 @@ fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
             }
         }
     }
+#endif
     if unit.mode != com::UNIT_MODE_ATTACK && clear_attack_data {
         out.attack_data = vec4(0u);
     }
 
 
-    if unit.mode == com::UNIT_MODE_IDLE || (unit.mode == com::UNIT_MODE_MOVE && !all(ufrag_coord == unit.dest)) {
+    if unit.order != com::UNIT_ORDER_HOLD && 
+       (unit.mode == com::UNIT_MODE_IDLE || (unit.mode == com::UNIT_MODE_MOVE && !all(ufrag_coord == unit.dest))) {
         let f_to_dest = vec2<f32>(unit.dest) - vec2<f32>(ufrag_coord);
 
         var dir_noise = vec2(0.0);
         dir_noise = vec2(
-            sampling::hash_noise(ufrag_coord, globals.frame_count + 74856u),
-            sampling::hash_noise(ufrag_coord, globals.frame_count + 36422u),
+            sampling::hash_noise(ufrag_coord, frame + 74856u),
+            sampling::hash_noise(ufrag_coord, frame + 36422u),
         ) * 2.0 - 1.0;
         dir_noise *= length(f_to_dest);
 
-        let step_dir = com::sign2i(vec2<i32>(f_to_dest + dir_noise));
+        var step_dir = com::sign2i(vec2<i32>(f_to_dest + dir_noise));
+        // Once the flow field has spread this far step to the neighbour closest to dest along it
+        if com::has_flow_field(flow_targets, unit.team, unit.dest) {
+            let dims = vec2<i32>(textureDimensions(flow_field));
+            let channel = clamp(unit.team, 1u, com::MAX_TEAMS) - 1u;
+            var best = textureLoad(flow_field, ifrag_coord, 0)[channel];
+            // Start somewhere random so ties don't all go the same way
+            let start = u32(sampling::hash_noise(ufrag_coord, frame + 51277u) * 8.0);
+            for (var i = 0u; i < 8u; i += 1u) {
+                let offset = com::neighbour_offset((start + i) % 8u);
+                let coord = ifrag_coord + offset;
+                if any(coord < vec2(0)) || any(coord >= dims) {
+                    continue;
+                }
+                let dist = textureLoad(flow_field, coord, 0)[channel];
+                if best < com::FLOW_UNREACHED && dist < best {
+                    best = dist;
+                    step_dir = offset;
+                }
+            }
+        }
+        // Never walk into a building, but let geese stuck in one out
+        if textureLoad(flow_field, ifrag_coord + step_dir, 0).x == com::FLOW_BLOCKED &&
+           textureLoad(flow_field, ifrag_coord, 0).x != com::FLOW_BLOCKED {
+            step_dir = vec2(0);
+        }
         if !all(step_dir == vec2(0)) {
             unit.step_dir = step_dir;
             unit.mode = com::UNIT_MODE_MOVE;
@@ -20,13 +20,15 @@
 #import bevy_pbr::utils::PI
 
 @group(0) @binding(101) var data_texture: texture_2d<u32>;
-@group(0) @binding(102) var<uniform> commands: com::UnitCommand;
+@group(0) @binding(102) var<uniform> commands: com::TeamCommands;
 @group(0) @binding(103) var attack_texture: texture_2d<u32>;
 // Cursed, but work on both webgl2 and native
 // Discussion: https://discord.com/channels/691052431525675048/743663924229963868/1182466862190186627
 @group(0) @binding(104) var unit_texture: texture_2d_array<f32>;
 @group(0) @binding(105) var nearest_sampler: sampler;
 @group(0) @binding(106) var large_unit_tex: texture_2d<u32>;
+@group(0) @binding(110) var<uniform> balance: com::BalanceConfig;
+@group(0) @binding(115) var terrain: texture_2d<f32>;
 
 struct VertexOutput {
     // this is `clip position` when the struct is used as a vertex stage output 
@@ -80,11 +82,21 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     ));
 
 
-    var center = vec3(f32(data_x), 0.5, f32(data_y));
+    let surface = com::load_terrain(terrain, idata_xy).x;
+    var center = vec3(f32(data_x), surface + 0.5, f32(data_y));
 
     if unit.mode == com::UNIT_MODE_MOVEING {
-        let prev = vec3(f32(data_x - unit.step_dir.x), 0.5, f32(data_y - unit.step_dir.y));
-        center = mix(prev, center, saturate(unit.progress));
+        let prev_xy = idata_xy - unit.step_dir;
+        let prev_surface = com::load_terrain(terrain, prev_xy).x;
+        let prev = vec3(f32(prev_xy.x), prev_surface + 0.5, f32(prev_xy.y));
+        // Carry on for the part of a tick since the last one, see unit_evaluate.wgsl
+        let command = com::team_command(commands, unit.team);
+        let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team, balance);
+        let type_stats = com::unit_type_stats(unit.unit_type, balance);
+        let water_depth = com::load_terrain(terrain, idata_xy).y;
+        let step_mult = unit_stats.move_rate * type_stats.move_rate * com::terrain_move_rate(water_depth, balance);
+        let progress = unit.progress + command.tick_alpha * com::tick_delta(commands) * step_mult;
+        center = mix(prev, center, saturate(progress));
     }
 
     //let center = vec3(2.0, 2.0, 0.0);
@@ -131,8 +143,7 @@ fn vertex(vertex: Vertex) -> VertexOutput {
         let attack_vector = vec2<i32>(attack_data.xy) - #{ATTACK_RADIUS};
         look_dir = com::sign2i(vec2<i32>(attack_vector));
     } else if unit.mode == com::UNIT_MODE_ATTACK_HYDRA {
-        let other_team_idx = select(0u, 1u, unit.team == 1u);
-        let coord = vec2(unit.attacking_hydra - 1u, other_team_idx);
+        let coord = vec2(unit.attacking_hydra - 1u, unit.attacking_team - 1u);
         let large_data = textureLoad(large_unit_tex, coord, 0);
         var large_unit = com::unpack_large_unit(large_data, coord);
         look_dir = com::sign2i(vec2<i32>(large_unit.pos) - idata_xy);
@@ -154,12 +165,14 @@ fn vertex(vertex: Vertex) -> VertexOutput {
         if unit.mode == com::UNIT_MODE_ATTACK {
             index += 8;
         }
-        let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team);
+        let unit_stats = com::get_unit_stats(large_unit_tex, #{LARGE_UNITS_DATA_WIDTH}u, unit.team, balance);
         if unit_stats.move_rate > 11.0 && (unit.mode == com::UNIT_MODE_ATTACK || unit.mode == com::UNIT_MODE_MOVEING) {
             index += 16;
         }
     }
-    out.dir_index = index;
+    // Each type's frames start at its layer, textures without them fall back to the first type's
+    let layer = index + i32(com::unit_type_stats(unit.unit_type, balance).texture_layer);
+    out.dir_index = select(index, layer, layer < i32(textureNumLayers(unit_texture)));
 
     return out;
 }
@@ -186,8 +199,7 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     //pbr.material.base_color = vec4(vec3(0.1), 1.0);
     //pbr.material.reflectance = 0.5;
     
-    var color = select(vec3(0.4, 0.02, 0.02), vec3(0.02, 0.15, 0.02), unit.team == 1u);
-    color = select(color, vec3(0.0, 0.0, 0.0), unit.team == 0u);
+    var color = com::team_color(unit.team);
     //pbr.material.base_color = vec4(color, 1.0);
 
 
@@ -201,9 +213,15 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     //let data = bitcast<vec2<u32>>(textureSampleLevel(unit_texture, nearest_sampler, uv, u32(index), f32(mip)));
     let data = bitcast<vec2<u32>>(textureSample(unit_texture, nearest_sampler, uv, u32(in.dir_index)));
     pbr = com::decompress_gbuffer(frag_coord, data.xy);
+    let tint = com::unit_type_stats(unit.unit_type, balance).tint;
+    pbr.material.base_color = vec4(pbr.material.base_color.rgb * tint, pbr.material.base_color.a);
     
     //pbr.material.base_color = select(pbr.material.base_color, pbr.material.base_color * vec4(1.0, 0.2, 0.2, 1.0), unit.team == 2u);
-    pbr.material.emissive = select(vec4(0.0, 0.005, 0.0, 0.0), vec4(0.08, 0.0, 0.0, 0.0), unit.team == 2u);
+    // The player's geese glow faintly, enemies more
+    pbr.material.emissive = vec4(com::team_color(unit.team) * select(0.2, 0.033, unit.team == 1u), 0.0);
+    if unit.selected {
+        pbr.material.emissive += vec4(0.08, 0.08, 0.06, 0.0);
+    }
 
     out.deferred = deferred_gbuffer_from_pbr_input(pbr);
     out.deferred_lighting_pass_id = 1u;
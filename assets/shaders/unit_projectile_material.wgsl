@@ -16,9 +16,10 @@
 #import bevy_pbr::utils::PI
 
 @group(0) @binding(101) var data_texture: texture_2d<u32>;
-@group(0) @binding(102) var<uniform> commands: com::UnitCommand;
+@group(0) @binding(102) var<uniform> commands: com::TeamCommands;
 @group(0) @binding(103) var attack_texture: texture_2d<u32>;
 @group(0) @binding(106) var large_unit_tex: texture_2d<u32>;
+@group(0) @binding(115) var terrain: texture_2d<f32>;
 
 struct VertexOutput {
     // this is `clip position` when the struct is used as a vertex stage output 
@@ -63,7 +64,8 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     var projectile_y = 0.7;
 
 
-    var center = vec3(f32(iunit_coord.x), projectile_y, f32(iunit_coord.y));
+    let surface = com::load_terrain(terrain, iunit_coord).x;
+    var center = vec3(f32(iunit_coord.x), projectile_y + surface, f32(iunit_coord.y));
 
     let attack_data = textureLoad(attack_texture, iunit_coord, 0);
     let attack_vector = vec2<i32>(attack_data.xy) - #{ATTACK_RADIUS};
@@ -71,16 +73,17 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     if unit.health != 0u && unit.mode == com::UNIT_MODE_ATTACK && !all(attack_vector == vec2(0))  {
 
         let iprojectile_dest = iunit_coord + attack_vector;
-        let fprojectile_dest = vec3(f32(iprojectile_dest.x), projectile_y, f32(iprojectile_dest.y));
+        let dest_surface = com::load_terrain(terrain, iprojectile_dest).x;
+        let fprojectile_dest = vec3(f32(iprojectile_dest.x), projectile_y + dest_surface, f32(iprojectile_dest.y));
 
         center = mix(center, fprojectile_dest, saturate(unit.progress));
     } else if unit.mode == com::UNIT_MODE_ATTACK_HYDRA {
-        let other_team_idx = select(0u, 1u, unit.team == 1u);
-        let coord = vec2(unit.attacking_hydra - 1u, other_team_idx);
+        let coord = vec2(unit.attacking_hydra - 1u, unit.attacking_team - 1u);
         let large_data = textureLoad(large_unit_tex, coord, 0);
         var large_unit = com::unpack_large_unit(large_data, coord);
         
-        let fprojectile_dest = vec3(large_unit.pos.x, 2.0, large_unit.pos.y);
+        let hydra_surface = com::load_terrain(terrain, vec2<i32>(large_unit.pos)).x;
+        let fprojectile_dest = vec3(large_unit.pos.x, 2.0 + hydra_surface, large_unit.pos.y);
         center = mix(center, fprojectile_dest, saturate(unit.progress));
     } else {
         out.position = vec4(0.0);
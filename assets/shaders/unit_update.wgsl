@@ -9,7 +9,7 @@
 
 
 @group(0) @binding(101) var data_texture: texture_2d<u32>;
-@group(0) @binding(102) var<uniform> command: com::UnitCommand;
+@group(0) @binding(102) var<uniform> commands: com::TeamCommands;
 @group(0) @binding(103) var attack_texture: texture_2d<u32>;
 
 
@@ -18,14 +18,15 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<u32> {
     let frag_coord = in.position.xy;
     let ufrag_coord = vec2<u32>(frag_coord);
     let ifrag_coord = vec2<i32>(ufrag_coord);
+    let frame = com::rng_frame(commands);
 
     let system_index = ufrag_coord.y;
     
     let data = textureLoad(data_texture, ifrag_coord, 0);
     var unit = com::unpack_unit(data);
 
-    let shuffle_x = max(i32(round(sampling::hash_noise(ufrag_coord, globals.frame_count + 83746u) * 3.0)), 0);
-    let shuffle_y = max(i32(round(sampling::hash_noise(ufrag_coord, globals.frame_count + 12339u) * 3.0)), 0);
+    let shuffle_x = max(i32(round(sampling::hash_noise(ufrag_coord, frame + 83746u) * 3.0)), 0);
+    let shuffle_y = max(i32(round(sampling::hash_noise(ufrag_coord, frame + 12339u) * 3.0)), 0);
 
     // if there is not living unit in this cell, we can allow another unit to take this spot
     if unit.health == 0u {
@@ -0,0 +1,383 @@
+use std::collections::VecDeque;
+
+use bevy::{math::*, prelude::*};
+
+use crate::{
+    balance::BalanceConfig,
+    difficulty::{AiSettings, Difficulty, UPGRADE_ATTACK, UPGRADE_MOVEMENT, UPGRADE_SPAWN},
+    game_state::GameState,
+    readback::SimulationSnapshot,
+    units::{
+        SimulationConfig, SimulationState, TeamCommands, UnitCommand, COMMAND_ATTACK_MOVE,
+        HATCH_REQUEST, PLAYER_TEAM,
+    },
+};
+
+/// What an `AiController` gets to see besides the snapshot
+pub struct AiContext<'a> {
+    /// Team the controller plays
+    pub team: u32,
+    pub settings: &'a AiSettings,
+    pub balance: &'a BalanceConfig,
+    pub config: &'a SimulationConfig,
+    /// Seconds since the last update
+    pub delta_time: f32,
+}
+
+/// Plays a team from readback snapshots, the same way the player does through `TeamCommands`.
+/// Swap an opponent with `AiOpponent::new`.
+pub trait AiController: Send + Sync {
+    /// Called for each new snapshot of the current match. Returned orders replace any that
+    /// haven't been sent yet, one is sent per frame.
+    fn update(&mut self, snapshot: &SimulationSnapshot, context: &AiContext) -> Vec<UnitCommand>;
+
+    /// A new match started
+    fn reset(&mut self) {}
+}
+
+pub struct AiOpponent {
+    team: u32,
+    controller: Box<dyn AiController>,
+    /// `SimulationState::match_index` the controller was last updated for
+    match_index: u32,
+    /// `Time::elapsed_seconds` at the last update
+    last_update: f32,
+    orders: VecDeque<UnitCommand>,
+}
+
+impl AiOpponent {
+    pub fn new(team: u32, controller: impl AiController + 'static) -> Self {
+        Self {
+            team,
+            controller: Box::new(controller),
+            match_index: u32::MAX,
+            last_update: 0.0,
+            orders: VecDeque::new(),
+        }
+    }
+}
+
+/// One opponent for every team but the player's. `AiSettings` apply to all of them in the shaders.
+#[derive(Resource)]
+pub struct AiOpponents(pub Vec<AiOpponent>);
+
+impl FromWorld for AiOpponents {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.resource::<SimulationConfig>();
+        Self(
+            (1..=config.teams)
+                .filter(|team| *team != PLAYER_TEAM)
+                .map(|team| AiOpponent::new(team, StrategicAi::default()))
+                .collect(),
+        )
+    }
+}
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AiOpponents>()
+            .add_systems(Update, run_ai.run_if(in_state(GameState::Playing)));
+    }
+}
+
+fn run_ai(
+    snapshot: Res<SimulationSnapshot>,
+    simulation: Res<SimulationState>,
+    difficulty: Res<Difficulty>,
+    balance: Res<BalanceConfig>,
+    config: Res<SimulationConfig>,
+    time: Res<Time>,
+    mut opponents: ResMut<AiOpponents>,
+    mut team_commands: ResMut<TeamCommands>,
+) {
+    let settings = difficulty.ai_settings();
+    for opponent in &mut opponents.0 {
+        if opponent.match_index != simulation.match_index {
+            opponent.match_index = simulation.match_index;
+            opponent.last_update = time.elapsed_seconds();
+            opponent.orders.clear();
+            opponent.controller.reset();
+        }
+
+        if snapshot.is_changed()
+            && snapshot.received
+            && snapshot.match_index == simulation.match_index
+        {
+            let context = AiContext {
+                team: opponent.team,
+                settings: &settings,
+                balance: &balance,
+                config: &config,
+                delta_time: time.elapsed_seconds() - opponent.last_update,
+            };
+            opponent.last_update = time.elapsed_seconds();
+            let orders = opponent.controller.update(&snapshot, &context);
+            if !orders.is_empty() {
+                opponent.orders = orders.into();
+            }
+        }
+
+        if let Some(order) = opponent.orders.pop_front() {
+            *team_commands.team_mut(opponent.team) = order;
+        }
+    }
+}
+
+// ---------------------------------------
+// Default AI
+// ---------------------------------------
+
+/// Seconds between orders when nothing changes
+const ORDER_INTERVAL: f32 = 1.5;
+/// Seconds between upgrade requests, readbacks lag a few frames behind
+const UPGRADE_INTERVAL: f32 = 0.5;
+/// Enemy geese within this many units of the hydra count as a threat
+const THREAT_RADIUS: f32 = 48.0;
+/// Retreat when this many enemy geese are near the hydra and they outnumber the defenders
+const SIEGE_MIN: u32 = 40;
+/// Retreat when the army is smaller than this fraction of the enemy's
+const RETREAT_RATIO: f32 = 0.6;
+/// Attack without waiting for an upgrade when the army is this much bigger than the enemy's
+const ADVANTAGE_RATIO: f32 = 1.4;
+/// Don't launch a wave with fewer geese than this
+const MIN_WAVE: u32 = 150;
+const FLANK_TIME: f32 = 8.0;
+const ASSAULT_TIME: f32 = 20.0;
+const MIN_RETREAT_TIME: f32 = 6.0;
+/// Stop hatching hydras at this many
+const MAX_HYDRAS: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    /// Gather in front of the hydra and upgrade
+    Build,
+    /// Swing around the side before attacking
+    Flank { waypoint: Vec2 },
+    /// Go straight for the enemy hydra
+    Assault,
+    /// Pull back to defend the hydra and move it away from the threat
+    Retreat,
+}
+
+/// Builds up behind a staging point, attacks the nearest enemy hydra in waves around alternating
+/// flanks and falls back when the hydra is under siege or the army is outnumbered.
+pub struct StrategicAi {
+    phase: Phase,
+    /// Seconds in the current phase
+    phase_time: f32,
+    /// Seconds until the next move order
+    order_cooldown: f32,
+    /// Seconds until the next upgrade request
+    upgrade_cooldown: f32,
+    /// Which side the next flank goes around, 1 or -1
+    flank_side: f32,
+    /// Attack upgrades when the last wave was launched
+    last_wave_upgrade: u32,
+}
+
+impl Default for StrategicAi {
+    fn default() -> Self {
+        Self {
+            phase: Phase::Build,
+            phase_time: 0.0,
+            order_cooldown: 0.0,
+            upgrade_cooldown: 0.0,
+            flank_side: 1.0,
+            last_wave_upgrade: 0,
+        }
+    }
+}
+
+impl AiController for StrategicAi {
+    fn update(&mut self, snapshot: &SimulationSnapshot, context: &AiContext) -> Vec<UnitCommand> {
+        let mine = (context.team - 1) as usize;
+        let me = &snapshot.teams[mine];
+        if me.hydra.health == 0 {
+            return Vec::new();
+        }
+        // Go after the closest hydra still standing
+        let Some(enemy) = (1..=context.config.teams)
+            .filter(|team| *team != context.team)
+            .map(|team| snapshot.team(team))
+            .filter(|enemy| enemy.hydra.health > 0)
+            .min_by(|a, b| {
+                let a = a.hydra.pos.distance(me.hydra.pos);
+                let b = b.hydra.pos.distance(me.hydra.pos);
+                a.total_cmp(&b)
+            })
+        else {
+            // Not spawned yet, or the match is over
+            return Vec::new();
+        };
+
+        self.phase_time += context.delta_time;
+        self.order_cooldown -= context.delta_time;
+        self.upgrade_cooldown -= context.delta_time;
+
+        let home = me.hydra.pos;
+        let target = enemy.hydra.pos;
+        let near_home = snapshot.density.sum_around(home, THREAT_RADIUS);
+        let enemies_near_home = near_home.to_array().iter().sum::<u32>() - near_home[mine];
+        let under_siege = enemies_near_home > SIEGE_MIN && enemies_near_home > near_home[mine];
+        let outnumbered = (me.alive as f32) < enemy.alive as f32 * RETREAT_RATIO;
+
+        let next_phase = match self.phase {
+            Phase::Retreat if self.phase_time < MIN_RETREAT_TIME => None,
+            Phase::Retreat if !under_siege && !outnumbered => Some(Phase::Build),
+            Phase::Retreat => None,
+            _ if under_siege || outnumbered => Some(Phase::Retreat),
+            Phase::Build if self.wave_ready(me.upgrades.y, me.alive, enemy.alive, context) => {
+                self.last_wave_upgrade = me.upgrades.y;
+                self.flank_side = -self.flank_side;
+                Some(Phase::Flank {
+                    waypoint: self.flank_waypoint(home, target, context.config),
+                })
+            }
+            Phase::Flank { waypoint }
+                if self.phase_time > FLANK_TIME
+                    || snapshot.density.sum_around(waypoint, THREAT_RADIUS)[mine]
+                        > me.alive / 2 =>
+            {
+                Some(Phase::Assault)
+            }
+            Phase::Assault if self.phase_time > ASSAULT_TIME => Some(Phase::Build),
+            _ => None,
+        };
+        if let Some(phase) = next_phase {
+            self.phase = phase;
+            self.phase_time = 0.0;
+            self.order_cooldown = 0.0;
+        }
+
+        let mut orders = Vec::new();
+        if self.order_cooldown <= 0.0 {
+            self.order_cooldown = ORDER_INTERVAL;
+            let geese_dest = match self.phase {
+                Phase::Build => home.lerp(target, 0.25),
+                Phase::Flank { waypoint } => waypoint,
+                Phase::Assault => target,
+                Phase::Retreat => home,
+            };
+            orders.push(move_order(1, geese_dest, context.config));
+            if self.phase == Phase::Retreat {
+                let threat = threat_center(snapshot, home, mine).unwrap_or(target);
+                let away = (home - threat).normalize_or_zero();
+                orders.push(move_order(0, home + away * THREAT_RADIUS, context.config));
+            }
+        }
+
+        if self.upgrade_cooldown <= 0.0 {
+            self.upgrade_cooldown = UPGRADE_INTERVAL;
+            let upgrade = self.pick_upgrade(me.upgrades, context);
+            let level = me.upgrades[upgrade as usize];
+            let upgrade_cost = context.balance.upgrade_cost(level, context.team);
+            let mut request = 0;
+            let mut credits = me.credits;
+            if credits > upgrade_cost {
+                request |= 1 << upgrade;
+                credits -= upgrade_cost;
+            }
+            // Extra hydras only with what the upgrades leave over
+            if me.hydras < MAX_HYDRAS && credits > context.balance.hatch_cost {
+                request |= HATCH_REQUEST;
+            }
+            if request != 0 {
+                if orders.is_empty() {
+                    orders.push(UnitCommand::default());
+                }
+                orders[0].upgrade_request |= request;
+            }
+        }
+
+        orders
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl StrategicAi {
+    fn wave_ready(
+        &self,
+        attack_upgrades: u32,
+        alive: u32,
+        enemy_alive: u32,
+        context: &AiContext,
+    ) -> bool {
+        if alive < MIN_WAVE {
+            return false;
+        }
+        let interval = context.settings.attack_interval;
+        let upgraded = interval > 0 && attack_upgrades >= self.last_wave_upgrade + interval;
+        upgraded || alive as f32 > enemy_alive as f32 * ADVANTAGE_RATIO
+    }
+
+    /// Off to `flank_side` of the line between the hydras, most of the way to the enemy
+    fn flank_waypoint(&self, home: Vec2, target: Vec2, config: &SimulationConfig) -> Vec2 {
+        let to_target = target - home;
+        let side = to_target.perp().normalize_or_zero() * self.flank_side;
+        clamp_to_grid(
+            home.lerp(target, 0.7) + side * to_target.length() * 0.5,
+            config,
+        )
+    }
+
+    /// Spawn when defending, otherwise whatever is furthest behind, weighted by the difficulty's
+    /// upgrade order.
+    fn pick_upgrade(&self, upgrades: UVec3, context: &AiContext) -> u32 {
+        if self.phase == Phase::Retreat {
+            return UPGRADE_SPAWN;
+        }
+        let order = context.settings.upgrade_order.xyz().to_array();
+        let weight = |upgrade: u32| {
+            let rank = order.iter().position(|u| *u == upgrade).unwrap_or(2);
+            (3 - rank) as f32
+        };
+        [UPGRADE_MOVEMENT, UPGRADE_ATTACK, UPGRADE_SPAWN]
+            .into_iter()
+            .min_by(|a, b| {
+                let a = (upgrades[*a as usize] + 1) as f32 / weight(*a);
+                let b = (upgrades[*b as usize] + 1) as f32 / weight(*b);
+                a.total_cmp(&b)
+            })
+            .unwrap_or(order[0])
+    }
+}
+
+/// Weighted center of everyone but `mine`'s geese within `THREAT_RADIUS` of `pos`
+fn threat_center(snapshot: &SimulationSnapshot, pos: Vec2, mine: usize) -> Option<Vec2> {
+    let density = &snapshot.density;
+    let (sum, count) = density
+        .iter()
+        .map(|(cell, data)| {
+            (
+                density.cell_center(cell),
+                data.to_array().iter().sum::<u32>() - data[mine],
+            )
+        })
+        .filter(|(center, count)| *count > 0 && center.distance(pos) <= THREAT_RADIUS)
+        .fold((Vec2::ZERO, 0), |(sum, total), (center, count)| {
+            (sum + center * count as f32, total + count)
+        });
+    (count > 0).then(|| sum / count as f32)
+}
+
+/// `unit_group` 0 is the hydra, 1 is the geese. The geese fight whatever they meet on the way.
+fn move_order(unit_group: u32, dest: Vec2, config: &SimulationConfig) -> UnitCommand {
+    UnitCommand {
+        dest: clamp_to_grid(dest, config).as_uvec2(),
+        command: COMMAND_ATTACK_MOVE,
+        unit_group,
+        ..default()
+    }
+}
+
+fn clamp_to_grid(pos: Vec2, config: &SimulationConfig) -> Vec2 {
+    pos.clamp(
+        Vec2::ZERO,
+        vec2(config.units_width as f32, config.units_height as f32) - 1.0,
+    )
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    reflect::TypePath,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::ShaderType,
+    },
+    utils::BoxedFuture,
+};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    nav_grid::SHALLOW_WATER_DEPTH,
+    units::{ATTACK_RADIUS, MAX_TEAMS, UNIT_TYPES},
+};
+
+pub const BALANCE_CONFIG_PATH: &str = "units.balance.ron";
+/// Layers of SmallGoose.ktx2 per unit type, 8 directions moving and attacking, then both again fast
+pub const GOOSE_FRAMES: u32 = 32;
+
+/// Unit stats read by `get_unit_stats` and the upgrade costs in large_unit_update.wgsl.
+/// Loaded from `assets/units.balance.ron`, edits are picked up while running with `file_watcher`.
+/// Must match `com::BalanceConfig`.
+#[derive(
+    Asset, TypePath, Resource, ExtractResource, ShaderType, Deserialize, Clone, Copy, Debug,
+)]
+#[serde(default)]
+pub struct BalanceConfig {
+    pub speed_move: f32,
+    pub speed_attack: f32,
+    pub large_speed_move: f32,
+    pub large_speed_attack: f32,
+    pub spawn_radius: f32,
+    pub spawn_rate: f32,
+    pub hydra_init_health: u32,
+    /// Upgrading from level n costs `upgrade_cost * floor(sqrt(n + 1))` credits
+    pub upgrade_cost: u32,
+    /// Move rate multipliers for geese on land, wading through shallow water and swimming
+    pub land_move_rate: f32,
+    pub shallow_water_move_rate: f32,
+    pub deep_water_move_rate: f32,
+    /// Credits another hydra costs to hatch
+    pub hatch_cost: u32,
+    /// Indexed by `UNIT_TYPE_*`
+    pub unit_types: [UnitTypeStats; UNIT_TYPES as usize],
+    /// Handicaps, indexed by team - 1. Teams missing from the asset get the defaults.
+    #[serde(deserialize_with = "deserialize_teams")]
+    pub teams: [TeamModifier; MAX_TEAMS as usize],
+}
+
+/// What sets each kind of goose apart, see `UNIT_TYPE_*`. Must match `com::UnitTypeStats`.
+#[derive(ShaderType, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct UnitTypeStats {
+    /// Cells away geese attack from, at most `ATTACK_RADIUS`
+    pub range: f32,
+    /// Damage of each hit before upgrades
+    pub damage: f32,
+    /// Multipliers of the team's move rate and spawn health
+    pub move_rate: f32,
+    pub health: f32,
+    /// First layer of SmallGoose.ktx2 this type is drawn with, each type has `GOOSE_FRAMES`.
+    /// Types past the end of the texture are drawn with the first type's frames.
+    pub texture_layer: u32,
+    /// Share of the geese hydras spawn until the team picks a mix of its own
+    pub spawn_weight: u32,
+    #[serde(skip)]
+    pub spare1_: u32,
+    #[serde(skip)]
+    pub spare2_: u32,
+    /// Multiplies the base color so types can be told apart while they share frames
+    pub tint: Vec3,
+}
+
+impl Default for UnitTypeStats {
+    fn default() -> Self {
+        Self {
+            range: ATTACK_RADIUS as f32,
+            damage: 1.0,
+            move_rate: 1.0,
+            health: 1.0,
+            texture_layer: 0,
+            spawn_weight: 1,
+            spare1_: 0,
+            spare2_: 0,
+            tint: Vec3::ONE,
+        }
+    }
+}
+
+/// Per team multipliers applied on top of the base stats in `get_unit_stats`.
+/// Must match `com::TeamModifier`.
+#[derive(ShaderType, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct TeamModifier {
+    /// Health units and the hydra spawn with, geese are capped at 255
+    pub health: f32,
+    /// How fast geese and hydras attack, the damage of each hit is the same
+    pub attack_rate: f32,
+    pub spawn_rate: f32,
+    pub move_rate: f32,
+    pub upgrade_cost: f32,
+    // Array elements in uniforms need a 16 byte stride
+    #[serde(skip)]
+    pub spare1_: f32,
+    #[serde(skip)]
+    pub spare2_: f32,
+    #[serde(skip)]
+    pub spare3_: f32,
+}
+
+impl Default for TeamModifier {
+    fn default() -> Self {
+        Self {
+            health: 1.0,
+            attack_rate: 1.0,
+            spawn_rate: 1.0,
+            move_rate: 1.0,
+            upgrade_cost: 1.0,
+            spare1_: 0.0,
+            spare2_: 0.0,
+            spare3_: 0.0,
+        }
+    }
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        Self {
+            speed_move: 5.0,
+            speed_attack: 1.0,
+            large_speed_move: 5.0,
+            large_speed_attack: 1.0,
+            spawn_radius: 8.0,
+            spawn_rate: 0.6,
+            hydra_init_health: 25000,
+            upgrade_cost: 100,
+            land_move_rate: 1.0,
+            shallow_water_move_rate: 0.6,
+            deep_water_move_rate: 1.0,
+            hatch_cost: 1000,
+            unit_types: [
+                UnitTypeStats {
+                    health: 0.7,
+                    spawn_weight: 2,
+                    ..default()
+                },
+                UnitTypeStats {
+                    range: 2.0,
+                    damage: 2.0,
+                    move_rate: 1.1,
+                    tint: Vec3::new(1.0, 0.7, 0.55),
+                    spawn_weight: 2,
+                    ..default()
+                },
+                UnitTypeStats {
+                    range: 3.0,
+                    damage: 0.5,
+                    move_rate: 1.3,
+                    health: 0.8,
+                    tint: Vec3::new(0.6, 0.8, 1.0),
+                    ..default()
+                },
+            ],
+            teams: [
+                // The player gets a slight edge
+                TeamModifier {
+                    attack_rate: 1.15,
+                    spawn_rate: 1.15,
+                    ..default()
+                },
+                TeamModifier::default(),
+                TeamModifier::default(),
+                TeamModifier::default(),
+            ],
+        }
+    }
+}
+
+fn deserialize_teams<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<[TeamModifier; MAX_TEAMS as usize], D::Error> {
+    let modifiers = Vec::<TeamModifier>::deserialize(deserializer)?;
+    if modifiers.len() > MAX_TEAMS as usize {
+        return Err(de::Error::invalid_length(
+            modifiers.len(),
+            &format!("at most {MAX_TEAMS} teams").as_str(),
+        ));
+    }
+    let mut teams = [TeamModifier::default(); MAX_TEAMS as usize];
+    teams[..modifiers.len()].copy_from_slice(&modifiers);
+    Ok(teams)
+}
+
+impl BalanceConfig {
+    /// `team` is 1 to `MAX_TEAMS`
+    pub fn team(&self, team: u32) -> &TeamModifier {
+        &self.teams[team.clamp(1, MAX_TEAMS) as usize - 1]
+    }
+
+    /// See `com::unit_type_stats`
+    pub fn unit_type(&self, unit_type: u32) -> &UnitTypeStats {
+        &self.unit_types[unit_type.min(UNIT_TYPES - 1) as usize]
+    }
+
+    /// The default `spawn_weight` of each `UNIT_TYPE_*`
+    pub fn spawn_weights(&self) -> UVec3 {
+        UVec3::from_array(self.unit_types.map(|stats| stats.spawn_weight))
+    }
+
+    /// See `com::upgrade_cost`
+    pub fn upgrade_cost(&self, level: u32, team: u32) -> u32 {
+        let cost = self.upgrade_cost * (level.wrapping_add(1) as f32).sqrt() as u32;
+        (cost as f32 * self.team(team).upgrade_cost) as u32
+    }
+
+    /// See `com::terrain_move_rate`
+    pub fn terrain_move_rate(&self, water_depth: f32) -> f32 {
+        if water_depth <= 0.0 {
+            self.land_move_rate
+        } else if water_depth < SHALLOW_WATER_DEPTH {
+            self.shallow_water_move_rate
+        } else {
+            self.deep_water_move_rate
+        }
+    }
+}
+
+pub struct BalancePlugin;
+
+impl Plugin for BalancePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<BalanceConfig>()
+            .init_asset_loader::<BalanceConfigLoader>()
+            .init_resource::<BalanceConfig>()
+            .add_plugins(ExtractResourcePlugin::<BalanceConfig>::default())
+            .add_systems(Startup, load_balance_config)
+            .add_systems(Update, apply_balance_config);
+    }
+}
+
+#[derive(Resource)]
+struct BalanceConfigHandle(Handle<BalanceConfig>);
+
+fn load_balance_config(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(BalanceConfigHandle(asset_server.load(BALANCE_CONFIG_PATH)));
+}
+
+fn apply_balance_config(
+    mut events: EventReader<AssetEvent<BalanceConfig>>,
+    assets: Res<Assets<BalanceConfig>>,
+    handle: Option<Res<BalanceConfigHandle>>,
+    mut balance: ResMut<BalanceConfig>,
+) {
+    let Some(handle) = handle else {
+        return;
+    };
+    for event in events.read() {
+        if event.is_loaded_with_dependencies(&handle.0) || event.is_modified(&handle.0) {
+            if let Some(config) = assets.get(&handle.0) {
+                *balance = *config;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BalanceConfigLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for BalanceConfigLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BalanceConfigLoaderError::Io(e) => write!(f, "{e}"),
+            BalanceConfigLoaderError::Ron(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for BalanceConfigLoaderError {}
+
+#[derive(Default)]
+struct BalanceConfigLoader;
+
+impl AssetLoader for BalanceConfigLoader {
+    type Asset = BalanceConfig;
+    type Settings = ();
+    type Error = BalanceConfigLoaderError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<BalanceConfig, BalanceConfigLoaderError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader
+                .read_to_end(&mut bytes)
+                .await
+                .map_err(BalanceConfigLoaderError::Io)?;
+            ron::de::from_bytes(&bytes).map_err(BalanceConfigLoaderError::Ron)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["balance.ron"]
+    }
+}
@@ -0,0 +1,115 @@
+use bevy::{
+    math::*,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::ShaderType,
+    },
+};
+
+/// Picked from the main menu, turned into `AiSettings` for the shaders and the AI controller.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Brutal,
+}
+
+impl Difficulty {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "EASY",
+            Difficulty::Normal => "NORMAL",
+            Difficulty::Hard => "HARD",
+            Difficulty::Brutal => "BRUTAL",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Brutal,
+            Difficulty::Brutal => Difficulty::Easy,
+        }
+    }
+
+    pub fn ai_settings(&self) -> AiSettings {
+        match self {
+            Difficulty::Easy => AiSettings {
+                upgrade_order: uvec4(UPGRADE_MOVEMENT, UPGRADE_SPAWN, UPGRADE_ATTACK, 0),
+                income: 0.6,
+                attack_interval: 20,
+                hydra_aggro_radius: 0.0,
+                spare1_: 0,
+            },
+            // Full income and a wave every 10 attack upgrades, geese only go for the hydra in waves
+            Difficulty::Normal => AiSettings {
+                upgrade_order: uvec4(UPGRADE_ATTACK, UPGRADE_SPAWN, UPGRADE_MOVEMENT, 0),
+                income: 1.0,
+                attack_interval: 10,
+                hydra_aggro_radius: 0.0,
+                spare1_: 0,
+            },
+            Difficulty::Hard => AiSettings {
+                upgrade_order: uvec4(UPGRADE_ATTACK, UPGRADE_SPAWN, UPGRADE_MOVEMENT, 0),
+                income: 1.3,
+                attack_interval: 6,
+                hydra_aggro_radius: 24.0,
+                spare1_: 0,
+            },
+            Difficulty::Brutal => AiSettings {
+                upgrade_order: uvec4(UPGRADE_SPAWN, UPGRADE_ATTACK, UPGRADE_MOVEMENT, 0),
+                income: 1.75,
+                attack_interval: 4,
+                hydra_aggro_radius: 48.0,
+                spare1_: 0,
+            },
+        }
+    }
+}
+
+// Index into the upgrades texel and bit in `UnitCommand::upgrade_request`
+pub const UPGRADE_MOVEMENT: u32 = 0;
+pub const UPGRADE_ATTACK: u32 = 1;
+pub const UPGRADE_SPAWN: u32 = 2;
+
+/// How the AI teams play, must match `com::AiSettings`
+#[derive(Resource, Clone, Copy, ShaderType, Debug)]
+pub struct AiSettings {
+    /// Upgrades the AI favours, xyz are `UPGRADE_*`
+    pub upgrade_order: UVec4,
+    /// Multiplier on the credits the AI gets for each player goose that dies
+    pub income: f32,
+    /// `StrategicAi` launches a wave every this many attack upgrades, 0 only attacks when it has
+    /// the bigger army
+    pub attack_interval: u32,
+    /// AI geese this close to the player hydra go for it
+    pub hydra_aggro_radius: f32,
+    pub spare1_: u32,
+}
+
+impl Default for AiSettings {
+    fn default() -> Self {
+        Difficulty::default().ai_settings()
+    }
+}
+
+impl ExtractResource for AiSettings {
+    type Source = Difficulty;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.ai_settings()
+    }
+}
+
+pub struct DifficultyPlugin;
+
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Difficulty>()
+            .add_plugins(ExtractResourcePlugin::<AiSettings>::default());
+    }
+}
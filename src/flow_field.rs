@@ -0,0 +1,304 @@
+use bevy::{
+    core_pipeline::core_3d::{self},
+    ecs::query::QueryItem,
+    math::*,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_asset::RenderAssets,
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutDescriptor, CachedRenderPipelineId,
+            Extent3d, PipelineCache, RenderPassDescriptor, ShaderType, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureUsages, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::{CachedTexture, TextureCache},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    bind_group_utils::{
+        basic_fullscreen_tri_pipeline, load_color_attachment, opaque_target, uniform_buffer,
+        uniform_layout_entry, utexture_layout_entry,
+    },
+    image,
+    nav_grid::{NavGrid, NavGridImage},
+    resource,
+    units::{
+        pack_2x16_, queue_team_commands, ResetSimulation, SimulationConfig, SimulationState,
+        TeamCommands, UnitsNode, UnitsPass, COMMAND_ATTACK_MOVE, COMMAND_MOVE, COMMAND_PATROL,
+        MAX_TEAMS,
+    },
+};
+
+/// One distance per team in each texel, see `FlowFieldTargets`
+pub const FLOW_FIELD_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
+/// Relaxation passes per frame, a new field spreads about this many cells each frame. Even so the
+/// result ends up back in `FlowFieldTextures::a`.
+pub const FLOW_FIELD_ITERATIONS: u32 = 16;
+/// Distance in every field of cells `NavGrid` blocks. Must match common.wgsl.
+pub const FLOW_BLOCKED: u32 = u32::MAX;
+/// Distance of cells a field hasn't spread to yet. Must match common.wgsl.
+pub const FLOW_UNREACHED: u32 = u32::MAX - 1;
+/// Distance of a step to an orthogonal and to a diagonal neighbour
+pub const FLOW_STEP_COST: UVec2 = UVec2::new(2, 3);
+
+/// Where each team's geese were last sent, the flow field in the team's channel leads there.
+/// Geese whose dest has no field step straight toward it. Must match `com::FlowFieldTargets`.
+#[derive(Resource, Clone, ExtractResource, Copy, ShaderType, Debug, Default)]
+pub struct FlowFieldTargets {
+    /// Packed with `pack_2x16_`, indexed by team - 1
+    pub dests: UVec4,
+    /// Bit per team, set once the team has a field
+    pub in_use: u32,
+    /// Bit per team, set for the frame a field starts over from a new dest
+    pub reset: u32,
+    pub spare1_: u32,
+    pub spare2_: u32,
+}
+
+impl FlowFieldTargets {
+    /// Restarts the field of every team that sent its geese somewhere new this frame
+    pub fn assign(&mut self, commands: &TeamCommands) {
+        self.reset = 0;
+        for (index, command) in commands.teams.iter().enumerate() {
+            let moves = matches!(
+                command.command,
+                COMMAND_MOVE | COMMAND_ATTACK_MOVE | COMMAND_PATROL
+            );
+            let dest = pack_2x16_(command.dest);
+            if command.unit_group != 1 || !moves || self.has_field(index as u32 + 1, dest) {
+                continue;
+            }
+            self.dests[index] = dest;
+            self.in_use |= 1 << index;
+            self.reset |= 1 << index;
+        }
+    }
+
+    /// See `com::has_flow_field`, `dest` is packed
+    pub fn has_field(&self, team: u32, dest: u32) -> bool {
+        let index = team.clamp(1, MAX_TEAMS) - 1;
+        (self.in_use >> index) & 1 != 0 && self.dests[index as usize] == dest
+    }
+}
+
+pub struct FlowFieldPlugin;
+
+impl Plugin for FlowFieldPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FlowFieldTargets>()
+            .add_systems(PostUpdate, assign_flow_fields.after(queue_team_commands))
+            .add_plugins(ExtractResourcePlugin::<FlowFieldTargets>::default());
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .add_systems(Render, prepare_textures.in_set(RenderSet::PrepareResources))
+            .add_render_graph_node::<ViewNodeRunner<FlowFieldNode>>(
+                core_3d::graph::NAME,
+                FlowFieldNode::NAME,
+            )
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    core_3d::graph::node::DEFERRED_PREPASS,
+                    FlowFieldNode::NAME,
+                    UnitsNode::NAME,
+                ],
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<FlowFieldPipeline>();
+    }
+}
+
+fn assign_flow_fields(
+    mut targets: ResMut<FlowFieldTargets>,
+    mut events: EventReader<ResetSimulation>,
+    commands: Res<TeamCommands>,
+    simulation: Res<SimulationState>,
+    grid: Res<NavGrid>,
+) {
+    if events.read().count() > 0 {
+        *targets = FlowFieldTargets::default();
+    }
+    // Commands given while paused are dropped by unit_evaluate.wgsl too
+    if simulation.paused {
+        return;
+    }
+    targets.assign(&commands);
+    // Fields only ever shrink, start them over once the city has been baked in
+    if grid.is_changed() {
+        targets.reset |= targets.in_use;
+    }
+}
+
+#[derive(Default)]
+pub struct FlowFieldNode;
+impl FlowFieldNode {
+    pub const NAME: &'static str = "flow_field_pass";
+}
+
+impl ViewNode for FlowFieldNode {
+    type ViewQuery = &'static UnitsPass;
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        _units_pass: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let flow_pipeline = world.resource::<FlowFieldPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let flow_textures = world.resource::<FlowFieldTextures>();
+        let simulation = world.resource::<SimulationState>();
+        let targets = world.resource::<FlowFieldTargets>();
+
+        let images = world.resource::<RenderAssets<Image>>();
+        let nav_grid = image!(images, &resource!(world, NavGridImage).image);
+
+        if simulation.paused {
+            return Ok(());
+        }
+
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(flow_pipeline.pipeline_id) else {
+            return Ok(());
+        };
+
+        // Only the first pass starts fields over, the rest spread them
+        let first_uniform = uniform_buffer(*targets, render_context, "Flow Field Targets Uniform");
+        let targets_uniform = uniform_buffer(
+            FlowFieldTargets {
+                reset: 0,
+                ..*targets
+            },
+            render_context,
+            "Flow Field Targets Uniform",
+        );
+
+        for i in 0..FLOW_FIELD_ITERATIONS {
+            let (source, target) = if i % 2 == 0 {
+                (&flow_textures.a, &flow_textures.b)
+            } else {
+                (&flow_textures.b, &flow_textures.a)
+            };
+            let uniform = if i == 0 {
+                &first_uniform
+            } else {
+                &targets_uniform
+            };
+
+            let bind_group = render_context.render_device().create_bind_group(
+                "flow_field_bind_group",
+                &flow_pipeline.layout,
+                &BindGroupEntries::with_indices((
+                    (101, &source.default_view),
+                    (102, &nav_grid.texture_view),
+                    (103, uniform.as_entire_binding()),
+                )),
+            );
+
+            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("Flow Field"),
+                color_attachments: &[load_color_attachment(&target.default_view)],
+                depth_stencil_attachment: None,
+            });
+
+            render_pass.set_render_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+
+            render_pass.draw(0..3, 0..1);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+struct FlowFieldPipeline {
+    layout: BindGroupLayout,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for FlowFieldPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader_defs = world.resource::<SimulationConfig>().shader_defs();
+
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("flow_field_bind_group_layout"),
+            entries: &[
+                utexture_layout_entry(101, TextureViewDimension::D2), // Prev Flow Field
+                utexture_layout_entry(102, TextureViewDimension::D2), // Nav Grid
+                uniform_layout_entry(103, FlowFieldTargets::min_size()),
+            ],
+        });
+
+        let pipeline_id = basic_fullscreen_tri_pipeline(
+            "flow_field_pipeline",
+            "shaders/flow_field.wgsl",
+            world,
+            &layout,
+            shader_defs,
+            vec![opaque_target(FLOW_FIELD_FORMAT)],
+        );
+
+        Self {
+            layout,
+            pipeline_id,
+        }
+    }
+}
+
+/// The flow fields, kept from frame to frame. unit_evaluate.wgsl reads `a`.
+#[derive(Resource)]
+pub struct FlowFieldTextures {
+    pub a: CachedTexture,
+    pub b: CachedTexture,
+}
+
+fn prepare_textures(
+    mut commands: Commands,
+    mut texture_cache: ResMut<TextureCache>,
+    render_device: Res<RenderDevice>,
+    views: Query<&ExtractedCamera, With<UnitsPass>>,
+    config: Res<SimulationConfig>,
+) {
+    for _camera in &views {
+        let mut texture_descriptor = TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                depth_or_array_layers: 1,
+                width: config.units_width,
+                height: config.units_height,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: FLOW_FIELD_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+
+        texture_descriptor.label = Some("flow_field_a");
+        let a = texture_cache.get(&render_device, texture_descriptor.clone());
+        texture_descriptor.label = Some("flow_field_b");
+        let b = texture_cache.get(&render_device, texture_descriptor.clone());
+
+        commands.insert_resource(FlowFieldTextures { a, b });
+    }
+}
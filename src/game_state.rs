@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::{
+    readback::SimulationSnapshot,
+    units::{SimulationConfig, SimulationState, MAX_TEAMS, PLAYER_TEAM},
+};
+
+#[derive(States, Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum GameState {
+    #[default]
+    MainMenu,
+    Playing,
+    Victory,
+    Defeat,
+    Paused,
+}
+
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state::<GameState>()
+            .init_resource::<HydrasSpawned>()
+            .add_systems(
+                Update,
+                (
+                    check_hydras.run_if(in_state(GameState::Playing)),
+                    toggle_pause,
+                    freeze_simulation,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Hydra health reads 0 until the hydra has spawned, so only end the match once we've seen it alive.
+#[derive(Resource, Default)]
+struct HydrasSpawned {
+    /// `SimulationState::match_index` the flags are for, a reset or load starts a new match.
+    match_index: u32,
+    spawned: [bool; MAX_TEAMS as usize],
+}
+
+fn check_hydras(
+    snapshot: Res<SimulationSnapshot>,
+    simulation: Res<SimulationState>,
+    config: Res<SimulationConfig>,
+    mut spawned: ResMut<HydrasSpawned>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !snapshot.is_changed()
+        || !snapshot.received
+        || snapshot.match_index != simulation.match_index
+    {
+        return;
+    }
+
+    if spawned.match_index != snapshot.match_index {
+        *spawned = HydrasSpawned {
+            match_index: snapshot.match_index,
+            ..default()
+        };
+    }
+
+    let mut dead = [false; MAX_TEAMS as usize];
+    for (i, team) in snapshot.teams.iter().enumerate() {
+        if team.hydra.health > 0 {
+            spawned.spawned[i] = true;
+        }
+        dead[i] = spawned.spawned[i] && team.hydra.health == 0;
+    }
+
+    // The player wins once every other hydra is gone
+    let player = PLAYER_TEAM as usize - 1;
+    let enemies_dead = (0..config.teams as usize)
+        .filter(|i| *i != player)
+        .all(|i| dead[i]);
+    if dead[player] {
+        next_state.set(GameState::Defeat);
+    } else if enemies_dead {
+        next_state.set(GameState::Victory);
+    }
+}
+
+fn toggle_pause(
+    key_input: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !key_input.just_pressed(KeyCode::Escape) {
+        return;
+    }
+    match state.get() {
+        GameState::Playing => next_state.set(GameState::Paused),
+        GameState::Paused => next_state.set(GameState::Playing),
+        _ => (),
+    }
+}
+
+fn freeze_simulation(state: Res<State<GameState>>, mut simulation: ResMut<SimulationState>) {
+    simulation.paused = *state.get() != GameState::Playing;
+}
@@ -1,10 +1,18 @@
 #![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
+pub mod ai;
+pub mod balance;
 pub mod bind_group_utils;
 pub mod camera_controller;
+pub mod difficulty;
+pub mod flow_field;
+pub mod game_state;
 pub mod minimap;
+pub mod nav_grid;
 pub mod particles;
 pub mod post_process;
+pub mod readback;
+pub mod save;
 pub mod ui;
 pub mod units;
 
@@ -22,6 +30,8 @@ use bevy::{
     window::{PresentMode, PrimaryWindow},
 };
 
+use ai::AiPlugin;
+use balance::{BalanceConfig, BalancePlugin, GOOSE_FRAMES};
 use bevy_mod_taa::{TAAPlugin, TAASettings};
 use bevy_picoui::{
     pico::{Pico, Pico2dCamera},
@@ -29,15 +39,51 @@ use bevy_picoui::{
 };
 use bevy_ridiculous_ssgi::{ssgi::SSGIPass, SSGIBundle};
 use camera_controller::{OrthoCameraController, OrthoCameraControllerPlugin};
+use difficulty::DifficultyPlugin;
+use flow_field::FlowFieldPlugin;
+use game_state::{GameState, GameStatePlugin};
 use minimap::{MinimapPass, MinimapPlugin};
+use nav_grid::{city_transform, NavGrid, NavGridPlugin, WaterPlane, CITY_SCENE_PATH};
 use particles::{ParticlesPass, ParticlesPlugin};
 use post_process::PostProcessPlugin;
+use readback::{ReadbackPlugin, SimulationSnapshot};
+use save::SavePlugin;
 use ui::UIPlugin;
-use units::{UnitCommand, UnitsPass, UnitsPlugin};
+use units::{
+    sim::LARGE_UNIT_SIZE, SimulationConfig, SimulationState, TeamCommands, TeamWaypoints,
+    UnitsPass, UnitsPlugin, COMMAND_ATTACK_MOVE, COMMAND_HOLD, COMMAND_MOVE, COMMAND_PATROL,
+    COMMAND_STOP, CONTROL_GROUPS, MAX_PATHS, PLAYER_TEAM,
+};
 
 fn main() {
+    let arg = |name: &str| -> Option<u32> {
+        let mut args = std::env::args().skip_while(|arg| arg != name).skip(1);
+        args.next().and_then(|value| value.parse().ok())
+    };
+    // `--grid 256` for weak hardware, `--grid 1024` for big maps
+    let mut config = arg("--grid")
+        .map(SimulationConfig::with_grid_size)
+        .unwrap_or_default();
+    // `--teams 4` for a free for all against 3 AI teams
+    if let Some(teams) = arg("--teams") {
+        config = config.with_teams(teams);
+    }
+    // `--deterministic-attacks` for fights that don't depend on the frame rate
+    if std::env::args().any(|arg| arg == "--deterministic-attacks") {
+        config = config.with_deterministic_attacks(true);
+    }
+    // `--tick-rate 60` to simulate at a fixed rate whatever the frame rate
+    if let Some(tick_rate) = arg("--tick-rate") {
+        config = config.with_tick_rate(tick_rate);
+    }
+    // `--seed 1234` to play the same match again given the same orders
+    if let Some(seed) = arg("--seed") {
+        config = config.with_seed(seed);
+    }
+
     App::new()
         .insert_resource(AssetMetaCheck::Never)
+        .insert_resource(config)
         .insert_resource(Msaa::Off)
         .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .insert_resource(AmbientLight {
@@ -67,9 +113,11 @@ fn main() {
         .add_plugins((
             OrthoCameraControllerPlugin,
             ParticlesPlugin,
-            UnitsPlugin,
+            (UnitsPlugin, NavGridPlugin, FlowFieldPlugin),
+            (BalancePlugin, DifficultyPlugin, AiPlugin),
             TAAPlugin,
             MinimapPlugin,
+            ReadbackPlugin,
             //SSGIPlugin, // If you turn this off use the default lighting plugin
             PostProcessPlugin,
             LogDiagnosticsPlugin::default(),
@@ -77,9 +125,20 @@ fn main() {
             ExtractResourcePlugin::<UnitTexture>::default(),
             PicoPlugin::default(),
             UIPlugin,
+            GameStatePlugin,
+            SavePlugin,
         ))
+        .init_resource::<PendingOrder>()
+        .init_resource::<SelectedHydra>()
         .add_systems(Startup, (setup, load_unit_texture))
-        .add_systems(Update, (command_units, adjust_spec))
+        .add_systems(
+            Update,
+            (
+                command_units.run_if(in_state(GameState::Playing)),
+                adjust_spec,
+                warn_missing_goose_frames,
+            ),
+        )
         .run();
 }
 
@@ -90,21 +149,24 @@ fn setup(
     asset_server: Res<AssetServer>,
 ) {
     // Water
-    commands.spawn(PbrBundle {
-        mesh: meshes.add(
-            shape::Plane {
-                size: 10000.0,
-                subdivisions: 0,
-            }
-            .into(),
-        ),
-        material: materials.add(StandardMaterial {
-            base_color: Vec4::splat(0.01).into(),
-            reflectance: 0.01,
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(
+                shape::Plane {
+                    size: 10000.0,
+                    subdivisions: 0,
+                }
+                .into(),
+            ),
+            material: materials.add(StandardMaterial {
+                base_color: Vec4::splat(0.01).into(),
+                reflectance: 0.01,
+                ..default()
+            }),
             ..default()
-        }),
-        ..default()
-    });
+        },
+        WaterPlane,
+    ));
 
     //commands.spawn(PbrBundle {
     //    mesh: meshes.add(Mesh::from(shape::Cube { size: 2.0 })),
@@ -129,11 +191,9 @@ fn setup(
         },
         NotShadowCaster,
     ));
-    let mut transform = Transform::from_xyz(246.0, -0.1, 256.0).with_scale(Vec3::splat(0.15));
-    transform.rotate_y(95.0_f32.to_radians());
     commands.spawn(SceneBundle {
-        scene: asset_server.load("models/city.gltf#Scene0"),
-        transform,
+        scene: asset_server.load(CITY_SCENE_PATH),
+        transform: city_transform(),
         ..default()
     });
 
@@ -243,108 +303,293 @@ pub fn from_screenspace(
     camera.viewport_to_world(camera_transform, viewport_pos)
 }
 
-fn ray_plane_intersection(ray: Ray, plane_point: Vec3, plane_normal: Vec3) -> Option<Vec3> {
-    let denominator = plane_normal.dot(ray.direction);
-    if denominator.abs() < 1e-6 {
-        return None; // Ray is parallel to the plane
+/// Drags shorter than this many pixels are clicks
+const DRAG_THRESHOLD: f32 = 4.0;
+
+const CONTROL_GROUP_KEYS: [KeyCode; CONTROL_GROUPS as usize] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+];
+
+const ORDER_KEYS: [(KeyCode, u32); 4] = [
+    (KeyCode::X, COMMAND_STOP),
+    (KeyCode::Z, COMMAND_HOLD),
+    (KeyCode::F, COMMAND_ATTACK_MOVE),
+    (KeyCode::P, COMMAND_PATROL),
+];
+
+/// The `COMMAND_*` the next click on the board gives, set from `ORDER_KEYS` or the sidebar.
+/// Stop and hold don't wait for a click.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingOrder(pub u32);
+
+impl Default for PendingOrder {
+    fn default() -> Self {
+        PendingOrder(COMMAND_MOVE)
     }
+}
+
+/// Key that selects the player's next hydra, see `SelectedHydra::next`
+const NEXT_HYDRA_KEY: KeyCode = KeyCode::Tab;
+
+/// Slot of the one player hydra hydra orders go to, all of them if None. Set by clicking a
+/// hydra, `NEXT_HYDRA_KEY` or the sidebar.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SelectedHydra(pub Option<u32>);
 
-    let t = plane_normal.dot(plane_point - ray.origin) / denominator;
-    if t < 0.0 {
-        return None; // Intersection is behind the ray origin
+impl SelectedHydra {
+    /// The living hydra after the selected one in slot order, wrapping around
+    pub fn next(&self, snapshot: &SimulationSnapshot) -> Option<u32> {
+        let slots = || snapshot.player_hydras.iter().map(|(slot, _)| *slot);
+        let current = self.0;
+        slots()
+            .find(|slot| current.is_none_or(|current| *slot > current))
+            .or_else(|| slots().next())
     }
+}
+
+#[derive(Default)]
+struct Selection {
+    /// Cursor position the left button went down at
+    drag_start: Option<Vec2>,
+    /// Move orders only go to the box selected geese
+    active: bool,
+    /// `SimulationState::match_index` the selection is for, units start out unselected
+    match_index: u32,
+}
 
-    Some(ray.origin + ray.direction * t)
+#[derive(Default)]
+struct OrderPaths {
+    /// Path in the player's `WaypointPaths` shift orders are queued on, by unit group
+    current: [Option<u32>; 2],
+    /// Paths are reused round robin
+    next: u32,
 }
 
 fn command_units(
-    //mut gizmos: Gizmos,
+    mut gizmos: Gizmos,
     mouse_button_input: Res<Input<MouseButton>>,
     window: Query<&Window, With<PrimaryWindow>>,
     cameras: Query<(&Camera, &GlobalTransform), With<Camera3d>>,
-    mut unit_command: ResMut<UnitCommand>,
-    //mut select_start: Local<Option<Vec3>>,
+    mut team_commands: ResMut<TeamCommands>,
+    mut waypoints: ResMut<TeamWaypoints>,
+    simulation: Res<SimulationState>,
+    mut selection: Local<Selection>,
+    mut paths: Local<OrderPaths>,
     key_input: Res<Input<KeyCode>>,
     mut unit_group: Local<u32>,
+    mut pending_order: ResMut<PendingOrder>,
     pico: Res<Pico>,
+    nav_grid: Res<NavGrid>,
+    mut selected_hydra: ResMut<SelectedHydra>,
+    snapshot: Res<SimulationSnapshot>,
 ) {
     let window = window.get_single().unwrap();
 
-    if pico.interacting {
-        return;
+    if selection.match_index != simulation.match_index {
+        *selection = Selection {
+            match_index: simulation.match_index,
+            ..default()
+        };
+        *paths = default();
+    }
+
+    let alive = |slot: u32| snapshot.player_hydras.iter().any(|(s, _)| *s == slot);
+    if selected_hydra.0.is_some_and(|slot| !alive(slot)) {
+        // Died
+        selected_hydra.0 = None;
+    }
+    if key_input.just_pressed(NEXT_HYDRA_KEY) {
+        selected_hydra.0 = selected_hydra.next(&snapshot);
+    }
+    // Picked from the sidebar too
+    if selected_hydra.is_changed() && selected_hydra.0.is_some() {
+        *unit_group = 0;
+        paths.current[0] = None;
+    }
+    if *unit_group == 0 {
+        // Ring the hydras hydra orders go to
+        for (slot, hydra) in &snapshot.player_hydras {
+            if selected_hydra.0.is_some_and(|selected| selected != *slot) {
+                continue;
+            }
+            let surface = nav_grid.surface(hydra.pos.as_ivec2());
+            let center = vec3(hydra.pos.x, surface + 0.5, hydra.pos.y);
+            let color = Color::rgba(0.0, 1.0, 0.0, 0.2);
+            gizmos.circle(center, Vec3::Y, LARGE_UNIT_SIZE, color);
+        }
     }
 
-    if key_input.pressed(KeyCode::Key1) {
+    for path in paths.current.iter().flatten() {
+        let player_paths = waypoints.team(PLAYER_TEAM);
+        let mut points = player_paths
+            .path(*path)
+            .map(|p| {
+                let surface = nav_grid.surface(p.as_ivec2());
+                vec3(p.x as f32, surface + 0.5, p.y as f32)
+            })
+            .collect::<Vec<_>>();
+        if player_paths.looping[*path as usize] != 0 {
+            points.extend(points.first().copied());
+        }
+        gizmos.linestrip(points, Color::rgba(1.0, 1.0, 0.0, 0.2));
+    }
+
+    let unit_command = team_commands.team_mut(PLAYER_TEAM);
+
+    if key_input.just_pressed(KeyCode::H) {
+        // Every hydra
         *unit_group = 0;
+        selected_hydra.0 = None;
+        paths.current[0] = None;
     }
-    if key_input.pressed(KeyCode::Key2) {
+    if key_input.just_pressed(KeyCode::G) {
+        // Back to ordering every goose
         *unit_group = 1;
+        selection.active = false;
+        paths.current[1] = None;
+    }
+    // Ctrl + number assigns the selection to a control group, the number recalls it
+    let control = key_input.pressed(KeyCode::ControlLeft);
+    for (group, key) in (1..).zip(CONTROL_GROUP_KEYS) {
+        if !key_input.just_pressed(key) {
+            continue;
+        }
+        if control && selection.active {
+            unit_command.assign_group(group);
+        } else if !control {
+            unit_command.recall_group(group);
+            selection.active = true;
+            *unit_group = 1;
+            paths.current[1] = None;
+        }
+    }
+    for (key, command) in ORDER_KEYS {
+        if key_input.just_pressed(key) {
+            pending_order.0 = command;
+        }
     }
     unit_command.unit_group = *unit_group;
+    unit_command.hydra = selected_hydra.0.map_or(0, |slot| slot + 1);
+    unit_command.selected_only = (selection.active && *unit_group == 1) as u32;
 
-    let modifier = key_input.pressed(KeyCode::ShiftLeft) | key_input.pressed(KeyCode::ControlLeft);
-
-    if let Some((camera, transform)) = cameras.iter().next() {
-        let ray = window
-            .cursor_position()
-            .and_then(|cursor_pos| from_screenspace(cursor_pos, camera, transform, window));
-        if let Some(ray) = ray {
-            let intersection =
-                ray_plane_intersection(ray, vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
-            //dbg!(transform);
-            if !modifier
-                && (mouse_button_input.just_pressed(MouseButton::Right)
-                    || mouse_button_input.just_pressed(MouseButton::Left))
-            {
-                if let Some(intersection) = intersection {
-                    if intersection.x > 0.0 && intersection.z > 0.0 {
-                        unit_command.dest = uvec2(intersection.x as u32, intersection.z as u32);
-                        unit_command.command = 1u32;
-                    }
-                }
+    if matches!(pending_order.0, COMMAND_STOP | COMMAND_HOLD) {
+        unit_command.command = pending_order.0;
+        *pending_order = default();
+        paths.current[*unit_group as usize] = None;
+    }
+
+    if pico.interacting {
+        selection.drag_start = None;
+        return;
+    }
+
+    let queue = key_input.pressed(KeyCode::ShiftLeft);
+    let modifier = queue | control;
+
+    let Some((camera, transform)) = cameras.iter().next() else {
+        return;
+    };
+    let Some(cursor_pos) = window.cursor_position() else {
+        return;
+    };
+    // Where a point on the screen lands on the board
+    let ground = |screen_pos: Vec2| {
+        from_screenspace(screen_pos, camera, transform, window)
+            .and_then(|ray| nav_grid.ray_intersection(ray))
+    };
+
+    let mut order = mouse_button_input.just_pressed(MouseButton::Right);
+
+    if modifier {
+        // The camera controller is using the left button
+        selection.drag_start = None;
+    } else if mouse_button_input.just_pressed(MouseButton::Left) {
+        selection.drag_start = Some(cursor_pos);
+    } else if let Some(drag_start) = selection.drag_start {
+        // The board is at an angle so the screen rectangle lands on it as a quad
+        let corners = [
+            drag_start,
+            vec2(cursor_pos.x, drag_start.y),
+            cursor_pos,
+            vec2(drag_start.x, cursor_pos.y),
+        ]
+        .map(ground);
+        let dragged = drag_start.distance(cursor_pos) > DRAG_THRESHOLD;
+        if let [Some(a), Some(b), Some(c), Some(d)] = corners {
+            if dragged {
+                let lift = Vec3::Y * 0.5;
+                let color = Color::rgba(0.0, 1.0, 0.0, 0.1);
+                gizmos.linestrip([a, b, c, d, a].map(|corner| corner + lift), color);
             }
-            /* // Doesn't work naively with board at angle
-            if mouse_button_input.just_pressed(MouseButton::Left) {
-                *select_start = intersection;
+            if dragged && mouse_button_input.just_released(MouseButton::Left) {
+                unit_command.select([a, b, c, d].map(|corner| corner.xz()));
+                selection.active = true;
+                *unit_group = 1;
+                unit_command.unit_group = 1;
+                paths.current[1] = None;
+            }
+        }
+        if mouse_button_input.just_released(MouseButton::Left) {
+            selection.drag_start = None;
+            // Clicking one of the player's hydras selects it instead of ordering
+            let clicked_hydra = ground(cursor_pos).and_then(|p| {
+                snapshot
+                    .player_hydras
+                    .iter()
+                    .find(|(_, hydra)| hydra.pos.distance(p.xz()) < LARGE_UNIT_SIZE)
+                    .map(|(slot, _)| *slot)
+            });
+            if let Some(slot) = clicked_hydra.filter(|_| !dragged) {
+                selected_hydra.0 = Some(slot);
+                *unit_group = 0;
+                unit_command.unit_group = 0;
+                unit_command.hydra = slot + 1;
+                paths.current[0] = None;
+            } else {
+                order |= !dragged;
             }
-            if mouse_button_input.pressed(MouseButton::Left) {
-                if let Some(select_end) = intersection {
-                    if let Some(select_start) = *select_start {
-                        let c = Color::rgba(0.0, 1.0, 0.0, 0.1);
-                        gizmos.line(
-                            select_start,
-                            vec3(select_end.x, select_end.y, select_start.z),
-                            c,
-                        );
-                        gizmos.line(
-                            select_start,
-                            vec3(select_start.x, select_end.y, select_end.z),
-                            c,
-                        );
-                        gizmos.line(
-                            select_end,
-                            vec3(select_end.x, select_end.y, select_start.z),
-                            c,
-                        );
-                        gizmos.line(
-                            select_end,
-                            vec3(select_start.x, select_end.y, select_end.z),
-                            c,
-                        );
-                        if mouse_button_input.just_released(MouseButton::Left) {
-                            unit_command.select_region = uvec4(
-                                select_start.x as u32,
-                                select_start.z as u32,
-                                select_end.x as u32,
-                                select_end.z as u32,
-                            )
+        }
+    }
+
+    if order {
+        let command = pending_order.0;
+        *pending_order = default();
+        if let Some(intersection) = ground(cursor_pos) {
+            if intersection.x > 0.0 && intersection.z > 0.0 {
+                let dest = uvec2(intersection.x as u32, intersection.z as u32);
+                let group = *unit_group as usize;
+                let player_paths = waypoints.team_mut(PLAYER_TEAM);
+                let current = paths.current[group];
+                if let Some(path) = current.filter(|_| queue && command != COMMAND_PATROL) {
+                    // Shift appends to the path, a full path ignores the order
+                    player_paths.push(path, dest);
+                } else {
+                    // Patrols go back and forth between dest and where the group was last sent
+                    let patrol_from = current.and_then(|path| player_paths.path(path).last());
+                    // Don't take the path the other group is still queueing on
+                    let mut path = paths.next;
+                    if paths.current[1 - group] == Some(path) {
+                        path = (path + 1) % MAX_PATHS;
+                    }
+                    paths.next = (path + 1) % MAX_PATHS;
+                    player_paths.start(path, dest);
+                    if command == COMMAND_PATROL {
+                        if let Some(from) = patrol_from {
+                            player_paths.push(path, from);
                         }
+                        player_paths.looping[path as usize] = 1;
                     }
+                    paths.current[group] = Some(path);
+                    unit_command.dest = dest;
+                    unit_command.command = command;
                 }
-            } else {
-                *select_start = None;
             }
-            */
         }
     }
 }
@@ -362,6 +607,33 @@ pub fn load_unit_texture(mut commands: Commands, ass: Res<AssetServer>) {
     });
 }
 
+/// unit_material.wgsl draws types whose frames are past the end of SmallGoose.ktx2 with the first
+/// type's frames
+fn warn_missing_goose_frames(
+    balance: Res<BalanceConfig>,
+    unit_texture: Option<Res<UnitTexture>>,
+    images: Res<Assets<Image>>,
+    mut warned: Local<bool>,
+) {
+    let Some(image) = unit_texture.and_then(|texture| images.get(&texture.small_goose)) else {
+        return;
+    };
+    if *warned {
+        return;
+    }
+    let layers = image.texture_descriptor.size.depth_or_array_layers;
+    for (unit_type, stats) in balance.unit_types.iter().enumerate() {
+        if stats.texture_layer + GOOSE_FRAMES > layers {
+            warn!(
+                "Unit type {unit_type} is drawn from layer {} but SmallGoose.ktx2 only has {layers}, \
+                using the first type's frames",
+                stats.texture_layer
+            );
+            *warned = true;
+        }
+    }
+}
+
 //fn move_directional_light(
 //    mut query: Query<&mut Transform, With<DirectionalLight>>,
 //    mut motion_evr: EventReader<MouseMotion>,
@@ -9,8 +9,8 @@ use bevy::{
         },
         render_resource::{
             BindGroupEntries, BindGroupLayout, BindGroupLayoutDescriptor, CachedRenderPipelineId,
-            Extent3d, PipelineCache, RenderPassDescriptor, TextureDescriptor, TextureDimension,
-            TextureFormat, TextureUsages, TextureViewDimension,
+            Extent3d, PipelineCache, RenderPassDescriptor, ShaderDefVal, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureUsages, TextureViewDimension,
         },
         renderer::{RenderContext, RenderDevice},
         texture::{CachedTexture, TextureCache},
@@ -26,12 +26,24 @@ use crate::{
         view_layout_entry,
     },
     shader_def_uint,
-    units::{UnitsDataTextures, UnitsNode, ATTACK_RADIUS, UNITS_DATA_HEIGHT, UNITS_DATA_WIDTH},
+    units::{SimulationConfig, UnitsDataTextures, UnitsNode, ATTACK_RADIUS},
 };
 
 pub const MINIMAP_DATA_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
 pub const MINIMAP_SCALE: u32 = 4;
 
+/// Minimap texels have one channel per team, geese alive in the low bits and geese that died this
+/// frame above them. Must match common.wgsl.
+pub const MINIMAP_ALIVE_BITS: u32 = 17;
+
+pub fn minimap_alive(data: UVec4) -> UVec4 {
+    data & UVec4::splat((1 << MINIMAP_ALIVE_BITS) - 1)
+}
+
+pub fn minimap_died(data: UVec4) -> UVec4 {
+    data >> MINIMAP_ALIVE_BITS
+}
+
 pub struct MinimapPlugin;
 
 impl Plugin for MinimapPlugin {
@@ -70,7 +82,7 @@ impl Plugin for MinimapPlugin {
 pub struct MinimapPass;
 
 #[derive(Default)]
-struct MinimapNode;
+pub struct MinimapNode;
 impl MinimapNode {
     pub const NAME: &'static str = "minimap_pass";
 }
@@ -85,18 +97,20 @@ impl ViewNode for MinimapNode {
         (view_uniform_offset, _view_target): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), NodeRunError> {
+        update_minimap(render_context, view_uniform_offset, world);
+
         let unit_pipeline = world.resource::<MinimapPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let minimap_textures = world.resource::<MinimapTextures>();
         let unit_data_texture = world.resource::<UnitsDataTextures>();
 
         // ---------------------------------------
-        // Generate Minimap Texture
+        // Generate Control Groups Texture
         // ---------------------------------------
 
         {
             let Some(pipeline) =
-                pipeline_cache.get_render_pipeline(unit_pipeline.update_pipeline_id)
+                pipeline_cache.get_render_pipeline(unit_pipeline.groups_pipeline_id)
             else {
                 return Ok(());
             };
@@ -108,14 +122,14 @@ impl ViewNode for MinimapNode {
                     (0, view_binding(world)),
                     (9, globals_binding(world)),
                     (101, &unit_data_texture.a.default_view),
-                    (103, &unit_data_texture.attack_a.default_view),
+                    (103, &unit_data_texture.attack_a.default_view), // Placeholder to avoid changing layout
                 )),
             );
 
             let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("generate_minimap_pass"),
+                label: Some("generate_groups_pass"),
                 color_attachments: &[load_color_attachment(
-                    &minimap_textures.minimap_tex.default_view,
+                    &minimap_textures.groups_tex.default_view,
                 )],
                 depth_stencil_attachment: None,
             });
@@ -127,99 +141,126 @@ impl ViewNode for MinimapNode {
         }
 
         // ---------------------------------------
-        // Downscale Minimap Texture
+        // Downscale Control Groups Textures
         // ---------------------------------------
 
-        let Some(pipeline) =
-            pipeline_cache.get_render_pipeline(unit_pipeline.minimap_downscale_pipeline_id)
-        else {
-            return Ok(());
-        };
-        {
-            let bind_group = render_context.render_device().create_bind_group(
-                "minimap_bind_group",
-                &unit_pipeline.update_layout,
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &minimap_textures.minimap_tex.default_view),
-                    (103, &unit_data_texture.attack_a.default_view), // Placeholder to avoid changing layout
-                )),
-            );
+        let t = minimap_textures;
+        downscale(
+            render_context,
+            view_uniform_offset,
+            world,
+            &[
+                (&t.groups_tex, &t.groups_sm_tex),
+                (&t.groups_sm_tex, &t.groups_sm2_tex),
+                (&t.groups_sm2_tex, &t.groups_sm3_tex),
+            ],
+        );
 
-            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("downscale_minimap_pass"),
-                color_attachments: &[load_color_attachment(
-                    &minimap_textures.minimap_sm_tex.default_view,
-                )],
-                depth_stencil_attachment: None,
-            });
+        Ok(())
+    }
+}
 
-            render_pass.set_render_pipeline(pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+/// Rebuilds the minimap and its downscaled levels from unit data a. `UnitsNode` also runs this
+/// between ticks so the large unit update counts the geese that died on every tick.
+pub fn update_minimap(
+    render_context: &mut RenderContext,
+    view_uniform_offset: &ViewUniformOffset,
+    world: &World,
+) {
+    let unit_pipeline = world.resource::<MinimapPipeline>();
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let minimap_textures = world.resource::<MinimapTextures>();
+    let unit_data_texture = world.resource::<UnitsDataTextures>();
 
-            render_pass.draw(0..3, 0..1);
-        }
+    // ---------------------------------------
+    // Generate Minimap Texture
+    // ---------------------------------------
 
-        // ---------------------------------------
-        // Downscale 2 Minimap Texture
-        // ---------------------------------------
-        {
-            let bind_group = render_context.render_device().create_bind_group(
-                "minimap_bind_group",
-                &unit_pipeline.update_layout,
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &minimap_textures.minimap_sm_tex.default_view),
-                    (103, &unit_data_texture.attack_a.default_view), // Placeholder to avoid changing layout
-                )),
-            );
+    {
+        let Some(pipeline) = pipeline_cache.get_render_pipeline(unit_pipeline.update_pipeline_id)
+        else {
+            return;
+        };
 
-            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("downscale_minimap_pass"),
-                color_attachments: &[load_color_attachment(
-                    &minimap_textures.minimap_sm2_tex.default_view,
-                )],
-                depth_stencil_attachment: None,
-            });
+        let bind_group = render_context.render_device().create_bind_group(
+            "minimap_bind_group",
+            &unit_pipeline.update_layout,
+            &BindGroupEntries::with_indices((
+                (0, view_binding(world)),
+                (9, globals_binding(world)),
+                (101, &unit_data_texture.a.default_view),
+                (103, &unit_data_texture.attack_a.default_view),
+            )),
+        );
 
-            render_pass.set_render_pipeline(pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("generate_minimap_pass"),
+            color_attachments: &[load_color_attachment(
+                &minimap_textures.minimap_tex.default_view,
+            )],
+            depth_stencil_attachment: None,
+        });
 
-            render_pass.draw(0..3, 0..1);
-        }
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
 
-        // ---------------------------------------
-        // Downscale 3 Minimap Texture
-        // ---------------------------------------
-        {
-            let bind_group = render_context.render_device().create_bind_group(
-                "minimap_bind_group",
-                &unit_pipeline.update_layout,
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &minimap_textures.minimap_sm2_tex.default_view),
-                    (103, &unit_data_texture.attack_a.default_view), // Placeholder to avoid changing layout
-                )),
-            );
+        render_pass.draw(0..3, 0..1);
+    }
 
-            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("downscale_minimap_pass"),
-                color_attachments: &[load_color_attachment(
-                    &minimap_textures.minimap_sm3_tex.default_view,
-                )],
-                depth_stencil_attachment: None,
-            });
+    // ---------------------------------------
+    // Downscale Minimap Texture
+    // ---------------------------------------
+
+    let t = minimap_textures;
+    downscale(
+        render_context,
+        view_uniform_offset,
+        world,
+        &[
+            (&t.minimap_tex, &t.minimap_sm_tex),
+            (&t.minimap_sm_tex, &t.minimap_sm2_tex),
+            (&t.minimap_sm2_tex, &t.minimap_sm3_tex),
+        ],
+    );
+}
 
-            render_pass.set_render_pipeline(pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+fn downscale(
+    render_context: &mut RenderContext,
+    view_uniform_offset: &ViewUniformOffset,
+    world: &World,
+    levels: &[(&CachedTexture, &CachedTexture)],
+) {
+    let unit_pipeline = world.resource::<MinimapPipeline>();
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let unit_data_texture = world.resource::<UnitsDataTextures>();
+
+    let Some(pipeline) =
+        pipeline_cache.get_render_pipeline(unit_pipeline.minimap_downscale_pipeline_id)
+    else {
+        return;
+    };
+    for (source, target) in levels {
+        let bind_group = render_context.render_device().create_bind_group(
+            "minimap_bind_group",
+            &unit_pipeline.update_layout,
+            &BindGroupEntries::with_indices((
+                (0, view_binding(world)),
+                (9, globals_binding(world)),
+                (101, &source.default_view),
+                (103, &unit_data_texture.attack_a.default_view), // Placeholder to avoid changing layout
+            )),
+        );
 
-            render_pass.draw(0..3, 0..1);
-        }
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("downscale_minimap_pass"),
+            color_attachments: &[load_color_attachment(&target.default_view)],
+            depth_stencil_attachment: None,
+        });
 
-        Ok(())
+        render_pass.set_render_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+
+        render_pass.draw(0..3, 0..1);
     }
 }
 
@@ -227,6 +268,7 @@ impl ViewNode for MinimapNode {
 struct MinimapPipeline {
     update_layout: BindGroupLayout,
     update_pipeline_id: CachedRenderPipelineId,
+    groups_pipeline_id: CachedRenderPipelineId,
     minimap_downscale_pipeline_id: CachedRenderPipelineId,
 }
 
@@ -234,7 +276,10 @@ impl FromWorld for MinimapPipeline {
     fn from_world(world: &mut World) -> Self {
         let mut shader_defs = Vec::new();
         shader_defs.extend_from_slice(&[shader_def_uint!(ATTACK_RADIUS)]);
-        shader_defs.extend_from_slice(&[shader_def_uint!(MINIMAP_SCALE)]);
+        shader_defs.extend_from_slice(&[ShaderDefVal::UInt(
+            "MINIMAP_SCALE".into(),
+            world.resource::<SimulationConfig>().minimap_scale,
+        )]);
 
         let render_device = world.resource::<RenderDevice>();
 
@@ -257,6 +302,15 @@ impl FromWorld for MinimapPipeline {
             vec![opaque_target(MINIMAP_DATA_FORMAT)],
         );
 
+        let groups_pipeline_id = basic_fullscreen_tri_pipeline(
+            "minimap_groups_pipeline",
+            "shaders/minimap_groups.wgsl",
+            world,
+            &update_layout,
+            shader_defs.clone(),
+            vec![opaque_target(MINIMAP_DATA_FORMAT)],
+        );
+
         let minimap_downscale_pipeline_id = basic_fullscreen_tri_pipeline(
             "minimap_downsample_pipeline",
             "shaders/minimap_downsample.wgsl",
@@ -269,6 +323,7 @@ impl FromWorld for MinimapPipeline {
         Self {
             update_layout,
             update_pipeline_id,
+            groups_pipeline_id,
             minimap_downscale_pipeline_id,
         }
     }
@@ -280,26 +335,38 @@ pub struct MinimapTextures {
     pub minimap_sm_tex: CachedTexture,
     pub minimap_sm2_tex: CachedTexture,
     pub minimap_sm3_tex: CachedTexture,
+    /// Player geese per control group, see minimap_groups.wgsl. Same sizes as the minimap.
+    pub groups_tex: CachedTexture,
+    pub groups_sm_tex: CachedTexture,
+    pub groups_sm2_tex: CachedTexture,
+    pub groups_sm3_tex: CachedTexture,
 }
 
 fn prepare_textures(
     mut commands: Commands,
     mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
+    config: Res<SimulationConfig>,
 ) {
+    let size = |level| {
+        let size = config.minimap_size(level);
+        Extent3d {
+            depth_or_array_layers: 1,
+            width: size.x,
+            height: size.y,
+        }
+    };
+
     let mut texture_descriptor = TextureDescriptor {
         label: None,
-        size: Extent3d {
-            depth_or_array_layers: 1,
-            width: UNITS_DATA_WIDTH / MINIMAP_SCALE,
-            height: UNITS_DATA_HEIGHT / MINIMAP_SCALE,
-        },
+        size: size(0),
         mip_level_count: 1,
         sample_count: 1,
         dimension: TextureDimension::D2,
         format: MINIMAP_DATA_FORMAT,
         usage: TextureUsages::RENDER_ATTACHMENT
             | TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_SRC
             | TextureUsages::COPY_DST,
         view_formats: &[],
     };
@@ -308,33 +375,35 @@ fn prepare_textures(
     let minimap_data_texture = texture_cache.get(&render_device, texture_descriptor.clone());
 
     texture_descriptor.label = Some("minimap_sm_data_texture");
-    texture_descriptor.size = Extent3d {
-        depth_or_array_layers: 1,
-        width: texture_descriptor.size.width / MINIMAP_SCALE,
-        height: texture_descriptor.size.width / MINIMAP_SCALE,
-    };
+    texture_descriptor.size = size(1);
     let minimap_sm_data_texture = texture_cache.get(&render_device, texture_descriptor.clone());
 
     texture_descriptor.label = Some("minimap_sm2_data_texture");
-    texture_descriptor.size = Extent3d {
-        depth_or_array_layers: 1,
-        width: texture_descriptor.size.width / MINIMAP_SCALE,
-        height: texture_descriptor.size.width / MINIMAP_SCALE,
-    };
+    texture_descriptor.size = size(2);
     let minimap_sm2_data_texture = texture_cache.get(&render_device, texture_descriptor.clone());
 
     texture_descriptor.label = Some("minimap_sm3_data_texture");
-    texture_descriptor.size = Extent3d {
-        depth_or_array_layers: 1,
-        width: texture_descriptor.size.width / MINIMAP_SCALE,
-        height: texture_descriptor.size.width / MINIMAP_SCALE,
-    };
+    texture_descriptor.size = size(3);
     let minimap_sm3_data_texture = texture_cache.get(&render_device, texture_descriptor.clone());
 
+    let mut groups_texture = |label, level| {
+        texture_descriptor.label = Some(label);
+        texture_descriptor.size = size(level);
+        texture_cache.get(&render_device, texture_descriptor.clone())
+    };
+    let groups_tex = groups_texture("groups_data_texture", 0);
+    let groups_sm_tex = groups_texture("groups_sm_data_texture", 1);
+    let groups_sm2_tex = groups_texture("groups_sm2_data_texture", 2);
+    let groups_sm3_tex = groups_texture("groups_sm3_data_texture", 3);
+
     commands.insert_resource(MinimapTextures {
         minimap_tex: minimap_data_texture,
         minimap_sm_tex: minimap_sm_data_texture,
         minimap_sm2_tex: minimap_sm2_data_texture,
         minimap_sm3_tex: minimap_sm3_data_texture,
+        groups_tex,
+        groups_sm_tex,
+        groups_sm2_tex,
+        groups_sm3_tex,
     });
 }
@@ -0,0 +1,516 @@
+use std::path::Path;
+
+use bevy::{
+    math::*,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        mesh::{PrimitiveTopology, VertexAttributeValues},
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+
+use crate::units::{f32_to_f16, hydra_start, SimulationConfig};
+
+pub const CITY_SCENE_PATH: &str = "models/city.gltf#Scene0";
+/// The files `CITY_SCENE_PATH` is loaded from, relative to the assets folder
+pub const CITY_SOURCE_PATHS: [&str; 2] = ["models/city.gltf", "models/city.bin"];
+pub const NAV_GRID_CACHE_PATH: &str = "city.navgrid";
+pub const NAV_GRID_MAGIC: [u8; 4] = *b"GGLN";
+/// Bump when the rasterization changes so old caches are baked again
+pub const NAV_GRID_VERSION: u32 = 2;
+// Magic, version, width, height, the 2 words of the source key and the water level
+const NAV_GRID_HEADER_SIZE: usize = 4 + 6 * 4;
+pub const NAV_GRID_FORMAT: TextureFormat = TextureFormat::R8Uint;
+/// Surface height and water depth of each cell, see `NavGrid::terrain_texels`
+pub const TERRAIN_FORMAT: TextureFormat = TextureFormat::Rg16Float;
+/// Geometry higher than this blocks the cell under it. Low things like roads and buoys don't.
+pub const OBSTACLE_HEIGHT: f32 = 2.0;
+/// Geese wade through water shallower than this and swim in anything deeper. Must match
+/// common.wgsl.
+pub const SHALLOW_WATER_DEPTH: f32 = 1.0;
+/// The mesh the water surface is taken from, it doesn't count as ground
+pub const WATER_NODE_NAME: &str = "Water Stand-In";
+
+/// Where `setup` spawns the city, the board is x and z from 0 to the grid size
+pub fn city_transform() -> Transform {
+    let mut transform = Transform::from_xyz(246.0, -0.1, 256.0).with_scale(Vec3::splat(0.15));
+    transform.rotate_y(95.0_f32.to_radians());
+    transform
+}
+
+/// Cells of the units grid geese can't walk through or spawn in, and the terrain under them.
+/// Open water at height 0 until the city scene has loaded and been baked into it, or it has been
+/// read from the cache. Baking only needs the scene and its meshes, not an `App` or a GPU.
+#[derive(Resource, Clone, Debug)]
+pub struct NavGrid {
+    pub size: UVec2,
+    /// One byte per cell, non zero if blocked
+    pub blocked: Vec<u8>,
+    /// Height of the highest ground at each cell, rooftops included. Negative infinity where
+    /// there is nothing under the water.
+    pub ground: Vec<f32>,
+    pub water_level: f32,
+}
+
+impl NavGrid {
+    pub fn new(size: UVec2) -> Self {
+        let cells = (size.x * size.y) as usize;
+        Self {
+            size,
+            blocked: vec![0; cells],
+            ground: vec![f32::NEG_INFINITY; cells],
+            water_level: 0.0,
+        }
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        if cell.cmplt(IVec2::ZERO).any() || cell.cmpge(self.size.as_ivec2()).any() {
+            return None;
+        }
+        Some((cell.y as u32 * self.size.x + cell.x as u32) as usize)
+    }
+
+    /// Out of bounds cells are open, like everywhere else off the city
+    pub fn is_blocked(&self, cell: IVec2) -> bool {
+        self.index(cell)
+            .is_some_and(|index| self.blocked[index] != 0)
+    }
+
+    /// Height units stand at, the ground or the water on top of it. Off the grid is open water.
+    pub fn surface(&self, cell: IVec2) -> f32 {
+        self.index(cell).map_or(self.water_level, |index| {
+            self.ground[index].max(self.water_level)
+        })
+    }
+
+    /// How far under the water the ground is, 0 on land
+    pub fn water_depth(&self, cell: IVec2) -> f32 {
+        self.index(cell).map_or(f32::INFINITY, |index| {
+            (self.water_level - self.ground[index]).max(0.0)
+        })
+    }
+
+    /// `surface` and `water_depth` of every cell as `TERRAIN_FORMAT` texels
+    pub fn terrain_texels(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.ground.len() * 4);
+        for y in 0..self.size.y as i32 {
+            for x in 0..self.size.x as i32 {
+                let cell = ivec2(x, y);
+                bytes.extend_from_slice(&f32_to_f16(self.surface(cell)).to_le_bytes());
+                bytes.extend_from_slice(&f32_to_f16(self.water_depth(cell)).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Where `ray` first hits the terrain, stepping along it half a cell at a time
+    pub fn ray_intersection(&self, ray: Ray) -> Option<Vec3> {
+        let top = self.ground.iter().copied().fold(self.water_level, f32::max);
+        let plane = |height: f32| {
+            ray.intersect_plane(vec3(0.0, height, 0.0), Vec3::Y)
+                .map(|distance| ray.get_point(distance))
+        };
+        let end = plane(self.water_level)?;
+        let start = plane(top).unwrap_or(end);
+        let steps = (start.xz().distance(end.xz()) * 2.0).ceil() as u32;
+        for i in 0..steps {
+            let p = start.lerp(end, i as f32 / steps as f32);
+            let surface = self.surface(p.xz().round().as_ivec2());
+            if p.y <= surface {
+                return Some(vec3(p.x, surface, p.z));
+            }
+        }
+        Some(end)
+    }
+
+    pub fn blocked_count(&self) -> usize {
+        self.blocked.iter().filter(|cell| **cell != 0).count()
+    }
+
+    /// Raises the ground to every mesh in `scene` and blocks the cells under the parts above
+    /// `OBSTACLE_HEIGHT`. The top of `WATER_NODE_NAME` becomes the water level. `root` is the
+    /// transform the scene is spawned with, see `city_transform`.
+    pub fn bake_scene(&mut self, scene: &Scene, meshes: &Assets<Mesh>, root: Transform) {
+        let world = &scene.world;
+        let mut water_level = None;
+        for entity in world.iter_entities() {
+            let Some(mesh) = entity
+                .get::<Handle<Mesh>>()
+                .and_then(|mesh| meshes.get(mesh))
+            else {
+                continue;
+            };
+            if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+                continue;
+            }
+            let Some(VertexAttributeValues::Float32x3(positions)) =
+                mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+            else {
+                continue;
+            };
+
+            // Scene entities have no GlobalTransform until spawned, walk up the hierarchy instead
+            let mut affine = Affine3A::IDENTITY;
+            let mut water = false;
+            let mut current = Some(entity.id());
+            while let Some(id) = current {
+                if let Some(transform) = world.get::<Transform>(id) {
+                    affine = transform.compute_affine() * affine;
+                }
+                water |= world
+                    .get::<Name>(id)
+                    .is_some_and(|name| name.as_str() == WATER_NODE_NAME);
+                current = world.get::<Parent>(id).map(|parent| parent.get());
+            }
+            let affine = root.compute_affine() * affine;
+
+            let positions: Vec<Vec3> = positions
+                .iter()
+                .map(|p| affine.transform_point3(Vec3::from(*p)))
+                .collect();
+            if water {
+                let top = positions
+                    .iter()
+                    .map(|p| p.y)
+                    .fold(f32::NEG_INFINITY, f32::max);
+                water_level = Some(water_level.unwrap_or(top).max(top));
+                continue;
+            }
+            let indices: Vec<usize> = match mesh.indices() {
+                Some(indices) => indices.iter().collect(),
+                None => (0..positions.len()).collect(),
+            };
+            for triangle in indices.chunks_exact(3) {
+                self.rasterize_triangle([
+                    positions[triangle[0]],
+                    positions[triangle[1]],
+                    positions[triangle[2]],
+                ]);
+            }
+        }
+        if let Some(water_level) = water_level {
+            self.water_level = water_level;
+        }
+    }
+
+    /// Raises the ground of the cells whose center is under the triangle and blocks them if it is
+    /// above `OBSTACLE_HEIGHT` there, x and z of the corners are in cells
+    pub fn rasterize_triangle(&mut self, [a, b, c]: [Vec3; 3]) {
+        let (a2, b2, c2) = (a.xz(), b.xz(), c.xz());
+        let area = (b2 - a2).perp_dot(c2 - a2);
+        // Walls seen from above, the roofs on top of them block the cells
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+        let min = a2.min(b2).min(c2).ceil().max(Vec2::ZERO);
+        let max = a2.max(b2).max(c2).floor().min(self.size.as_vec2() - 1.0);
+        if min.cmpgt(max).any() {
+            return;
+        }
+        for y in min.y as u32..=max.y as u32 {
+            for x in min.x as u32..=max.x as u32 {
+                let p = vec2(x as f32, y as f32);
+                let weight_a = (c2 - b2).perp_dot(p - b2) / area;
+                let weight_b = (a2 - c2).perp_dot(p - c2) / area;
+                let weight_c = 1.0 - weight_a - weight_b;
+                if weight_a < 0.0 || weight_b < 0.0 || weight_c < 0.0 {
+                    continue;
+                }
+                let index = (y * self.size.x + x) as usize;
+                let height = weight_a * a.y + weight_b * b.y + weight_c * c.y;
+                self.ground[index] = self.ground[index].max(height);
+                if height > OBSTACLE_HEIGHT {
+                    self.blocked[index] = 1;
+                }
+            }
+        }
+    }
+
+    /// `key` is from `city_source_key`
+    pub fn to_bytes(&self, key: u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(NAV_GRID_HEADER_SIZE + self.blocked.len() * 5);
+        bytes.extend_from_slice(&NAV_GRID_MAGIC);
+        for word in [
+            NAV_GRID_VERSION,
+            self.size.x,
+            self.size.y,
+            key as u32,
+            (key >> 32) as u32,
+            self.water_level.to_bits(),
+        ] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.blocked);
+        for height in &self.ground {
+            bytes.extend_from_slice(&height.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// None unless the grid was baked at `size` by this version from the same city as `key`
+    pub fn from_bytes(bytes: &[u8], size: UVec2, key: u64) -> Option<Self> {
+        let cells = (size.x * size.y) as usize;
+        let (header, rest) = bytes.split_at_checked(NAV_GRID_HEADER_SIZE)?;
+        let word = |i: usize| u32::from_le_bytes(header[4 + i * 4..8 + i * 4].try_into().unwrap());
+        let current = [
+            NAV_GRID_VERSION,
+            size.x,
+            size.y,
+            key as u32,
+            (key >> 32) as u32,
+        ];
+        if header[..4] != NAV_GRID_MAGIC
+            || (0..current.len()).any(|i| word(i) != current[i])
+            || rest.len() != cells * 5
+        {
+            return None;
+        }
+        let (blocked, ground) = rest.split_at(cells);
+        Some(Self {
+            size,
+            blocked: blocked.to_vec(),
+            ground: ground
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect(),
+            water_level: f32::from_bits(word(5)),
+        })
+    }
+
+    pub fn write_cache(&self, path: &Path, key: u64) -> std::io::Result<()> {
+        std::fs::write(path, self.to_bytes(key))
+    }
+
+    /// None if there is no cache or it's out of date
+    pub fn read_cache(path: &Path, size: UVec2, key: u64) -> Option<Self> {
+        Self::from_bytes(&std::fs::read(path).ok()?, size, key)
+    }
+}
+
+/// Hash of everything the grid is baked from but its size: the city files, where the city is put
+/// and the obstacle height. None if the city files can't be read, then nothing is cached.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn city_source_key() -> Option<u64> {
+    use bevy::asset::io::file::FileAssetReader;
+    use std::hash::{Hash, Hasher};
+
+    // The default `AssetPlugin::file_path`
+    let reader = FileAssetReader::new("assets");
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for path in CITY_SOURCE_PATHS {
+        std::fs::read(reader.root_path().join(path))
+            .ok()?
+            .hash(&mut hasher);
+    }
+    // Debug prints floats exactly
+    format!("{:?} {OBSTACLE_HEIGHT:?}", city_transform()).hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn city_source_key() -> Option<u64> {
+    None
+}
+
+/// `NavGrid` as a `NAV_GRID_FORMAT` texture for the flow field pass and unit_evaluate.wgsl
+#[derive(Resource, ExtractResource, Clone)]
+pub struct NavGridImage {
+    pub image: Handle<Image>,
+    /// `TERRAIN_FORMAT`, for the units shaders
+    pub terrain: Handle<Image>,
+}
+
+/// Kept at the water level of `NavGrid`
+#[derive(Component)]
+pub struct WaterPlane;
+
+#[derive(Resource)]
+struct CityBake {
+    /// None once the grid has been baked or read from the cache
+    scene: Option<Handle<Scene>>,
+    cache_key: Option<u64>,
+}
+
+pub struct NavGridPlugin;
+
+impl Plugin for NavGridPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_nav_grid)
+            .add_systems(Update, (bake_nav_grid, follow_water_level).chain())
+            .add_plugins(ExtractResourcePlugin::<NavGridImage>::default());
+    }
+}
+
+fn setup_nav_grid(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    config: Res<SimulationConfig>,
+) {
+    let size = uvec2(config.units_width, config.units_height);
+    let cache_key = city_source_key();
+    let cached =
+        cache_key.and_then(|key| NavGrid::read_cache(Path::new(NAV_GRID_CACHE_PATH), size, key));
+    let scene = if cached.is_some() {
+        info!("Loaded the nav grid from {NAV_GRID_CACHE_PATH}");
+        None
+    } else {
+        Some(asset_server.load(CITY_SCENE_PATH))
+    };
+    let grid = cached.unwrap_or_else(|| NavGrid::new(size));
+    if scene.is_none() {
+        check_hydra_starts(&grid, &config);
+    }
+
+    let size = Extent3d {
+        width: grid.size.x,
+        height: grid.size.y,
+        depth_or_array_layers: 1,
+    };
+    let image = Image::new(
+        size,
+        TextureDimension::D2,
+        grid.blocked.clone(),
+        NAV_GRID_FORMAT,
+    );
+    let terrain = Image::new(
+        size,
+        TextureDimension::D2,
+        grid.terrain_texels(),
+        TERRAIN_FORMAT,
+    );
+    commands.insert_resource(NavGridImage {
+        image: images.add(image),
+        terrain: images.add(terrain),
+    });
+    commands.insert_resource(grid);
+    commands.insert_resource(CityBake { scene, cache_key });
+}
+
+fn bake_nav_grid(
+    asset_server: Res<AssetServer>,
+    mut bake: ResMut<CityBake>,
+    scenes: Res<Assets<Scene>>,
+    meshes: Res<Assets<Mesh>>,
+    mut grid: ResMut<NavGrid>,
+    nav_image: Res<NavGridImage>,
+    mut images: ResMut<Assets<Image>>,
+    config: Res<SimulationConfig>,
+) {
+    let Some(handle) = &bake.scene else {
+        return;
+    };
+    if !asset_server.is_loaded_with_dependencies(handle) {
+        return;
+    }
+    let Some(scene) = scenes.get(handle) else {
+        return;
+    };
+
+    grid.bake_scene(scene, &meshes, city_transform());
+    if let Some(image) = images.get_mut(&nav_image.image) {
+        image.data = grid.blocked.clone();
+    }
+    if let Some(image) = images.get_mut(&nav_image.terrain) {
+        image.data = grid.terrain_texels();
+    }
+    info!(
+        "Baked the nav grid, {} cells blocked, water at {}",
+        grid.blocked_count(),
+        grid.water_level
+    );
+    check_hydra_starts(&grid, &config);
+
+    if let Some(key) = bake.cache_key {
+        if let Err(e) = grid.write_cache(Path::new(NAV_GRID_CACHE_PATH), key) {
+            error!("Failed to cache the nav grid to {NAV_GRID_CACHE_PATH}: {e}");
+        }
+    }
+    bake.scene = None;
+}
+
+fn follow_water_level(grid: Res<NavGrid>, mut planes: Query<&mut Transform, With<WaterPlane>>) {
+    if !grid.is_changed() {
+        return;
+    }
+    for mut transform in &mut planes {
+        // A little over the city's own water so the two don't fight
+        transform.translation.y = grid.water_level + 0.1;
+    }
+}
+
+/// Geese can't spawn in blocked cells, so a hydra starting in one would be left without any
+fn check_hydra_starts(grid: &NavGrid, config: &SimulationConfig) {
+    let size = uvec2(config.units_width, config.units_height).as_vec2();
+    for team in 1..=config.teams {
+        let start = (hydra_start(team) * size).as_ivec2();
+        if grid.is_blocked(start) {
+            warn!("Team {team}'s hydra starts at {start} inside a building");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4 cell high box over cells 3 to 6 and a water plane at 1
+    fn box_grid() -> NavGrid {
+        let mut meshes = Assets::<Mesh>::default();
+        let mut world = World::new();
+        world.spawn((
+            meshes.add(Mesh::from(shape::Box::new(4.0, 4.0, 4.0))),
+            Transform::from_xyz(4.5, 2.0, 4.5),
+        ));
+        world.spawn((
+            meshes.add(Mesh::from(shape::Plane::from_size(32.0))),
+            Transform::from_xyz(8.0, 1.0, 8.0),
+            Name::new(WATER_NODE_NAME),
+        ));
+        let mut grid = NavGrid::new(uvec2(16, 16));
+        grid.bake_scene(&Scene::new(world), &meshes, Transform::IDENTITY);
+        grid
+    }
+
+    #[test]
+    fn bake_box() {
+        let grid = box_grid();
+        assert_eq!(grid.water_level, 1.0);
+        assert_eq!(grid.blocked_count(), 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                let inside = (3..=6).contains(&x) && (3..=6).contains(&y);
+                assert_eq!(grid.is_blocked(ivec2(x, y)), inside, "cell {x} {y}");
+            }
+        }
+        assert_eq!(grid.surface(ivec2(4, 4)), 4.0);
+        assert_eq!(grid.water_depth(ivec2(4, 4)), 0.0);
+        assert_eq!(grid.surface(ivec2(10, 10)), 1.0);
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let grid = box_grid();
+        let path = std::env::temp_dir().join("gaggle_nav_grid_round_trip.navgrid");
+        grid.write_cache(&path, 42).unwrap();
+        let cached = NavGrid::read_cache(&path, grid.size, 42).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(cached.size, grid.size);
+        assert_eq!(cached.blocked, grid.blocked);
+        assert_eq!(cached.ground, grid.ground);
+        assert_eq!(cached.water_level, grid.water_level);
+    }
+
+    #[test]
+    fn stale_cache() {
+        let grid = box_grid();
+        let path = std::env::temp_dir().join("gaggle_nav_grid_stale.navgrid");
+        grid.write_cache(&path, 42).unwrap();
+        let stale_key = NavGrid::read_cache(&path, grid.size, 43);
+        let stale_size = NavGrid::read_cache(&path, uvec2(32, 32), 42);
+        let _ = std::fs::remove_file(&path);
+        assert!(stale_key.is_none());
+        assert!(stale_size.is_none());
+    }
+}
@@ -9,8 +9,8 @@ use bevy::{
         },
         render_resource::{
             BindGroupEntries, BindGroupLayout, BindGroupLayoutDescriptor, CachedRenderPipelineId,
-            PipelineCache, RenderPassDescriptor, Sampler, SamplerDescriptor, TextureFormat,
-            TextureViewDimension,
+            PipelineCache, RenderPassDescriptor, Sampler, SamplerDescriptor, ShaderType,
+            TextureFormat, TextureViewDimension,
         },
         renderer::{RenderContext, RenderDevice},
         view::{ViewTarget, ViewUniformOffset},
@@ -20,17 +20,14 @@ use bevy::{
 use bevy_ridiculous_ssgi::bind_group_utils::{fsampler_layout_entry, ftexture_layout_entry};
 
 use crate::{
+    balance::BalanceConfig,
     bind_group_utils::{
         basic_fullscreen_tri_pipeline, globals_binding, globals_layout_entry,
-        load_color_attachment, opaque_target, utexture_layout_entry, view_binding,
-        view_layout_entry,
-    },
-    minimap::{MinimapTextures, MINIMAP_SCALE},
-    shader_def_uint,
-    units::{
-        UnitsDataTextures, ATTACK_RADIUS, LARGE_UNITS_DATA_WIDTH, LARGE_UNITS_TEXTURE_HEIGHT,
-        LARGE_UNITS_TEXTURE_WIDTH, UNITS_DATA_HEIGHT, UNITS_DATA_WIDTH,
+        load_color_attachment, opaque_target, uniform_buffer, uniform_layout_entry,
+        utexture_layout_entry, view_binding, view_layout_entry,
     },
+    minimap::MinimapTextures,
+    units::{SimulationConfig, UnitsDataTextures},
 };
 
 pub struct PostProcessPlugin;
@@ -92,6 +89,7 @@ impl ViewNode for PostProcessNode {
         let post_process_pipeline = world.resource::<PostProcessPipeline>();
         let minimap_textures = world.resource::<MinimapTextures>();
         let unit_data_texture = world.resource::<UnitsDataTextures>();
+        let balance = world.resource::<BalanceConfig>();
 
         let pipeline_cache = world.resource::<PipelineCache>();
 
@@ -102,6 +100,8 @@ impl ViewNode for PostProcessNode {
 
         let post_process = view_target.post_process_write();
 
+        let balance_uniform = uniform_buffer(*balance, render_context, "Balance Uniform");
+
         let bind_group = render_context.render_device().create_bind_group(
             "post_process_bind_group",
             &post_process_pipeline.layout,
@@ -113,6 +113,7 @@ impl ViewNode for PostProcessNode {
                 (103, &minimap_textures.minimap_tex.default_view),
                 (104, &minimap_textures.minimap_sm3_tex.default_view),
                 (105, &unit_data_texture.large_unit_b.default_view),
+                (106, balance_uniform.as_entire_binding()),
             )),
         );
 
@@ -139,17 +140,7 @@ struct PostProcessPipeline {
 
 impl FromWorld for PostProcessPipeline {
     fn from_world(world: &mut World) -> Self {
-        let mut shader_defs = Vec::new();
-
-        shader_defs.extend_from_slice(&[
-            shader_def_uint!(MINIMAP_SCALE),
-            shader_def_uint!(UNITS_DATA_WIDTH),
-            shader_def_uint!(UNITS_DATA_HEIGHT),
-            shader_def_uint!(LARGE_UNITS_DATA_WIDTH),
-            shader_def_uint!(LARGE_UNITS_TEXTURE_WIDTH),
-            shader_def_uint!(LARGE_UNITS_TEXTURE_HEIGHT),
-            shader_def_uint!(ATTACK_RADIUS),
-        ]);
+        let shader_defs = world.resource::<SimulationConfig>().shader_defs();
 
         let render_device = world.resource::<RenderDevice>();
 
@@ -163,6 +154,7 @@ impl FromWorld for PostProcessPipeline {
                 utexture_layout_entry(103, TextureViewDimension::D2), // Minimap
                 utexture_layout_entry(104, TextureViewDimension::D2), // Minimap sm
                 utexture_layout_entry(105, TextureViewDimension::D2), // Large Unit Data
+                uniform_layout_entry(106, BalanceConfig::min_size()),
             ],
         });
 
@@ -0,0 +1,550 @@
+use async_channel::{Receiver, Sender};
+use bevy::{
+    core::FrameCount,
+    core_pipeline::core_3d,
+    ecs::query::QueryItem,
+    math::*,
+    prelude::*,
+    render::{
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageCopyTexture,
+            ImageDataLayout, MapMode, Origin3d, TextureAspect,
+        },
+        renderer::{RenderContext, RenderDevice},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    minimap::{minimap_alive, minimap_died, MinimapNode, MinimapTextures},
+    units::{
+        unpack_4x8_, LargeUnit, SimulationConfig, SimulationState, UnitsDataTextures, UnitsPass,
+        CONTROL_GROUPS, LARGE_UNITS_TEXTURE_HEIGHT, MAX_TEAMS, PLAYER_TEAM,
+    },
+};
+
+// Staging buffers in flight. Readback results arrive a frame or two late.
+const READBACK_SLOTS: usize = 3;
+const TEXEL_SIZE: u32 = 16; // Rgba32Uint
+
+const fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    (width * TEXEL_SIZE).div_ceil(align) * align
+}
+
+/// Where the large unit texture, `minimap_sm3_tex`, `minimap_sm_tex` and `groups_sm3_tex` end up
+/// in the readback buffer.
+#[derive(Clone, Copy)]
+struct ReadbackLayout {
+    large_units_data_width: u32,
+    large_units_width: u32,
+    large_units_bytes_per_row: u32,
+    minimap_size: UVec2,
+    minimap_bytes_per_row: u32,
+    minimap_offset: u64,
+    density_size: UVec2,
+    density_bytes_per_row: u32,
+    density_offset: u64,
+    /// Units per density texel along each axis
+    density_cell_size: u32,
+    /// `groups_sm3_tex` is the same size as `minimap_sm3_tex`
+    groups_offset: u64,
+    size: u64,
+}
+
+impl ReadbackLayout {
+    fn new(config: &SimulationConfig) -> Self {
+        let large_units_width = config.large_units_texture_width();
+        let large_units_bytes_per_row = padded_bytes_per_row(large_units_width);
+        // minimap_tex is downsampled 3 more times
+        let minimap_size = config.minimap_size(3);
+        let minimap_bytes_per_row = padded_bytes_per_row(minimap_size.x);
+        let minimap_offset = (large_units_bytes_per_row * LARGE_UNITS_TEXTURE_HEIGHT) as u64;
+        let density_size = config.minimap_size(1);
+        let density_bytes_per_row = padded_bytes_per_row(density_size.x);
+        let density_offset = minimap_offset + (minimap_bytes_per_row * minimap_size.y) as u64;
+        let groups_offset = density_offset + (density_bytes_per_row * density_size.y) as u64;
+        Self {
+            large_units_data_width: config.large_units_width,
+            large_units_width,
+            large_units_bytes_per_row,
+            minimap_size,
+            minimap_bytes_per_row,
+            minimap_offset,
+            density_size,
+            density_bytes_per_row,
+            density_offset,
+            density_cell_size: config.units_width / density_size.x,
+            groups_offset,
+            size: groups_offset + (minimap_bytes_per_row * minimap_size.y) as u64,
+        }
+    }
+}
+
+/// The state of the GPU simulation as of `frame`, decoded from the large unit texture and the
+/// minimap textures.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct SimulationSnapshot {
+    /// False until the first readback arrives.
+    pub received: bool,
+    /// `FrameCount` of the frame the data was copied on.
+    pub frame: u32,
+    /// `SimulationState::match_index` of the match the data belongs to.
+    pub match_index: u32,
+    /// Indexed by team - 1, teams that aren't playing stay empty
+    pub teams: [TeamSnapshot; MAX_TEAMS as usize],
+    pub density: DensityMap,
+    /// Player geese per control group, indexed by group - 1
+    pub control_groups: [u32; CONTROL_GROUPS as usize],
+    /// Player geese selected
+    pub selected: u32,
+    /// Living player hydras and their slots in the large unit texture
+    pub player_hydras: Vec<(u32, LargeUnit)>,
+}
+
+/// Geese alive per cell, read from `minimap_sm_tex`.
+#[derive(Clone, Debug, Default)]
+pub struct DensityMap {
+    /// Cells along each axis
+    pub size: UVec2,
+    /// Units per cell along each axis
+    pub cell_size: u32,
+    /// Geese alive, indexed by team - 1
+    pub cells: Vec<UVec4>,
+}
+
+impl DensityMap {
+    pub fn get(&self, cell: UVec2) -> UVec4 {
+        if cell.x >= self.size.x || cell.y >= self.size.y {
+            return UVec4::ZERO;
+        }
+        self.cells[(cell.y * self.size.x + cell.x) as usize]
+    }
+
+    /// Cell containing `pos` in unit texture coordinates
+    pub fn cell(&self, pos: Vec2) -> UVec2 {
+        (pos.max(Vec2::ZERO) / self.cell_size as f32)
+            .as_uvec2()
+            .min(self.size.saturating_sub(UVec2::ONE))
+    }
+
+    /// Center of `cell` in unit texture coordinates
+    pub fn cell_center(&self, cell: UVec2) -> Vec2 {
+        (cell.as_vec2() + 0.5) * self.cell_size as f32
+    }
+
+    /// Sum of the cells whose centers are within `radius` of `pos`
+    pub fn sum_around(&self, pos: Vec2, radius: f32) -> UVec4 {
+        self.iter()
+            .filter(|(cell, _)| self.cell_center(*cell).distance(pos) <= radius)
+            .map(|(_, data)| data)
+            .sum()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (UVec2, UVec4)> + '_ {
+        let width = self.size.x.max(1);
+        self.cells
+            .iter()
+            .enumerate()
+            .map(move |(i, data)| (uvec2(i as u32 % width, i as u32 / width), *data))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TeamSnapshot {
+    /// The team's first living hydra, or the first slot once they are all dead
+    pub hydra: LargeUnit,
+    /// Hydras alive
+    pub hydras: u32,
+    /// Geese alive
+    pub alive: u32,
+    /// Geese that died on the readback frame
+    pub died: u32,
+    /// Geese lost over the whole match
+    pub lost: u32,
+    pub credits: u32,
+    /// x: movement, y: attack, z: spawn
+    pub upgrades: UVec3,
+    /// Weights of each `UNIT_TYPE_*` the team picked, `None` spawns `BalanceConfig::spawn_weights`
+    pub spawn_mix: Option<UVec3>,
+}
+
+impl SimulationSnapshot {
+    /// `team` is 1 to `MAX_TEAMS`
+    pub fn team(&self, team: u32) -> &TeamSnapshot {
+        &self.teams[team as usize - 1]
+    }
+
+    /// `bytes` is a mapped readback buffer, see `ReadbackNode` for the layout.
+    pub fn decode(config: &SimulationConfig, frame: u32, match_index: u32, bytes: &[u8]) -> Self {
+        let layout = ReadbackLayout::new(config);
+        let texel = |offset: u64, row_bytes: u32, coord: UVec2| {
+            let start = offset as usize + (coord.y * row_bytes + coord.x * TEXEL_SIZE) as usize;
+            let data: &[u32] = bytemuck::cast_slice(&bytes[start..start + TEXEL_SIZE as usize]);
+            uvec4(data[0], data[1], data[2], data[3])
+        };
+
+        let mut alive = UVec4::ZERO;
+        let mut died = UVec4::ZERO;
+        // Two 16 bit counts per channel, see minimap_groups.wgsl
+        let mut groups = [0; CONTROL_GROUPS as usize + 1];
+        for y in 0..layout.minimap_size.y {
+            for x in 0..layout.minimap_size.x {
+                let data = texel(
+                    layout.minimap_offset,
+                    layout.minimap_bytes_per_row,
+                    uvec2(x, y),
+                );
+                alive += minimap_alive(data);
+                died += minimap_died(data);
+                let data = texel(
+                    layout.groups_offset,
+                    layout.minimap_bytes_per_row,
+                    uvec2(x, y),
+                );
+                for (i, count) in groups.iter_mut().enumerate() {
+                    *count += (data[i / 2] >> (16 * (i % 2))) & 0xFFFF;
+                }
+            }
+        }
+        let (control_groups, selected) = groups.split_at(CONTROL_GROUPS as usize);
+
+        let mut teams = [TeamSnapshot::default(); MAX_TEAMS as usize];
+        let mut player_hydras = Vec::new();
+        for (i, team) in teams.iter_mut().enumerate() {
+            let row = i as u32;
+            let large = |x: u32| texel(0, layout.large_units_bytes_per_row, uvec2(x, row));
+            let tracker = large(layout.large_units_data_width);
+            let upgrades = large(layout.large_units_data_width + 1);
+            let hydras: Vec<_> = (0..layout.large_units_data_width)
+                .map(|x| (x, LargeUnit::unpack(large(x), uvec2(x, row))))
+                .filter(|(_, hydra)| hydra.health > 0)
+                .collect();
+            if row + 1 == PLAYER_TEAM {
+                player_hydras = hydras.clone();
+            }
+            *team = TeamSnapshot {
+                hydra: hydras
+                    .first()
+                    .map_or_else(|| LargeUnit::unpack(large(0), uvec2(0, row)), |h| h.1),
+                hydras: hydras.len() as u32,
+                alive: alive[i],
+                died: died[i],
+                lost: tracker.x,
+                credits: tracker.y,
+                upgrades: upgrades.xyz(),
+                spawn_mix: Some(unpack_4x8_(upgrades.w))
+                    .filter(|mix| mix.w != 0)
+                    .map(|mix| mix.xyz()),
+            };
+        }
+
+        let mut density = DensityMap {
+            size: layout.density_size,
+            cell_size: layout.density_cell_size,
+            cells: Vec::with_capacity((layout.density_size.x * layout.density_size.y) as usize),
+        };
+        for y in 0..layout.density_size.y {
+            for x in 0..layout.density_size.x {
+                density.cells.push(minimap_alive(texel(
+                    layout.density_offset,
+                    layout.density_bytes_per_row,
+                    uvec2(x, y),
+                )));
+            }
+        }
+
+        Self {
+            received: true,
+            frame,
+            match_index,
+            teams,
+            density,
+            control_groups: control_groups.try_into().unwrap(),
+            selected: selected[0],
+            player_hydras,
+        }
+    }
+}
+
+pub struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        let (snapshot_sender, snapshot_receiver) = async_channel::unbounded();
+
+        app.init_resource::<SimulationSnapshot>()
+            .insert_resource(SnapshotReceiver(snapshot_receiver))
+            .add_systems(PreUpdate, receive_snapshot);
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(SnapshotSender(snapshot_sender))
+            .add_systems(
+                Render,
+                (
+                    prepare_readback.in_set(RenderSet::PrepareResources),
+                    map_readback.in_set(RenderSet::Cleanup),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<ReadbackNode>>(
+                core_3d::graph::NAME,
+                ReadbackNode::NAME,
+            )
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    MinimapNode::NAME,
+                    ReadbackNode::NAME,
+                    core_3d::graph::node::START_MAIN_PASS,
+                ],
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<ReadbackBuffers>();
+    }
+}
+
+#[derive(Resource)]
+struct SnapshotReceiver(Receiver<SimulationSnapshot>);
+
+#[derive(Resource)]
+struct SnapshotSender(Sender<SimulationSnapshot>);
+
+fn receive_snapshot(receiver: Res<SnapshotReceiver>, mut snapshot: ResMut<SimulationSnapshot>) {
+    while let Ok(received) = receiver.0.try_recv() {
+        *snapshot = received;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SlotState {
+    Free,
+    Copying,
+    Mapping,
+}
+
+struct ReadbackSlot {
+    buffer: Buffer,
+    state: SlotState,
+    frame: u32,
+    match_index: u32,
+}
+
+#[derive(Resource)]
+struct ReadbackBuffers {
+    layout: ReadbackLayout,
+    slots: Vec<ReadbackSlot>,
+    /// Slot the node copies into this frame
+    current: Option<usize>,
+    mapped_sender: Sender<(usize, bool)>,
+    mapped_receiver: Receiver<(usize, bool)>,
+}
+
+impl FromWorld for ReadbackBuffers {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = ReadbackLayout::new(world.resource::<SimulationConfig>());
+        let slots = (0..READBACK_SLOTS)
+            .map(|_| ReadbackSlot {
+                buffer: render_device.create_buffer(&BufferDescriptor {
+                    label: Some("simulation_readback_buffer"),
+                    size: layout.size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                }),
+                state: SlotState::Free,
+                frame: 0,
+                match_index: 0,
+            })
+            .collect();
+        let (mapped_sender, mapped_receiver) = async_channel::unbounded();
+        Self {
+            layout,
+            slots,
+            current: None,
+            mapped_sender,
+            mapped_receiver,
+        }
+    }
+}
+
+fn prepare_readback(
+    mut buffers: ResMut<ReadbackBuffers>,
+    render_device: Res<RenderDevice>,
+    sender: Res<SnapshotSender>,
+    frame_count: Res<FrameCount>,
+    simulation: Res<SimulationState>,
+    config: Res<SimulationConfig>,
+) {
+    render_device.poll(wgpu::Maintain::Poll);
+
+    while let Ok((index, success)) = buffers.mapped_receiver.try_recv() {
+        let slot = &mut buffers.slots[index];
+        if success {
+            let snapshot = {
+                let bytes = slot.buffer.slice(..).get_mapped_range();
+                SimulationSnapshot::decode(&config, slot.frame, slot.match_index, &bytes)
+            };
+            let _ = sender.0.try_send(snapshot);
+            slot.buffer.unmap();
+        }
+        slot.state = SlotState::Free;
+    }
+
+    // If every slot is still in flight skip the readback this frame
+    buffers.current = buffers
+        .slots
+        .iter()
+        .position(|slot| slot.state == SlotState::Free);
+    if let Some(index) = buffers.current {
+        let slot = &mut buffers.slots[index];
+        slot.state = SlotState::Copying;
+        slot.frame = frame_count.0;
+        slot.match_index = simulation.match_index;
+    }
+}
+
+fn map_readback(mut buffers: ResMut<ReadbackBuffers>) {
+    let Some(index) = buffers.current.take() else {
+        return;
+    };
+    let sender = buffers.mapped_sender.clone();
+    let slot = &mut buffers.slots[index];
+    slot.state = SlotState::Mapping;
+    slot.buffer
+        .slice(..)
+        .map_async(MapMode::Read, move |result| {
+            let _ = sender.try_send((index, result.is_ok()));
+        });
+}
+
+#[derive(Default)]
+pub struct ReadbackNode;
+impl ReadbackNode {
+    pub const NAME: &'static str = "simulation_readback";
+}
+
+impl ViewNode for ReadbackNode {
+    type ViewQuery = &'static UnitsPass;
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        _units_pass: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let buffers = world.resource::<ReadbackBuffers>();
+        let Some(index) = buffers.current else {
+            return Ok(());
+        };
+        let buffer = &buffers.slots[index].buffer;
+        let layout = buffers.layout;
+        let unit_data_texture = world.resource::<UnitsDataTextures>();
+        let minimap_textures = world.resource::<MinimapTextures>();
+
+        let encoder = render_context.command_encoder();
+
+        // large_unit_b was written by the large unit update this frame
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &unit_data_texture.large_unit_b.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(layout.large_units_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: layout.large_units_width,
+                height: LARGE_UNITS_TEXTURE_HEIGHT,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &minimap_textures.minimap_sm3_tex.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: layout.minimap_offset,
+                    bytes_per_row: Some(layout.minimap_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: layout.minimap_size.x,
+                height: layout.minimap_size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &minimap_textures.minimap_sm_tex.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: layout.density_offset,
+                    bytes_per_row: Some(layout.density_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: layout.density_size.x,
+                height: layout.density_size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &minimap_textures.groups_sm3_tex.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: layout.groups_offset,
+                    bytes_per_row: Some(layout.minimap_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: layout.minimap_size.x,
+                height: layout.minimap_size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,777 @@
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use async_channel::{Receiver, Sender};
+use bevy::{
+    core_pipeline::core_3d,
+    ecs::query::QueryItem,
+    math::*,
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_graph::{
+            NodeRunError, RenderGraphApp, RenderGraphContext, ViewNode, ViewNodeRunner,
+        },
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageCopyTexture,
+            ImageDataLayout, MapMode, Origin3d, Texture, TextureAspect,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    game_state::GameState,
+    units::{
+        SimulationConfig, SimulationState, UnitsDataTextures, UnitsNode, UnitsPass, ATTACK_RADIUS,
+        LARGE_UNITS_TEXTURE_HEIGHT, MAX_TEAMS,
+    },
+};
+
+pub const SAVE_MAGIC: [u8; 4] = *b"GGLS";
+pub const SAVE_VERSION: u32 = 5;
+pub const QUICKSAVE_PATH: &str = "quicksave.gaggle";
+
+const UNITS_TEXEL_SIZE: u32 = 16; // Rgba32Uint
+const ATTACK_TEXEL_SIZE: u32 = 4; // Rgba8Uint
+const LARGE_UNITS_TEXEL_SIZE: u32 = 16; // Rgba32Uint
+
+// 10 fields and 3 upgrades per team
+const HEADER_WORDS: usize = 10 + 3 * MAX_TEAMS as usize;
+
+/// Size of one of the textures stored in a save.
+#[derive(Clone, Copy)]
+struct SavedTexture {
+    width: u32,
+    height: u32,
+    texel_size: u32,
+}
+
+impl SavedTexture {
+    /// Units, attack map and large units, in the order they are stored.
+    fn all(config: &SimulationConfig) -> [SavedTexture; 3] {
+        [
+            SavedTexture {
+                width: config.units_width,
+                height: config.units_height,
+                texel_size: UNITS_TEXEL_SIZE,
+            },
+            SavedTexture {
+                width: config.units_width,
+                height: config.units_height,
+                texel_size: ATTACK_TEXEL_SIZE,
+            },
+            SavedTexture {
+                width: config.large_units_texture_width(),
+                height: LARGE_UNITS_TEXTURE_HEIGHT,
+                texel_size: LARGE_UNITS_TEXEL_SIZE,
+            },
+        ]
+    }
+
+    fn bytes_per_row(&self) -> u32 {
+        self.width * self.texel_size
+    }
+
+    fn padded_bytes_per_row(&self) -> u32 {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        self.bytes_per_row().div_ceil(align) * align
+    }
+
+    fn len(&self) -> usize {
+        (self.bytes_per_row() * self.height) as usize
+    }
+}
+
+/// Save `SimulationState` and the unit textures to a file.
+#[derive(Event, Clone, Debug)]
+pub struct SaveMatch(pub PathBuf);
+
+/// Replace the running match with one from a file written by `SaveMatch`.
+#[derive(Event, Clone, Debug)]
+pub struct LoadMatch(pub PathBuf);
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(std::io::Error),
+    Unsupported,
+    BadMagic,
+    Version(u32),
+    /// The save was made with different simulation constants.
+    Mismatch(&'static str, u32, u32),
+    Truncated,
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(e) => write!(f, "{e}"),
+            SaveError::Unsupported => write!(f, "saving is not supported on this platform"),
+            SaveError::BadMagic => write!(f, "not a match save"),
+            SaveError::Version(v) => write!(f, "unsupported save version {v}"),
+            SaveError::Mismatch(name, saved, current) => {
+                write!(
+                    f,
+                    "{name} is {saved} in the save but {current} in this build"
+                )
+            }
+            SaveError::Truncated => write!(f, "save file is truncated"),
+        }
+    }
+}
+
+impl From<std::io::Error> for SaveError {
+    fn from(e: std::io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SaveHeader {
+    pub version: u32,
+    pub units_width: u32,
+    pub units_height: u32,
+    pub attack_radius: u32,
+    pub minimap_scale: u32,
+    pub large_units_width: u32,
+    pub large_units_height: u32,
+    pub teams: u32,
+    /// `SimulationState::frame` the textures were copied on.
+    pub frame: u32,
+    /// `SimulationState::seed` of the match
+    pub seed: u32,
+    /// Per team, x: movement, y: attack, z: spawn
+    pub upgrades: [UVec3; MAX_TEAMS as usize],
+}
+
+impl SaveHeader {
+    fn current(
+        config: &SimulationConfig,
+        frame: u32,
+        seed: u32,
+        upgrades: [UVec3; MAX_TEAMS as usize],
+    ) -> Self {
+        Self {
+            version: SAVE_VERSION,
+            units_width: config.units_width,
+            units_height: config.units_height,
+            attack_radius: ATTACK_RADIUS,
+            minimap_scale: config.minimap_scale,
+            large_units_width: config.large_units_texture_width(),
+            large_units_height: LARGE_UNITS_TEXTURE_HEIGHT,
+            teams: config.teams,
+            frame,
+            seed,
+            upgrades,
+        }
+    }
+
+    fn words(&self) -> [u32; HEADER_WORDS] {
+        let mut words = [0; HEADER_WORDS];
+        words[..10].copy_from_slice(&[
+            self.version,
+            self.units_width,
+            self.units_height,
+            self.attack_radius,
+            self.minimap_scale,
+            self.large_units_width,
+            self.large_units_height,
+            self.teams,
+            self.frame,
+            self.seed,
+        ]);
+        for (words, upgrades) in words[10..].chunks_exact_mut(3).zip(self.upgrades) {
+            words.copy_from_slice(&upgrades.to_array());
+        }
+        words
+    }
+
+    fn from_words(words: [u32; HEADER_WORDS]) -> Self {
+        let mut upgrades = [UVec3::ZERO; MAX_TEAMS as usize];
+        for (upgrades, words) in upgrades.iter_mut().zip(words[10..].chunks_exact(3)) {
+            *upgrades = UVec3::from_slice(words);
+        }
+        Self {
+            version: words[0],
+            units_width: words[1],
+            units_height: words[2],
+            attack_radius: words[3],
+            minimap_scale: words[4],
+            large_units_width: words[5],
+            large_units_height: words[6],
+            teams: words[7],
+            frame: words[8],
+            seed: words[9],
+            upgrades,
+        }
+    }
+
+    fn check(&self, config: &SimulationConfig) -> Result<(), SaveError> {
+        if self.version != SAVE_VERSION {
+            return Err(SaveError::Version(self.version));
+        }
+        let current = Self::current(config, self.frame, self.seed, self.upgrades);
+        for (name, saved, current) in [
+            ("units width", self.units_width, current.units_width),
+            ("units height", self.units_height, current.units_height),
+            ("ATTACK_RADIUS", self.attack_radius, current.attack_radius),
+            ("MINIMAP_SCALE", self.minimap_scale, current.minimap_scale),
+            (
+                "large units width",
+                self.large_units_width,
+                current.large_units_width,
+            ),
+            (
+                "large units height",
+                self.large_units_height,
+                current.large_units_height,
+            ),
+            ("teams", self.teams, current.teams),
+        ] {
+            if saved != current {
+                return Err(SaveError::Mismatch(name, saved, current));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed to resume a match. Texture data is tightly packed rows.
+#[derive(Clone, Debug)]
+pub struct MatchSave {
+    pub header: SaveHeader,
+    /// `UnitsDataTextures::a`
+    pub units: Vec<u8>,
+    /// The attack map written on `header.frame`
+    pub attack: Vec<u8>,
+    /// The large unit texture written on `header.frame`
+    pub large_units: Vec<u8>,
+}
+
+impl MatchSave {
+    pub fn new(
+        config: &SimulationConfig,
+        frame: u32,
+        seed: u32,
+        units: Vec<u8>,
+        attack: Vec<u8>,
+        large_units: Vec<u8>,
+    ) -> Self {
+        let upgrades = std::array::from_fn(|row| {
+            let row = row as u32;
+            let start = ((row * config.large_units_texture_width() + config.large_units_width + 1)
+                * LARGE_UNITS_TEXEL_SIZE) as usize;
+            let word = |i: usize| {
+                u32::from_le_bytes(
+                    large_units[start + i * 4..start + i * 4 + 4]
+                        .try_into()
+                        .unwrap(),
+                )
+            };
+            uvec3(word(0), word(1), word(2))
+        });
+        Self {
+            header: SaveHeader::current(config, frame, seed, upgrades),
+            units,
+            attack,
+            large_units,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            4 + HEADER_WORDS * 4 + self.units.len() + self.attack.len() + self.large_units.len(),
+        );
+        bytes.extend_from_slice(&SAVE_MAGIC);
+        for word in self.header.words() {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.units);
+        bytes.extend_from_slice(&self.attack);
+        bytes.extend_from_slice(&self.large_units);
+        bytes
+    }
+
+    /// Fails if the save was made with a different `SimulationConfig`.
+    pub fn from_bytes(config: &SimulationConfig, bytes: &[u8]) -> Result<Self, SaveError> {
+        let mut rest = bytes;
+        let mut take = |len: usize| {
+            if rest.len() < len {
+                return Err(SaveError::Truncated);
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            Ok(taken)
+        };
+
+        if take(4)? != SAVE_MAGIC {
+            return Err(SaveError::BadMagic);
+        }
+        let mut words = [0u32; HEADER_WORDS];
+        for word in words.iter_mut() {
+            *word = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        }
+        let header = SaveHeader::from_words(words);
+        header.check(config)?;
+
+        let [units, attack, large_units] = SavedTexture::all(config);
+        Ok(Self {
+            header,
+            units: take(units.len())?.to_vec(),
+            attack: take(attack.len())?.to_vec(),
+            large_units: take(large_units.len())?.to_vec(),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn write(&self, path: &std::path::Path) -> Result<(), SaveError> {
+        Ok(std::fs::write(path, self.to_bytes())?)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read(config: &SimulationConfig, path: &std::path::Path) -> Result<Self, SaveError> {
+        Self::from_bytes(config, &std::fs::read(path)?)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn write(&self, _path: &std::path::Path) -> Result<(), SaveError> {
+        Err(SaveError::Unsupported)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn read(_config: &SimulationConfig, _path: &std::path::Path) -> Result<Self, SaveError> {
+        Err(SaveError::Unsupported)
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        let (save_sender, save_receiver) = async_channel::unbounded();
+
+        app.add_event::<SaveMatch>()
+            .add_event::<LoadMatch>()
+            .init_resource::<SaveRequest>()
+            .init_resource::<PendingLoad>()
+            .insert_resource(SaveReceiver(save_receiver))
+            .add_plugins((
+                ExtractResourcePlugin::<SaveRequest>::default(),
+                ExtractResourcePlugin::<PendingLoad>::default(),
+            ))
+            .add_systems(PreUpdate, (clear_requests, receive_save))
+            .add_systems(
+                Update,
+                (
+                    save_hotkeys
+                        .run_if(in_state(GameState::Playing).or_else(in_state(GameState::Paused))),
+                    request_save,
+                    load_match,
+                )
+                    .chain(),
+            );
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .insert_resource(SaveSender(save_sender))
+            .add_systems(
+                Render,
+                (
+                    prepare_save.in_set(RenderSet::PrepareResources),
+                    upload_load.in_set(RenderSet::PrepareBindGroups),
+                    map_save.in_set(RenderSet::Cleanup),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<SaveNode>>(core_3d::graph::NAME, SaveNode::NAME)
+            .add_render_graph_edges(
+                core_3d::graph::NAME,
+                &[
+                    UnitsNode::NAME,
+                    SaveNode::NAME,
+                    core_3d::graph::node::START_MAIN_PASS,
+                ],
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<SaveBuffers>();
+    }
+}
+
+/// Set for the frame the unit textures should be copied for a save.
+#[derive(Resource, ExtractResource, Clone, Default)]
+struct SaveRequest(Option<PathBuf>);
+
+/// Set for the frame a loaded save is uploaded on.
+#[derive(Resource, ExtractResource, Clone, Default)]
+struct PendingLoad(Option<Arc<MatchSave>>);
+
+#[derive(Resource)]
+struct SaveReceiver(Receiver<(PathBuf, MatchSave)>);
+
+#[derive(Resource)]
+struct SaveSender(Sender<(PathBuf, MatchSave)>);
+
+fn clear_requests(mut save_request: ResMut<SaveRequest>, mut pending_load: ResMut<PendingLoad>) {
+    save_request.0 = None;
+    pending_load.0 = None;
+}
+
+fn save_hotkeys(
+    key_input: Res<Input<KeyCode>>,
+    mut save_events: EventWriter<SaveMatch>,
+    mut load_events: EventWriter<LoadMatch>,
+) {
+    if key_input.just_pressed(KeyCode::F5) {
+        save_events.send(SaveMatch(QUICKSAVE_PATH.into()));
+    }
+    if key_input.just_pressed(KeyCode::F9) {
+        load_events.send(LoadMatch(QUICKSAVE_PATH.into()));
+    }
+}
+
+fn request_save(mut events: EventReader<SaveMatch>, mut save_request: ResMut<SaveRequest>) {
+    if let Some(SaveMatch(path)) = events.read().last() {
+        save_request.0 = Some(path.clone());
+    }
+}
+
+fn receive_save(receiver: Res<SaveReceiver>) {
+    while let Ok((path, save)) = receiver.0.try_recv() {
+        match save.write(&path) {
+            Ok(()) => info!("Saved match to {}", path.display()),
+            Err(e) => error!("Failed to save match to {}: {e}", path.display()),
+        }
+    }
+}
+
+fn load_match(
+    mut events: EventReader<LoadMatch>,
+    mut simulation: ResMut<SimulationState>,
+    mut pending_load: ResMut<PendingLoad>,
+    config: Res<SimulationConfig>,
+) {
+    let Some(LoadMatch(path)) = events.read().last() else {
+        return;
+    };
+    match MatchSave::read(&config, path) {
+        Ok(save) => {
+            // advance_simulation moves this on to the frame after the save was made
+            simulation.frame = save.header.frame;
+            simulation.seed = save.header.seed;
+            simulation.match_index = simulation.match_index.wrapping_add(1);
+            pending_load.0 = Some(Arc::new(save));
+            info!("Loaded match from {}", path.display());
+        }
+        Err(e) => error!("Failed to load match from {}: {e}", path.display()),
+    }
+}
+
+// ---------------------------------------
+// Render world
+// ---------------------------------------
+
+/// The saved textures one after another in the staging buffer, with padded rows.
+fn buffer_offsets(config: &SimulationConfig) -> ([u64; 3], u64) {
+    let mut offsets = [0; 3];
+    let mut size = 0;
+    for (offset, texture) in offsets.iter_mut().zip(SavedTexture::all(config)) {
+        *offset = size;
+        size += (texture.padded_bytes_per_row() * texture.height) as u64;
+    }
+    (offsets, size)
+}
+
+struct SaveCopy {
+    id: u32,
+    path: PathBuf,
+    frame: u32,
+    seed: u32,
+    buffer: Buffer,
+}
+
+#[derive(Resource)]
+struct SaveBuffers {
+    /// Copy the node records this frame
+    current: Option<SaveCopy>,
+    /// Copies waiting on map_async
+    mapping: Vec<SaveCopy>,
+    next_id: u32,
+    mapped_sender: Sender<(u32, bool)>,
+    mapped_receiver: Receiver<(u32, bool)>,
+}
+
+impl FromWorld for SaveBuffers {
+    fn from_world(_world: &mut World) -> Self {
+        let (mapped_sender, mapped_receiver) = async_channel::unbounded();
+        Self {
+            current: None,
+            mapping: Vec::new(),
+            next_id: 0,
+            mapped_sender,
+            mapped_receiver,
+        }
+    }
+}
+
+fn prepare_save(
+    mut buffers: ResMut<SaveBuffers>,
+    render_device: Res<RenderDevice>,
+    sender: Res<SaveSender>,
+    save_request: Res<SaveRequest>,
+    simulation: Res<SimulationState>,
+    config: Res<SimulationConfig>,
+) {
+    let (offsets, buffer_size) = buffer_offsets(&config);
+
+    if !buffers.mapping.is_empty() {
+        render_device.poll(wgpu::Maintain::Poll);
+    }
+
+    while let Ok((id, success)) = buffers.mapped_receiver.try_recv() {
+        let Some(index) = buffers.mapping.iter().position(|copy| copy.id == id) else {
+            continue;
+        };
+        let copy = buffers.mapping.swap_remove(index);
+        if success {
+            let save = {
+                let bytes = copy.buffer.slice(..).get_mapped_range();
+                let [units, attack, large_units] = SavedTexture::all(&config)
+                    .into_iter()
+                    .zip(offsets)
+                    .map(|(texture, offset)| {
+                        let mut data = Vec::with_capacity(texture.len());
+                        for y in 0..texture.height {
+                            let start =
+                                offset as usize + (y * texture.padded_bytes_per_row()) as usize;
+                            data.extend_from_slice(
+                                &bytes[start..start + texture.bytes_per_row() as usize],
+                            );
+                        }
+                        data
+                    })
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .unwrap();
+                MatchSave::new(&config, copy.frame, copy.seed, units, attack, large_units)
+            };
+            copy.buffer.unmap();
+            let _ = sender.0.try_send((copy.path, save));
+        }
+    }
+
+    let id = buffers.next_id;
+    buffers.next_id = id.wrapping_add(1);
+    buffers.current = save_request.0.as_ref().map(|path| SaveCopy {
+        id,
+        path: path.clone(),
+        frame: simulation.frame,
+        seed: simulation.seed,
+        buffer: render_device.create_buffer(&BufferDescriptor {
+            label: Some("match_save_buffer"),
+            size: buffer_size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }),
+    });
+}
+
+fn map_save(mut buffers: ResMut<SaveBuffers>) {
+    let Some(copy) = buffers.current.take() else {
+        return;
+    };
+    let sender = buffers.mapped_sender.clone();
+    let id = copy.id;
+    copy.buffer
+        .slice(..)
+        .map_async(MapMode::Read, move |result| {
+            let _ = sender.try_send((id, result.is_ok()));
+        });
+    buffers.mapping.push(copy);
+}
+
+/// Queue writes land before the units node runs, so the simulation continues from the loaded
+/// textures this frame.
+fn upload_load(
+    pending_load: Res<PendingLoad>,
+    textures: Option<Res<UnitsDataTextures>>,
+    render_queue: Res<RenderQueue>,
+    config: Res<SimulationConfig>,
+) {
+    let (Some(save), Some(textures)) = (&pending_load.0, textures) else {
+        return;
+    };
+    let [units, attack, large_units] = SavedTexture::all(&config);
+
+    let write = |texture: &Texture, data: &[u8], saved: SavedTexture| {
+        render_queue.write_texture(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(saved.bytes_per_row()),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: saved.width,
+                height: saved.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    };
+
+    write(&textures.a.texture, &save.units, units);
+    // Write both sides of the ping-pong so the draws are right even while paused
+    for texture in [&textures.attack_a.texture, &textures.attack_b.texture] {
+        write(texture, &save.attack, attack);
+    }
+    for texture in [
+        &textures.large_unit_a.texture,
+        &textures.large_unit_b.texture,
+    ] {
+        write(texture, &save.large_units, large_units);
+    }
+}
+
+#[derive(Default)]
+pub struct SaveNode;
+impl SaveNode {
+    pub const NAME: &'static str = "match_save";
+}
+
+impl ViewNode for SaveNode {
+    type ViewQuery = &'static UnitsPass;
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        _units_pass: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Some(copy) = &world.resource::<SaveBuffers>().current else {
+            return Ok(());
+        };
+        let unit_data_texture = world.resource::<UnitsDataTextures>();
+        let config = world.resource::<SimulationConfig>();
+        let (offsets, _) = buffer_offsets(config);
+
+        let encoder = render_context.command_encoder();
+
+        // After the units node: a was written by the update, attack_a by the evaluate and
+        // large_unit_b by the large unit update.
+        let textures = [
+            &unit_data_texture.a.texture,
+            &unit_data_texture.attack_a.texture,
+            &unit_data_texture.large_unit_b.texture,
+        ];
+        for ((texture, offset), saved) in textures
+            .into_iter()
+            .zip(offsets)
+            .zip(SavedTexture::all(config))
+        {
+            encoder.copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer: &copy.buffer,
+                    layout: ImageDataLayout {
+                        offset,
+                        bytes_per_row: Some(saved.padded_bytes_per_row()),
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d {
+                    width: saved.width,
+                    height: saved.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn save(config: &SimulationConfig) -> MatchSave {
+        let [units, attack, large_units] = SavedTexture::all(config);
+        let pattern = |len: usize| (0..len).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>();
+        MatchSave::new(
+            config,
+            1234,
+            99,
+            pattern(units.len()),
+            pattern(attack.len()),
+            pattern(large_units.len()),
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = SimulationConfig::with_grid_size(256);
+        let saved = save(&config);
+        let loaded = MatchSave::from_bytes(&config, &saved.to_bytes()).unwrap();
+        assert_eq!(loaded.header, saved.header);
+        assert_eq!(loaded.header.frame, 1234);
+        assert_eq!(loaded.header.seed, 99);
+        assert_eq!(loaded.units, saved.units);
+        assert_eq!(loaded.attack, saved.attack);
+        assert_eq!(loaded.large_units, saved.large_units);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let config = SimulationConfig::with_grid_size(256);
+        let mut bytes = save(&config).to_bytes();
+        bytes[0] = b'X';
+        assert!(matches!(
+            MatchSave::from_bytes(&config, &bytes),
+            Err(SaveError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        let config = SimulationConfig::with_grid_size(256);
+        let bytes = save(&config).to_bytes();
+        for len in [2, 4 + HEADER_WORDS * 4 - 1, bytes.len() - 1] {
+            assert!(matches!(
+                MatchSave::from_bytes(&config, &bytes[..len]),
+                Err(SaveError::Truncated)
+            ));
+        }
+    }
+
+    #[test]
+    fn mismatch() {
+        let config = SimulationConfig::with_grid_size(256);
+        let bytes = save(&config).to_bytes();
+        assert!(matches!(
+            MatchSave::from_bytes(&SimulationConfig::with_grid_size(512), &bytes),
+            Err(SaveError::Mismatch("units width", 256, 512))
+        ));
+        assert!(matches!(
+            MatchSave::from_bytes(&config.with_teams(3), &bytes),
+            Err(SaveError::Mismatch("teams", 2, 3))
+        ));
+    }
+}
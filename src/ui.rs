@@ -6,18 +6,44 @@ use bevy::sprite::Anchor;
 
 use bevy_picoui::pico::*;
 
+use crate::balance::BalanceConfig;
+use crate::difficulty::Difficulty;
+use crate::game_state::GameState;
 use crate::post_process::PostProcessPass;
-use crate::units::UnitCommand;
+use crate::readback::SimulationSnapshot;
+use crate::units::{
+    ResetSimulation, SimulationConfig, TeamCommands, COMMAND_ATTACK_MOVE, COMMAND_HOLD,
+    COMMAND_PATROL, COMMAND_STOP, PLAYER_TEAM, UNIT_TYPE_MELEE, UNIT_TYPE_RANGED,
+    UNIT_TYPE_SUPPORT,
+};
+use crate::{PendingOrder, SelectedHydra};
 
 pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_2d_camera)
-            .add_systems(Update, update);
+            .add_systems(Update, (update, state_overlay));
     }
 }
 
+/// Sidebar buttons for `PendingOrder`, the key for each is in `ORDER_KEYS`
+const ORDER_BUTTONS: [(&str, u32); 4] = [
+    ("STOP X", COMMAND_STOP),
+    ("HOLD Z", COMMAND_HOLD),
+    ("ATTACK MOVE F", COMMAND_ATTACK_MOVE),
+    ("PATROL P", COMMAND_PATROL),
+];
+
+/// Sidebar buttons for the weight of each goose type in the spawn mix
+const SPAWN_MIX_BUTTONS: [(&str, u32); 3] = [
+    ("RANGED", UNIT_TYPE_RANGED),
+    ("MELEE", UNIT_TYPE_MELEE),
+    ("SUPPORT", UNIT_TYPE_SUPPORT),
+];
+/// Clicking a spawn mix button steps its weight up to this, then back to 0
+const MAX_SPAWN_WEIGHT: u32 = 4;
+
 fn setup_2d_camera(mut commands: Commands) {
     commands
         .spawn(Camera2dBundle {
@@ -39,19 +65,20 @@ fn update(
     _gizmos: Gizmos,
     mut pico: ResMut<Pico>,
     windows: Query<&Window>,
-    mut unit_command: ResMut<UnitCommand>,
+    mut team_commands: ResMut<TeamCommands>,
+    config: Res<SimulationConfig>,
+    snapshot: Res<SimulationSnapshot>,
+    mut pending_order: ResMut<PendingOrder>,
+    mut selected_hydra: ResMut<SelectedHydra>,
+    balance: Res<BalanceConfig>,
 ) {
     let Some(window) = windows.iter().next() else {
         return;
     };
 
-    // using physical_height to match minimap shader
-    let minimap_scale = (window.physical_height() as f32 / 720.0).round().max(1.0);
-    let window_factor = 1.0 / window.scale_factor() as f32;
-
-    let scale = minimap_scale * window_factor;
+    let scale = ui_scale(window);
 
-    let minimap_size = 128.0 * scale;
+    let minimap_size = config.minimap_size(0).y as f32 * scale;
 
     let sidebar = pico.add(PicoItem {
         depth: Some(0.01),
@@ -96,7 +123,7 @@ fn update(
         text_section(scale, 9.0, "MOVEMENT", main_box),
     );
     if pico.clicked(&btn) {
-        unit_command.upgrade_movment_rate();
+        team_commands.team_mut(PLAYER_TEAM).upgrade_movment_rate();
     }
     let btn = ubutton(
         &mut pico,
@@ -104,7 +131,7 @@ fn update(
         text_section(scale, 10.0, "ATTACK", main_box),
     );
     if pico.clicked(&btn) {
-        unit_command.upgrade_attack_rate();
+        team_commands.team_mut(PLAYER_TEAM).upgrade_attack_rate();
     }
     let btn = ubutton(
         &mut pico,
@@ -112,10 +139,167 @@ fn update(
         text_section(scale, 11.0, "SPAWN", main_box),
     );
     if pico.clicked(&btn) {
-        unit_command.upgrade_spawn_rate();
+        team_commands.team_mut(PLAYER_TEAM).upgrade_spawn_rate();
+    }
+    let hatch = format!("HATCH HYDRA {}", balance.hatch_cost);
+    let btn = ubutton(
+        &mut pico,
+        scale,
+        text_section(scale, 12.0, &hatch, main_box),
+    );
+    if pico.clicked(&btn) {
+        team_commands.team_mut(PLAYER_TEAM).hatch_hydra();
+    }
+
+    pico.add(text_section(scale, 14.0, "SPAWN MIX", main_box));
+    let spawn_mix = snapshot
+        .team(PLAYER_TEAM)
+        .spawn_mix
+        .unwrap_or_else(|| balance.spawn_weights());
+    for (row, (name, unit_type)) in SPAWN_MIX_BUTTONS.iter().enumerate() {
+        let weight = spawn_mix[*unit_type as usize];
+        let text = format!("{name} {weight}");
+        let btn = ubutton(
+            &mut pico,
+            scale,
+            text_section(scale, 15.0 + row as f32, &text, main_box),
+        );
+        if pico.clicked(&btn) {
+            let mut mix = spawn_mix;
+            mix[*unit_type as usize] = (weight + 1) % (MAX_SPAWN_WEIGHT + 1);
+            // Something has to spawn
+            if mix != UVec3::ZERO {
+                team_commands.team_mut(PLAYER_TEAM).set_spawn_mix(mix);
+            }
+        }
+    }
+
+    pico.add(text_section(scale, 19.0, "ORDERS", main_box));
+    for (row, (text, command)) in ORDER_BUTTONS.iter().enumerate() {
+        let mut item = text_section(scale, 20.0 + row as f32, text, main_box);
+        if pending_order.0 == *command {
+            // Waiting for a click on the board
+            item.style.background_color = Color::WHITE * 0.4;
+        }
+        let btn = ubutton(&mut pico, scale, item);
+        if pico.clicked(&btn) {
+            pending_order.0 = *command;
+        }
+    }
+
+    // Cycles through the hydras like Tab
+    let hydras = snapshot.player_hydras.len();
+    let text = match selected_hydra.0 {
+        Some(slot) => format!("HYDRA {} OF {hydras} TAB", slot + 1),
+        None => format!("HYDRAS {hydras} TAB"),
+    };
+    let btn = ubutton(&mut pico, scale, text_section(scale, 24.0, &text, main_box));
+    if pico.clicked(&btn) {
+        selected_hydra.0 = selected_hydra.next(&snapshot);
+    }
+
+    let selected = format!("SELECTED {}", snapshot.selected);
+    pico.add(text_section(scale, 26.0, &selected, main_box));
+    let groups = (1..)
+        .zip(snapshot.control_groups)
+        .filter(|(_, size)| *size > 0);
+    for (row, (group, size)) in groups.enumerate() {
+        let text = format!("GROUP {group} {size}");
+        pico.add(text_section(scale, 27.0 + row as f32, &text, main_box));
+    }
+}
+
+fn state_overlay(
+    mut pico: ResMut<Pico>,
+    windows: Query<&Window>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut reset_events: EventWriter<ResetSimulation>,
+    snapshot: Res<SimulationSnapshot>,
+    config: Res<SimulationConfig>,
+    mut difficulty: ResMut<Difficulty>,
+) {
+    let Some(window) = windows.iter().next() else {
+        return;
+    };
+
+    let scale = ui_scale(window);
+
+    // (title, button text, restart the match)
+    let (title, button, reset) = match state.get() {
+        GameState::Playing => return,
+        GameState::MainMenu => ("GAGGLE", "PLAY", true),
+        GameState::Paused => ("PAUSED", "RESUME", false),
+        GameState::Victory => ("VICTORY", "PLAY AGAIN", true),
+        GameState::Defeat => ("DEFEAT", "PLAY AGAIN", true),
+    };
+
+    let panel = pico.add(PicoItem {
+        depth: Some(0.6),
+        x: Val::Px(0.0),
+        y: Val::Px(0.0),
+        width: Val::Px(260.0 * scale),
+        height: Val::Px(172.0 * scale),
+        style: ItemStyle {
+            background_color: Color::WHITE * 0.1,
+            ..default()
+        },
+        anchor: Anchor::Center,
+        anchor_parent: Anchor::Center,
+        ..default()
+    });
+
+    pico.add(text_section(scale, 0.0, title, panel));
+
+    let mut button_row = 2.0;
+    if matches!(state.get(), GameState::Victory | GameState::Defeat) {
+        let player = snapshot.team(PLAYER_TEAM);
+        let defeated: u32 = (1..=config.teams)
+            .filter(|team| *team != PLAYER_TEAM)
+            .map(|team| snapshot.team(team).lost)
+            .sum();
+        let lost = format!("GEESE LOST {}", player.lost);
+        let defeated = format!("DEFEATED {defeated}");
+        let hydra = format!("HYDRA HEALTH {}", player.hydra.health);
+        pico.add(text_section(scale, 2.0, &lost, panel));
+        pico.add(text_section(scale, 3.0, &defeated, panel));
+        pico.add(text_section(scale, 4.0, &hydra, panel));
+        button_row = 5.0;
+    }
+
+    let btn = ubutton(
+        &mut pico,
+        scale,
+        text_section(scale, button_row, button, panel),
+    );
+    if pico.clicked(&btn) {
+        if reset {
+            reset_events.send(ResetSimulation);
+        }
+        next_state.set(GameState::Playing);
+    }
+
+    // Only between matches
+    if reset {
+        let text = format!("DIFFICULTY {}", difficulty.name());
+        let btn = ubutton(
+            &mut pico,
+            scale,
+            text_section(scale, button_row + 1.0, &text, panel),
+        );
+        if pico.clicked(&btn) {
+            *difficulty = difficulty.next();
+        }
     }
 }
 
+// using physical_height to match minimap shader
+fn ui_scale(window: &Window) -> f32 {
+    let minimap_scale = (window.physical_height() as f32 / 720.0).round().max(1.0);
+    let window_factor = 1.0 / window.scale_factor() as f32;
+    minimap_scale * window_factor
+}
+
 pub fn ubutton(pico: &mut Pico, scale: f32, mut item: PicoItem) -> ItemIndex {
     item.width = Val::Percent(60.0);
     item.height = Val::Px(19.0 * scale);
@@ -1,6 +1,13 @@
+pub mod sim;
+
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::{BuildHasher, Hasher},
+};
+
+use bevy::math::*;
 use bevy::render::render_resource::*;
 use bevy::{
-    core::FrameCount,
     core_pipeline::{
         core_3d::{self},
         deferred::{DEFERRED_LIGHTING_PASS_ID_FORMAT, DEFERRED_PREPASS_FORMAT},
@@ -23,14 +30,18 @@ use bevy::{
     },
 };
 
+use crate::balance::BalanceConfig;
 use crate::bind_group_utils::ftexture_layout_entry;
-use crate::minimap::{MinimapTextures, MINIMAP_SCALE};
+use crate::difficulty::AiSettings;
+use crate::flow_field::{FlowFieldTargets, FlowFieldTextures};
+use crate::minimap::{update_minimap, MinimapTextures, MINIMAP_SCALE};
+use crate::nav_grid::NavGridImage;
 use crate::{
     bind_group_utils::{
-        basic_fullscreen_tri_pipeline, basic_opaque_pipeline, fsampler_layout_entry,
-        globals_binding, globals_layout_entry, load_color_attachment, load_depth_attachment,
-        nearest_sampler, opaque_target, uniform_buffer, uniform_layout_entry,
-        utexture_layout_entry, view_binding, view_layout_entry,
+        basic_fullscreen_tri_pipeline, basic_opaque_pipeline, clear_color_attachment,
+        fsampler_layout_entry, globals_binding, globals_layout_entry, load_color_attachment,
+        load_depth_attachment, nearest_sampler, opaque_target, uniform_buffer,
+        uniform_layout_entry, utexture_layout_entry, view_binding, view_layout_entry,
     },
     image, resource, shader_def_uint, UnitTexture,
 };
@@ -43,18 +54,469 @@ pub const ATTACK_RADIUS: u32 = 5;
 pub const LARGE_UNITS_DATA_FORMAT: TextureFormat = TextureFormat::Rgba32Uint;
 pub const LARGE_UNITS_TEXTURE_WIDTH: u32 = 67;
 pub const LARGE_UNITS_DATA_WIDTH: u32 = 65;
-pub const LARGE_UNITS_TEXTURE_HEIGHT: u32 = 2;
+/// Hydra destinations are stored in 16 bit fixed point with this many steps per cell, which covers
+/// grids up to `MAX_GRID_SIZE`. Must match common.wgsl.
+pub const LARGE_UNIT_DEST_SCALE: f32 = 32.0;
+pub const MAX_GRID_SIZE: u32 = 2048;
+/// Most teams a match can have, see `SimulationConfig::teams`
+pub const MAX_TEAMS: u32 = 4;
+/// One row per team, rows of teams that aren't playing stay empty
+pub const LARGE_UNITS_TEXTURE_HEIGHT: u32 = MAX_TEAMS;
+
+/// Grid size of the GPU simulation, picked at startup. Insert before `UnitsPlugin` to override the
+/// defaults, the pipelines are built with matching shader defs.
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SimulationConfig {
+    pub units_width: u32,
+    pub units_height: u32,
+    /// Large unit slots per team, the texture has 2 more columns for the tracker and upgrades.
+    pub large_units_width: u32,
+    pub minimap_scale: u32,
+    /// Teams in the match, 2 to `MAX_TEAMS`. Team 1 is the player, the rest are played by the AI.
+    pub teams: u32,
+    /// Geese attack the nearest enemy instead of a random cell in range and deal damage in step
+    /// with delta time, so fights play out the same at any frame rate. See `DETERMINISTIC_ATTACKS`
+    /// in unit_evaluate.wgsl.
+    pub deterministic_attacks: bool,
+    /// Simulation ticks per second. The evaluate, update and large update passes run as many times
+    /// each frame as it takes to keep up, and the units are drawn part way to the next tick. 0 runs
+    /// them once every rendered frame.
+    pub tick_rate: u32,
+    /// Seed of every match, the same seed and orders play out the same match. A new one is picked
+    /// for each match if unset.
+    pub seed: Option<u32>,
+}
 
-#[derive(Resource, Clone, ExtractResource, Copy, ShaderType, Debug, Default)]
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            units_width: UNITS_DATA_WIDTH,
+            units_height: UNITS_DATA_HEIGHT,
+            large_units_width: LARGE_UNITS_DATA_WIDTH,
+            minimap_scale: MINIMAP_SCALE,
+            teams: 2,
+            deterministic_attacks: false,
+            tick_rate: 0,
+            seed: None,
+        }
+    }
+}
+
+impl SimulationConfig {
+    /// Square grid of `size` units, must be divisible by `MINIMAP_SCALE`^4 for the minimap chain.
+    pub fn with_grid_size(size: u32) -> Self {
+        let config = Self {
+            units_width: size,
+            units_height: size,
+            ..default()
+        };
+        let min_size = config.minimap_scale.pow(4);
+        assert!(
+            size >= min_size && size % min_size == 0,
+            "grid size {size} must be a multiple of {min_size}"
+        );
+        assert!(
+            size <= MAX_GRID_SIZE,
+            "grid size {size} is over {MAX_GRID_SIZE}"
+        );
+        config
+    }
+
+    pub fn with_teams(self, teams: u32) -> Self {
+        assert!(
+            (2..=MAX_TEAMS).contains(&teams),
+            "teams must be between 2 and {MAX_TEAMS}"
+        );
+        Self { teams, ..self }
+    }
+
+    pub fn with_deterministic_attacks(self, deterministic_attacks: bool) -> Self {
+        Self {
+            deterministic_attacks,
+            ..self
+        }
+    }
+
+    pub fn with_tick_rate(self, tick_rate: u32) -> Self {
+        Self { tick_rate, ..self }
+    }
+
+    pub fn with_seed(self, seed: u32) -> Self {
+        Self {
+            seed: Some(seed),
+            ..self
+        }
+    }
+
+    /// Seed for a new match, see `seed`
+    pub fn match_seed(&self) -> u32 {
+        self.seed.unwrap_or_else(|| {
+            // Random keys from the OS where there are any
+            RandomState::new().build_hasher().finish() as u32
+        })
+    }
+
+    pub fn large_units_texture_width(&self) -> u32 {
+        self.large_units_width + 2
+    }
+
+    pub fn unit_count(&self) -> u32 {
+        self.units_width * self.units_height
+    }
+
+    /// Size of `minimap_tex` (level 0) and its downsamples `minimap_sm_tex` .. `minimap_sm3_tex`.
+    pub fn minimap_size(&self, level: u32) -> UVec2 {
+        let scale = self.minimap_scale.pow(level + 1);
+        uvec2(self.units_width / scale, self.units_height / scale)
+    }
+
+    pub fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        let mut shader_defs = vec![
+            ShaderDefVal::UInt("MINIMAP_SCALE".into(), self.minimap_scale),
+            ShaderDefVal::UInt("UNITS_DATA_WIDTH".into(), self.units_width),
+            ShaderDefVal::UInt("UNITS_DATA_HEIGHT".into(), self.units_height),
+            ShaderDefVal::UInt("LARGE_UNITS_DATA_WIDTH".into(), self.large_units_width),
+            ShaderDefVal::UInt(
+                "LARGE_UNITS_TEXTURE_WIDTH".into(),
+                self.large_units_texture_width(),
+            ),
+            shader_def_uint!(LARGE_UNITS_TEXTURE_HEIGHT),
+            ShaderDefVal::UInt("TEAMS".into(), self.teams),
+            shader_def_uint!(ATTACK_RADIUS),
+        ];
+        if self.deterministic_attacks {
+            shader_defs.push("DETERMINISTIC_ATTACKS".into());
+        }
+        shader_defs
+    }
+}
+
+pub const UNIT_MODE_IDLE: u32 = 0;
+pub const UNIT_MODE_MOVE: u32 = 1;
+pub const UNIT_MODE_MOVEING: u32 = 2;
+pub const UNIT_MODE_ATTACK: u32 = 3;
+pub const UNIT_MODE_ATTACK_HYDRA: u32 = 4;
+
+/// Idle units fight anything in range
+pub const UNIT_ORDER_NONE: u32 = 0;
+/// Ignore enemies until the end of the path
+pub const UNIT_ORDER_MOVE: u32 = 1;
+/// Fight but never move
+pub const UNIT_ORDER_HOLD: u32 = 2;
+/// Fight anything met on the way
+pub const UNIT_ORDER_ATTACK_MOVE: u32 = 3;
+/// Attack move around a looping path
+pub const UNIT_ORDER_PATROL: u32 = 4;
+
+/// Geese that shoot from the full `ATTACK_RADIUS`
+pub const UNIT_TYPE_RANGED: u32 = 0;
+/// Geese that hit hard up close
+pub const UNIT_TYPE_MELEE: u32 = 1;
+/// Fast, weak geese
+pub const UNIT_TYPE_SUPPORT: u32 = 2;
+/// Stats of each come from `BalanceConfig::unit_types`. Must fit in 4 bits of `Unit::pack`.
+pub const UNIT_TYPES: u32 = 3;
+
+/// See `com::command_order`
+pub fn command_order(command: u32) -> u32 {
+    match command {
+        COMMAND_MOVE => UNIT_ORDER_MOVE,
+        COMMAND_HOLD => UNIT_ORDER_HOLD,
+        COMMAND_ATTACK_MOVE => UNIT_ORDER_ATTACK_MOVE,
+        COMMAND_PATROL => UNIT_ORDER_PATROL,
+        _ => UNIT_ORDER_NONE,
+    }
+}
+
+/// Where `team`'s hydra spawns, normalized, scale by the grid size. See `com::hydra_start`.
+pub fn hydra_start(team: u32) -> Vec2 {
+    match team {
+        2 => vec2(0.5, 0.5859375),
+        3 => vec2(0.40234375, 0.48828125),
+        4 => vec2(0.59765625, 0.48828125),
+        _ => vec2(0.5, 0.390625),
+    }
+}
+
+// ---------------------------------------
+// Unit packing, must match common.wgsl
+// ---------------------------------------
+
+/// See `com::Unit` and `pack_unit`. One texel of `UnitsDataTextures::a`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Unit {
+    pub health: u32,
+    pub progress: f32,
+    pub step_dir: IVec2,
+    pub dest: UVec2,
+    pub mode: u32,
+    pub team: u32,
+    pub attacking_hydra: u32,
+    /// Team of the hydra being attacked
+    pub attacking_team: u32,
+    /// `UNIT_ORDER_*`, unlike `mode` this lasts until the next order
+    pub order: u32,
+    /// `UNIT_TYPE_*`
+    pub unit_type: u32,
+    pub selected: bool,
+    /// Control group, 0 is none
+    pub group: u32,
+    pub id: u32,
+}
+
+/// See `com::LargeUnit` and `pack_large_unit`. One texel of the large unit texture, the team comes
+/// from the row the texel is in.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LargeUnit {
+    pub pos: Vec2,
+    pub dest: Vec2,
+    pub health: u32,
+    pub mode: u32,
+    pub progress: f32,
+    pub team: u32,
+    pub dir_index: u32,
+    pub order: u32,
+}
+
+pub fn unpack_2x4_from_8(v: u32) -> UVec2 {
+    uvec2(v & 0xF, (v >> 4) & 0xF)
+}
+
+pub fn pack_2x4_to_8(v: UVec2) -> u32 {
+    ((v.y & 0xF) << 4) | (v.x & 0xF)
+}
+
+pub fn unpack_4x8_(v: u32) -> UVec4 {
+    uvec4(
+        v & 0xFF,
+        (v >> 8) & 0xFF,
+        (v >> 16) & 0xFF,
+        (v >> 24) & 0xFF,
+    )
+}
+
+pub fn pack_4x8_(v: UVec4) -> u32 {
+    ((v.w & 0xFF) << 24) | ((v.z & 0xFF) << 16) | ((v.y & 0xFF) << 8) | (v.x & 0xFF)
+}
+
+pub fn unpack_2x16_(v: u32) -> UVec2 {
+    uvec2(v & 0xFFFF, (v >> 16) & 0xFFFF)
+}
+
+pub fn pack_2x16_(v: UVec2) -> u32 {
+    ((v.y & 0xFFFF) << 16) | (v.x & 0xFFFF)
+}
+
+/// Round to nearest even f16, matching `pack2x16float`.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let x = value.to_bits();
+    let sign = ((x >> 16) & 0x8000) as u16;
+    let exp = ((x >> 23) & 0xFF) as i32;
+    let man = x & 0x7F_FFFF;
+
+    if exp == 0xFF {
+        // Inf or NaN
+        return sign | 0x7C00 | if man != 0 { 0x200 } else { 0 };
+    }
+
+    let e = exp - 127 + 15;
+    if e >= 0x1F {
+        return sign | 0x7C00;
+    }
+
+    let (half, rem, halfway) = if e <= 0 {
+        if e < -10 {
+            return sign;
+        }
+        let man = man | 0x80_0000;
+        let shift = (14 - e) as u32;
+        (man >> shift, man & ((1 << shift) - 1), 1 << (shift - 1))
+    } else {
+        (((e as u32) << 10) | (man >> 13), man & 0x1FFF, 0x1000)
+    };
+
+    let rounded = if rem > halfway || (rem == halfway && (half & 1) == 1) {
+        half + 1
+    } else {
+        half
+    };
+    sign | rounded as u16
+}
+
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1F) as u32;
+    let man = (h & 0x3FF) as u32;
+
+    let bits = if exp == 0 {
+        if man == 0 {
+            sign
+        } else {
+            // Subnormal, renormalize
+            let mut e = 127 - 15 + 1;
+            let mut m = man;
+            while m & 0x400 == 0 {
+                m <<= 1;
+                e -= 1;
+            }
+            sign | (e << 23) | ((m & 0x3FF) << 13)
+        }
+    } else if exp == 0x1F {
+        sign | 0x7F80_0000 | (man << 13)
+    } else {
+        sign | ((exp + 127 - 15) << 23) | (man << 13)
+    };
+
+    f32::from_bits(bits)
+}
+
+pub fn pack2x16float(v: Vec2) -> u32 {
+    (f32_to_f16(v.x) as u32) | ((f32_to_f16(v.y) as u32) << 16)
+}
+
+pub fn unpack2x16float(v: u32) -> Vec2 {
+    vec2(f16_to_f32(v as u16), f16_to_f32((v >> 16) as u16))
+}
+
+impl Unit {
+    pub fn unpack(data: UVec4) -> Self {
+        let a = unpack_2x16_(data.x);
+        let d = unpack_4x8_(data.y);
+        let mode_team = unpack_2x4_from_8(d.w);
+        Self {
+            progress: unpack2x16float(a.x).x,
+            attacking_hydra: a.y & 0xFF,
+            order: (a.y >> 8) & 0xF,
+            unit_type: a.y >> 12,
+            step_dir: unpack_2x4_from_8(d.x).as_ivec2() - 1,
+            attacking_team: unpack_2x4_from_8(d.y).x,
+            selected: unpack_2x4_from_8(d.y).y & 1 != 0,
+            group: unpack_2x4_from_8(d.y).y >> 1,
+            health: d.z,
+            mode: mode_team.x,
+            team: mode_team.y,
+            dest: unpack_2x16_(data.z),
+            id: data.w,
+        }
+    }
+
+    pub fn pack(&self) -> UVec4 {
+        uvec4(
+            pack_2x16_(uvec2(
+                pack2x16float(vec2(self.progress, 0.0)),
+                self.attacking_hydra | (self.order << 8) | (self.unit_type << 12),
+            )),
+            pack_4x8_(uvec4(
+                pack_2x4_to_8(uvec2(
+                    (self.step_dir.x + 1) as u32,
+                    (self.step_dir.y + 1) as u32,
+                )),
+                pack_2x4_to_8(uvec2(
+                    self.attacking_team,
+                    self.selected as u32 | (self.group << 1),
+                )),
+                self.health,
+                pack_2x4_to_8(uvec2(self.mode, self.team)),
+            )),
+            pack_2x16_(self.dest),
+            self.id,
+        )
+    }
+}
+
+impl LargeUnit {
+    pub fn unpack(data: UVec4, coord: UVec2) -> Self {
+        let d1 = unpack_2x16_(data.w);
+        let d1b = unpack_4x8_(d1.y);
+        let mode_order = unpack_2x4_from_8(d1b.x);
+        Self {
+            // f16 was not accurate enough for pos given a small enough delta time
+            pos: vec2(f32::from_bits(data.x), f32::from_bits(data.y)),
+            dest: unpack_2x16_(data.z).as_vec2() / LARGE_UNIT_DEST_SCALE,
+            health: d1.x,
+            mode: mode_order.x,
+            progress: 0.0,
+            team: coord.y + 1,
+            dir_index: d1b.y,
+            // Only the low 16 bits are free, so the order shares a byte with the mode
+            order: mode_order.y,
+        }
+    }
+
+    pub fn pack(&self) -> UVec4 {
+        uvec4(
+            self.pos.x.to_bits(),
+            self.pos.y.to_bits(),
+            pack_2x16_(
+                (self.dest * LARGE_UNIT_DEST_SCALE + 0.5)
+                    .clamp(Vec2::ZERO, Vec2::splat(65535.0))
+                    .as_uvec2(),
+            ),
+            pack_2x16_(uvec2(
+                self.health,
+                pack_4x8_(uvec4(
+                    pack_2x4_to_8(uvec2(self.mode, self.order)),
+                    self.dir_index,
+                    0,
+                    0,
+                )),
+            )),
+        )
+    }
+}
+
+/// Decodes a tightly packed `UNITS_DATA_FORMAT` texture readback, in texel order.
+pub fn unpack_units(bytes: &[u8]) -> impl Iterator<Item = Unit> + '_ {
+    bytemuck::cast_slice::<u8, [u32; 4]>(bytes)
+        .iter()
+        .map(|data| Unit::unpack(UVec4::from_array(*data)))
+}
+
+pub const COMMAND_MOVE: u32 = 1;
+/// Replace the selection with the geese inside `UnitCommand::select_region`
+pub const COMMAND_SELECT: u32 = 2;
+/// Selected geese join `UnitCommand::control_group`, the rest leave it
+pub const COMMAND_ASSIGN_GROUP: u32 = 3;
+/// Select the geese in `UnitCommand::control_group`
+pub const COMMAND_RECALL_GROUP: u32 = 4;
+pub const COMMAND_STOP: u32 = 5;
+pub const COMMAND_HOLD: u32 = 6;
+pub const COMMAND_ATTACK_MOVE: u32 = 7;
+pub const COMMAND_PATROL: u32 = 8;
+
+/// Groups 1 to `CONTROL_GROUPS`, 0 is no group. Must fit in 3 bits of `Unit::pack`.
+pub const CONTROL_GROUPS: u32 = 7;
+
+/// Bit of `UnitCommand::upgrade_request` that spends `BalanceConfig::hatch_cost` on another hydra
+/// in a free slot of the team's row of the large unit texture. Must match `com::HATCH_REQUEST`.
+pub const HATCH_REQUEST: u32 = 1 << 3;
+
+/// Orders for one team this frame, see `TeamCommands`. Must match `com::UnitCommand`.
+#[derive(Clone, Copy, ShaderType, Debug, Default)]
 pub struct UnitCommand {
-    pub select_region: UVec4,
+    /// Corners of a quad on the ground in order around it, xy and zw of each. See
+    /// `in_select_region`.
+    pub select_region: [Vec4; 2],
     pub dest: UVec2,
     pub command: u32,
     pub delta_time: f32,
     pub upgrade_request: u32,
     pub unit_group: u32,
-    pub spare2_: u32,
-    pub spare3_: u32,
+    pub match_frame: u32,
+    /// Move orders only apply to selected geese
+    pub selected_only: u32,
+    pub control_group: u32,
+    /// Slot + 1 of the one hydra hydra orders go to and hatching happens next to, 0 for all
+    /// of them
+    pub hydra: u32,
+    /// Weights of each `UNIT_TYPE_*` the team's hydras spawn from now on, see `set_spawn_mix`. 0
+    /// keeps the mix as it is.
+    pub spawn_mix: u32,
+    /// Fraction of a tick that has passed since the last one, the draw passes move units on by
+    /// that much. Always 0 without a `SimulationConfig::tick_rate`.
+    pub tick_alpha: f32,
 }
 
 impl UnitCommand {
@@ -67,16 +529,247 @@ impl UnitCommand {
     pub fn upgrade_spawn_rate(&mut self) {
         self.upgrade_request |= 1 << 2;
     }
+    pub fn hatch_hydra(&mut self) {
+        self.upgrade_request |= HATCH_REQUEST;
+    }
+    /// Weights of each `UNIT_TYPE_*` up to 255, they can't all be 0
+    pub fn set_spawn_mix(&mut self, weights: UVec3) {
+        self.spawn_mix = pack_4x8_(weights.min(UVec3::splat(255)).extend(1));
+    }
+
+    pub fn select(&mut self, corners: [Vec2; 4]) {
+        let [a, b, c, d] = corners;
+        self.select_region = [vec4(a.x, a.y, b.x, b.y), vec4(c.x, c.y, d.x, d.y)];
+        self.command = COMMAND_SELECT;
+    }
+
+    pub fn assign_group(&mut self, group: u32) {
+        self.control_group = group;
+        self.command = COMMAND_ASSIGN_GROUP;
+    }
+
+    pub fn recall_group(&mut self, group: u32) {
+        self.control_group = group;
+        self.command = COMMAND_RECALL_GROUP;
+    }
+
+    /// Anything for a tick to carry out
+    pub fn is_pending(&self) -> bool {
+        self.command != 0 || self.upgrade_request != 0 || self.spawn_mix != 0
+    }
+
+    /// Folds `newer` into this one if the order is the same kind or either has none, the newer
+    /// order wins and the upgrade requests add up
+    pub fn merge(&mut self, newer: &UnitCommand) -> bool {
+        if self.command != 0 && newer.command != 0 && self.command != newer.command {
+            return false;
+        }
+        if newer.command != 0 {
+            self.select_region = newer.select_region;
+            self.dest = newer.dest;
+            self.command = newer.command;
+            self.unit_group = newer.unit_group;
+            self.selected_only = newer.selected_only;
+            self.control_group = newer.control_group;
+        }
+        if newer.hydra != 0 {
+            self.hydra = newer.hydra;
+        }
+        if newer.spawn_mix != 0 {
+            self.spawn_mix = newer.spawn_mix;
+        }
+        self.upgrade_request |= newer.upgrade_request;
+        true
+    }
+}
+
+/// See `com::in_select_region`
+pub fn in_select_region(region: [Vec4; 2], p: Vec2) -> bool {
+    let corners = [
+        region[0].xy(),
+        region[0].zw(),
+        region[1].xy(),
+        region[1].zw(),
+    ];
+    let (mut positive, mut negative) = (false, false);
+    for i in 0..4 {
+        let edge = corners[(i + 1) % 4] - corners[i];
+        let side = edge.perp_dot(p - corners[i]);
+        positive |= side > 0.0;
+        negative |= side < 0.0;
+    }
+    !(positive && negative)
+}
+
+/// The team the local player controls
+pub const PLAYER_TEAM: u32 = 1;
+
+/// One `UnitCommand` per team, cleared every frame. The player writes `PLAYER_TEAM`, the AI
+/// writes its own team. Frames without a tick hold them in `QueuedCommands`. Must match
+/// `com::TeamCommands`.
+#[derive(Resource, Clone, ExtractResource, Copy, ShaderType, Debug, Default)]
+pub struct TeamCommands {
+    pub teams: [UnitCommand; MAX_TEAMS as usize],
+    /// `SimulationState::seed`, set by `UnitsNode`
+    pub seed: u32,
+}
+
+impl TeamCommands {
+    /// `team` is 1 to `MAX_TEAMS`
+    pub fn team(&self, team: u32) -> &UnitCommand {
+        &self.teams[team as usize - 1]
+    }
+
+    /// `team` is 1 to `MAX_TEAMS`
+    pub fn team_mut(&mut self, team: u32) -> &mut UnitCommand {
+        &mut self.teams[team as usize - 1]
+    }
+}
+
+/// Orders given in frames without a tick, or while an earlier one was still waiting for one. Each
+/// frame that ticks carries out the oldest for every team.
+#[derive(Resource, Default)]
+pub struct QueuedCommands {
+    pub teams: [VecDeque<UnitCommand>; MAX_TEAMS as usize],
+}
+
+pub const MAX_PATHS: u32 = 4;
+pub const MAX_WAYPOINTS: u32 = 8;
+/// Units this close to a queued waypoint head for the next one
+pub const WAYPOINT_RADIUS: f32 = 12.0;
+
+/// Waypoints queued by one team. Units aren't told which path they are on, a unit whose dest is
+/// on a path moves on to the next point once it gets close. Must match `com::WaypointPaths`.
+#[derive(Clone, Copy, ShaderType, Debug, Default)]
+pub struct WaypointPaths {
+    /// `MAX_WAYPOINTS` per path packed with `pack_2x16_`, 4 per element
+    pub points: [UVec4; (MAX_PATHS * MAX_WAYPOINTS / 4) as usize],
+    pub lengths: UVec4,
+    /// Non zero if the path goes back to its first point after the last, used by patrols
+    pub looping: UVec4,
+}
+
+impl WaypointPaths {
+    pub fn point(&self, path: u32, i: u32) -> UVec2 {
+        let index = path * MAX_WAYPOINTS + i;
+        unpack_2x16_(self.points[index as usize / 4][index as usize % 4])
+    }
+
+    pub fn path(&self, path: u32) -> impl Iterator<Item = UVec2> + '_ {
+        (0..self.lengths[path as usize]).map(move |i| self.point(path, i))
+    }
+
+    /// Replaces `path` with one that only has `dest`
+    pub fn start(&mut self, path: u32, dest: UVec2) {
+        self.lengths[path as usize] = 0;
+        self.looping[path as usize] = 0;
+        self.push(path, dest);
+    }
+
+    /// Queues `dest` at the end of `path`, false if it is full
+    pub fn push(&mut self, path: u32, dest: UVec2) -> bool {
+        let len = self.lengths[path as usize];
+        if len >= MAX_WAYPOINTS {
+            return false;
+        }
+        let index = (path * MAX_WAYPOINTS + len) as usize;
+        self.points[index / 4][index % 4] = pack_2x16_(dest);
+        self.lengths[path as usize] += 1;
+        true
+    }
+
+    /// See `com::next_waypoint`
+    pub fn next(&self, dest: Vec2) -> Vec2 {
+        for path in 0..MAX_PATHS {
+            let len = self.lengths[path as usize];
+            // Looping paths carry on from the last point back to the first
+            let count = if self.looping[path as usize] != 0 {
+                len
+            } else {
+                len.saturating_sub(1)
+            };
+            for i in 0..count {
+                if self.point(path, i).as_vec2().distance(dest) < 1.0 {
+                    return self.point(path, (i + 1) % len).as_vec2();
+                }
+            }
+        }
+        dest
+    }
+}
+
+/// Shift queued orders for every team, cleared when a match starts. Must match
+/// `com::TeamWaypoints`.
+#[derive(Resource, Clone, ExtractResource, Copy, ShaderType, Debug, Default)]
+pub struct TeamWaypoints {
+    pub teams: [WaypointPaths; MAX_TEAMS as usize],
 }
 
+impl TeamWaypoints {
+    /// `team` is 1 to `MAX_TEAMS`
+    pub fn team(&self, team: u32) -> &WaypointPaths {
+        &self.teams[team as usize - 1]
+    }
+
+    /// `team` is 1 to `MAX_TEAMS`
+    pub fn team_mut(&mut self, team: u32) -> &mut WaypointPaths {
+        &mut self.teams[team as usize - 1]
+    }
+}
+
+/// Most ticks run in one frame, time beyond that is dropped so a slow frame can't snowball
+pub const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// Drives the GPU simulation. While paused the units are still drawn but not updated.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, Default)]
+pub struct SimulationState {
+    pub paused: bool,
+    /// Ticks simulated this match, also picks which of the ping-pong textures are read and written.
+    pub frame: u32,
+    /// Ticks `UnitsNode` runs this frame, `frame` already counts them.
+    pub ticks: u32,
+    /// Delta time of each of those ticks
+    pub tick_delta: f32,
+    /// See `UnitCommand::tick_alpha`
+    pub tick_alpha: f32,
+    /// Time carried over to the next frame that didn't make up a whole tick.
+    pub tick_time: f32,
+    /// Seeds the simulation's random choices along with `frame`, see `com::rng_frame`.
+    pub seed: u32,
+    /// Set for the frame the unit, large unit and minimap textures are cleared on.
+    pub reset: bool,
+    /// Incremented on every reset so stale readbacks from an earlier match can be told apart.
+    pub match_index: u32,
+}
+
+/// Clears the GPU simulation and starts a new match.
+#[derive(Event, Clone, Copy, Debug, Default)]
+pub struct ResetSimulation;
+
 pub struct UnitsPlugin;
 
 impl Plugin for UnitsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreUpdate, clear_unit_command)
-            .init_resource::<UnitCommand>()
+        app.init_resource::<SimulationConfig>();
+        let config = *app.world.resource::<SimulationConfig>();
+
+        app.add_systems(PreUpdate, clear_team_commands)
+            .add_systems(
+                PostUpdate,
+                (advance_simulation, reset_simulation, queue_team_commands).chain(),
+            )
+            .add_event::<ResetSimulation>()
+            .init_resource::<TeamCommands>()
+            .init_resource::<QueuedCommands>()
+            .init_resource::<TeamWaypoints>()
+            .insert_resource(SimulationState {
+                seed: config.match_seed(),
+                ..default()
+            })
             .add_plugins((
-                ExtractResourcePlugin::<UnitCommand>::default(),
+                ExtractResourcePlugin::<TeamCommands>::default(),
+                ExtractResourcePlugin::<TeamWaypoints>::default(),
+                ExtractResourcePlugin::<SimulationState>::default(),
                 ExtractComponentPlugin::<UnitsPass>::default(),
             ));
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
@@ -84,6 +777,7 @@ impl Plugin for UnitsPlugin {
         };
 
         render_app
+            .insert_resource(config)
             .add_systems(Render, prepare_textures.in_set(RenderSet::PrepareResources))
             .add_render_graph_node::<ViewNodeRunner<UnitsNode>>(
                 core_3d::graph::NAME,
@@ -135,10 +829,16 @@ impl ViewNode for UnitsNode {
         world: &World,
     ) -> Result<(), NodeRunError> {
         let unit_pipeline = world.resource::<UnitPipeline>();
-        let unit_command = world.resource::<UnitCommand>();
-        let time = world.resource::<Time>();
+        let team_commands = world.resource::<TeamCommands>();
         let minimap_textures = world.resource::<MinimapTextures>();
         let unit_data_texture = world.resource::<UnitsDataTextures>();
+        let simulation = world.resource::<SimulationState>();
+        let config = world.resource::<SimulationConfig>();
+        let balance = world.resource::<BalanceConfig>();
+        let ai_settings = world.resource::<AiSettings>();
+        let waypoints = world.resource::<TeamWaypoints>();
+        let flow_textures = resource!(world, FlowFieldTextures);
+        let flow_targets = resource!(world, FlowFieldTargets);
 
         let pipeline_cache = world.resource::<PipelineCache>();
 
@@ -151,143 +851,226 @@ impl ViewNode for UnitsNode {
         let images = world.resource::<RenderAssets<Image>>();
         let small_goose = image!(images, &resource!(world, UnitTexture).small_goose);
         let big_goose = image!(images, &resource!(world, UnitTexture).big_goose);
+        let terrain = image!(images, &resource!(world, NavGridImage).terrain);
 
         // ---------------------------------------
-        // Units Evaluate
+        // Reset
         // ---------------------------------------
 
-        let mut unit_command = *unit_command;
-        unit_command.delta_time = time.delta_seconds();
-
-        let commands_uniform = uniform_buffer(unit_command, render_context, "Unit Command Uniform");
-
-        {
-            let Some(pipeline) =
-                pipeline_cache.get_render_pipeline(unit_pipeline.evaluate_pipeline_id)
-            else {
-                return Ok(());
-            };
-
-            let bind_group = render_context.render_device().create_bind_group(
-                "unit_evaluate_bind_group",
-                &unit_pipeline.evaluate_layout,
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &unit_data_texture.a.default_view),
-                    (102, commands_uniform.as_entire_binding()),
-                    (103, &unit_data_texture.attack_b.default_view),
-                    (104, &small_goose.texture_view),
-                    (105, &unit_pipeline.sampler),
-                    (106, &unit_data_texture.large_unit_a.default_view),
-                    (107, &big_goose.texture_view),
-                    (108, &minimap_textures.minimap_sm_tex.default_view),
-                    (109, &minimap_textures.minimap_sm3_tex.default_view),
-                )),
-            );
-
-            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("Units Evaluate"),
-                color_attachments: &[
-                    load_color_attachment(&unit_data_texture.b.default_view),
-                    load_color_attachment(&unit_data_texture.attack_a.default_view),
-                ],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_render_pipeline(pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
-
-            render_pass.draw(0..3, 0..1);
+        if simulation.reset {
+            for view in [
+                &unit_data_texture.a.default_view,
+                &unit_data_texture.b.default_view,
+                &unit_data_texture.attack_a.default_view,
+                &unit_data_texture.attack_b.default_view,
+                &unit_data_texture.large_unit_a.default_view,
+                &unit_data_texture.large_unit_b.default_view,
+                &minimap_textures.minimap_tex.default_view,
+                &minimap_textures.minimap_sm_tex.default_view,
+                &minimap_textures.minimap_sm2_tex.default_view,
+                &minimap_textures.minimap_sm3_tex.default_view,
+            ] {
+                render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                    label: Some("Units Reset"),
+                    color_attachments: &[clear_color_attachment(view)],
+                    depth_stencil_attachment: None,
+                });
+            }
         }
 
-        // ---------------------------------------
-        // Units Update
-        // ---------------------------------------
-
-        let commands_uniform = uniform_buffer(unit_command, render_context, "Unit Command Uniform");
-
-        {
-            let Some(pipeline) =
-                pipeline_cache.get_render_pipeline(unit_pipeline.update_pipeline_id)
-            else {
-                return Ok(());
-            };
-
-            let bind_group = render_context.render_device().create_bind_group(
-                "unit_update_bind_group",
-                &unit_pipeline.update_layout,
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &unit_data_texture.b.default_view),
-                    (102, commands_uniform.as_entire_binding()),
-                    (103, &unit_data_texture.attack_a.default_view),
-                    (104, &small_goose.texture_view),
-                    (105, &unit_pipeline.sampler),
-                    (106, &unit_data_texture.large_unit_a.default_view),
-                    (107, &big_goose.texture_view),
-                    (108, &minimap_textures.minimap_sm_tex.default_view),
-                    (109, &minimap_textures.minimap_sm3_tex.default_view),
-                )),
-            );
-
-            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("Units Update"),
-                color_attachments: &[load_color_attachment(&unit_data_texture.a.default_view)],
-                depth_stencil_attachment: None,
-            });
-
-            render_pass.set_render_pipeline(pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
-
-            render_pass.draw(0..3, 0..1);
+        let mut team_commands = *team_commands;
+        for command in &mut team_commands.teams {
+            command.delta_time = simulation.tick_delta;
+            command.match_frame = simulation.frame;
+            command.tick_alpha = simulation.tick_alpha;
         }
+        team_commands.seed = simulation.seed;
+        let balance_uniform = uniform_buffer(*balance, render_context, "Balance Uniform");
+        let ai_uniform = uniform_buffer(*ai_settings, render_context, "AI Settings Uniform");
+        let waypoints_uniform = uniform_buffer(*waypoints, render_context, "Waypoints Uniform");
+        let flow_targets_uniform =
+            uniform_buffer(*flow_targets, render_context, "Flow Field Targets Uniform");
+
+        for tick in 0..simulation.ticks {
+            // Counted back from the last tick this frame, which `unit_data_texture` is set up for
+            let back = simulation.ticks - 1 - tick;
+            let textures = unit_data_texture.rewind(back);
+
+            // The large unit update counts deaths from the minimap, which `MinimapNode` only
+            // builds after the last tick
+            if tick > 0 {
+                update_minimap(render_context, view_uniform_offset, world);
+            }
 
-        // ---------------------------------------
-        // Large Units Update
-        // ---------------------------------------
-
-        let commands_uniform = uniform_buffer(unit_command, render_context, "Unit Command Uniform");
-
-        {
-            let Some(pipeline) =
-                pipeline_cache.get_render_pipeline(unit_pipeline.large_update_pipeline_id)
-            else {
-                return Ok(());
+            // Orders are only carried out once, on the first tick
+            let mut tick_commands = if tick == 0 {
+                team_commands
+            } else {
+                TeamCommands {
+                    seed: simulation.seed,
+                    ..default()
+                }
             };
+            for command in &mut tick_commands.teams {
+                command.delta_time = simulation.tick_delta;
+                command.match_frame = simulation.frame.wrapping_sub(back);
+            }
+            let commands_uniform =
+                uniform_buffer(tick_commands, render_context, "Team Commands Uniform");
+
+            // ---------------------------------------
+            // Units Evaluate
+            // ---------------------------------------
+
+            {
+                let Some(pipeline) =
+                    pipeline_cache.get_render_pipeline(unit_pipeline.evaluate_pipeline_id)
+                else {
+                    return Ok(());
+                };
+
+                let bind_group = render_context.render_device().create_bind_group(
+                    "unit_evaluate_bind_group",
+                    &unit_pipeline.evaluate_layout,
+                    &BindGroupEntries::with_indices((
+                        (0, view_binding(world)),
+                        (9, globals_binding(world)),
+                        (101, &textures.a.default_view),
+                        (102, commands_uniform.as_entire_binding()),
+                        (103, &textures.attack_b.default_view),
+                        (104, &small_goose.texture_view),
+                        (105, &unit_pipeline.sampler),
+                        (106, &textures.large_unit_a.default_view),
+                        (107, &big_goose.texture_view),
+                        (108, &minimap_textures.minimap_sm_tex.default_view),
+                        (109, &minimap_textures.minimap_sm3_tex.default_view),
+                        (110, balance_uniform.as_entire_binding()),
+                        (111, ai_uniform.as_entire_binding()),
+                        (112, waypoints_uniform.as_entire_binding()),
+                        (113, &flow_textures.a.default_view),
+                        (114, flow_targets_uniform.as_entire_binding()),
+                        (115, &terrain.texture_view),
+                    )),
+                );
+
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("Units Evaluate"),
+                        color_attachments: &[
+                            load_color_attachment(&textures.b.default_view),
+                            load_color_attachment(&textures.attack_a.default_view),
+                        ],
+                        depth_stencil_attachment: None,
+                    });
+
+                render_pass.set_render_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+
+                render_pass.draw(0..3, 0..1);
+            }
 
-            let bind_group = render_context.render_device().create_bind_group(
-                "unit_update_bind_group",
-                &unit_pipeline.large_update_layout,
-                &BindGroupEntries::with_indices((
-                    (0, view_binding(world)),
-                    (9, globals_binding(world)),
-                    (101, &unit_data_texture.a.default_view),
-                    (102, commands_uniform.as_entire_binding()),
-                    (103, &unit_data_texture.attack_a.default_view),
-                    (104, &small_goose.texture_view),
-                    (105, &unit_pipeline.sampler),
-                    (106, &unit_data_texture.large_unit_a.default_view),
-                    (107, &big_goose.texture_view),
-                    (108, &minimap_textures.minimap_sm_tex.default_view),
-                    (109, &minimap_textures.minimap_sm3_tex.default_view),
-                )),
-            );
+            // ---------------------------------------
+            // Units Update
+            // ---------------------------------------
+
+            {
+                let Some(pipeline) =
+                    pipeline_cache.get_render_pipeline(unit_pipeline.update_pipeline_id)
+                else {
+                    return Ok(());
+                };
+
+                let bind_group = render_context.render_device().create_bind_group(
+                    "unit_update_bind_group",
+                    &unit_pipeline.update_layout,
+                    &BindGroupEntries::with_indices((
+                        (0, view_binding(world)),
+                        (9, globals_binding(world)),
+                        (101, &textures.b.default_view),
+                        (102, commands_uniform.as_entire_binding()),
+                        (103, &textures.attack_a.default_view),
+                        (104, &small_goose.texture_view),
+                        (105, &unit_pipeline.sampler),
+                        (106, &textures.large_unit_a.default_view),
+                        (107, &big_goose.texture_view),
+                        (108, &minimap_textures.minimap_sm_tex.default_view),
+                        (109, &minimap_textures.minimap_sm3_tex.default_view),
+                        (110, balance_uniform.as_entire_binding()),
+                        (111, ai_uniform.as_entire_binding()),
+                        (112, waypoints_uniform.as_entire_binding()),
+                        (113, &flow_textures.a.default_view),
+                        (114, flow_targets_uniform.as_entire_binding()),
+                        (115, &terrain.texture_view),
+                    )),
+                );
+
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("Units Update"),
+                        color_attachments: &[load_color_attachment(&textures.a.default_view)],
+                        depth_stencil_attachment: None,
+                    });
+
+                render_pass.set_render_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+
+                render_pass.draw(0..3, 0..1);
+            }
 
-            let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-                label: Some("Large Units Update"),
-                color_attachments: &[load_color_attachment(
-                    &unit_data_texture.large_unit_b.default_view,
-                )],
-                depth_stencil_attachment: None,
-            });
+            // ---------------------------------------
+            // Large Units Update
+            // ---------------------------------------
+
+            {
+                let Some(pipeline) =
+                    pipeline_cache.get_render_pipeline(unit_pipeline.large_update_pipeline_id)
+                else {
+                    return Ok(());
+                };
+
+                let bind_group = render_context.render_device().create_bind_group(
+                    "unit_update_bind_group",
+                    &unit_pipeline.large_update_layout,
+                    &BindGroupEntries::with_indices((
+                        (0, view_binding(world)),
+                        (9, globals_binding(world)),
+                        (101, &textures.a.default_view),
+                        (102, commands_uniform.as_entire_binding()),
+                        (103, &textures.attack_a.default_view),
+                        (104, &small_goose.texture_view),
+                        (105, &unit_pipeline.sampler),
+                        (106, &textures.large_unit_a.default_view),
+                        (107, &big_goose.texture_view),
+                        (108, &minimap_textures.minimap_sm_tex.default_view),
+                        (109, &minimap_textures.minimap_sm3_tex.default_view),
+                        (110, balance_uniform.as_entire_binding()),
+                        (111, ai_uniform.as_entire_binding()),
+                        (112, waypoints_uniform.as_entire_binding()),
+                        (113, &flow_textures.a.default_view),
+                        (114, flow_targets_uniform.as_entire_binding()),
+                        (115, &terrain.texture_view),
+                    )),
+                );
+
+                let mut render_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("Large Units Update"),
+                        color_attachments: &[load_color_attachment(
+                            &textures.large_unit_b.default_view,
+                        )],
+                        depth_stencil_attachment: None,
+                    });
+
+                render_pass.set_render_pipeline(pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+
+                render_pass.draw(0..3, 0..1);
+            }
+        }
 
-            render_pass.set_render_pipeline(pipeline);
-            render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
+        let commands_uniform =
+            uniform_buffer(team_commands, render_context, "Team Commands Uniform");
 
-            render_pass.draw(0..3, 0..1);
-        }
         // ---------------------------------------
         // Units Draw
         // ---------------------------------------
@@ -312,6 +1095,12 @@ impl ViewNode for UnitsNode {
                     (107, &big_goose.texture_view),
                     (108, &minimap_textures.minimap_sm_tex.default_view),
                     (109, &minimap_textures.minimap_sm3_tex.default_view),
+                    (110, balance_uniform.as_entire_binding()),
+                    (111, ai_uniform.as_entire_binding()),
+                    (112, waypoints_uniform.as_entire_binding()),
+                    (113, &flow_textures.a.default_view),
+                    (114, flow_targets_uniform.as_entire_binding()),
+                    (115, &terrain.texture_view),
                 )),
             );
 
@@ -327,7 +1116,7 @@ impl ViewNode for UnitsNode {
             // DRAW UNITS
             render_pass.set_render_pipeline(pipeline);
             render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
-            render_pass.draw(0..UNITS_DATA_WIDTH * UNITS_DATA_HEIGHT * 6, 0..1);
+            render_pass.draw(0..config.unit_count() * 6, 0..1);
 
             let Some(pipeline) =
                 pipeline_cache.get_render_pipeline(unit_pipeline.draw_projectiles_pipeline_id)
@@ -338,7 +1127,7 @@ impl ViewNode for UnitsNode {
             // DRAW UNIT PROJECTILES
             render_pass.set_render_pipeline(pipeline);
             render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
-            render_pass.draw(0..UNITS_DATA_WIDTH * UNITS_DATA_HEIGHT * 6, 0..1);
+            render_pass.draw(0..config.unit_count() * 6, 0..1);
         }
 
         // ---------------------------------------
@@ -366,6 +1155,12 @@ impl ViewNode for UnitsNode {
                     (107, &big_goose.texture_view),
                     (108, &minimap_textures.minimap_sm_tex.default_view),
                     (109, &minimap_textures.minimap_sm3_tex.default_view),
+                    (110, balance_uniform.as_entire_binding()),
+                    (111, ai_uniform.as_entire_binding()),
+                    (112, waypoints_uniform.as_entire_binding()),
+                    (113, &flow_textures.a.default_view),
+                    (114, flow_targets_uniform.as_entire_binding()),
+                    (115, &terrain.texture_view),
                 )),
             );
 
@@ -382,7 +1177,7 @@ impl ViewNode for UnitsNode {
             render_pass.set_render_pipeline(pipeline);
             render_pass.set_bind_group(0, &bind_group, &[view_uniform_offset.offset]);
             render_pass.draw(
-                0..LARGE_UNITS_DATA_WIDTH * LARGE_UNITS_TEXTURE_HEIGHT * 6,
+                0..config.large_units_width * LARGE_UNITS_TEXTURE_HEIGHT * 6,
                 0..1,
             );
         }
@@ -408,16 +1203,7 @@ struct UnitPipeline {
 
 impl FromWorld for UnitPipeline {
     fn from_world(world: &mut World) -> Self {
-        let mut shader_defs = Vec::new();
-        shader_defs.extend_from_slice(&[
-            shader_def_uint!(MINIMAP_SCALE),
-            shader_def_uint!(UNITS_DATA_WIDTH),
-            shader_def_uint!(UNITS_DATA_HEIGHT),
-            shader_def_uint!(LARGE_UNITS_DATA_WIDTH),
-            shader_def_uint!(LARGE_UNITS_TEXTURE_WIDTH),
-            shader_def_uint!(LARGE_UNITS_TEXTURE_HEIGHT),
-            shader_def_uint!(ATTACK_RADIUS),
-        ]);
+        let shader_defs = world.resource::<SimulationConfig>().shader_defs();
 
         let render_device = world.resource::<RenderDevice>();
 
@@ -427,7 +1213,7 @@ impl FromWorld for UnitPipeline {
                 view_layout_entry(0),
                 globals_layout_entry(9),
                 utexture_layout_entry(101, TextureViewDimension::D2), // Prev Particle State
-                uniform_layout_entry(102, UnitCommand::min_size()),
+                uniform_layout_entry(102, TeamCommands::min_size()),
                 utexture_layout_entry(103, TextureViewDimension::D2), // Prev Attack data
                 ftexture_layout_entry(104, TextureViewDimension::D2Array), // Unit Material Texture
                 fsampler_layout_entry(105),
@@ -435,6 +1221,12 @@ impl FromWorld for UnitPipeline {
                 ftexture_layout_entry(107, TextureViewDimension::D2Array), // Unit Material Texture
                 utexture_layout_entry(108, TextureViewDimension::D2), // Minimap sm
                 utexture_layout_entry(109, TextureViewDimension::D2), // Minimap sm3
+                uniform_layout_entry(110, BalanceConfig::min_size()),
+                uniform_layout_entry(111, AiSettings::min_size()),
+                uniform_layout_entry(112, TeamWaypoints::min_size()),
+                utexture_layout_entry(113, TextureViewDimension::D2), // Flow Field
+                uniform_layout_entry(114, FlowFieldTargets::min_size()),
+                ftexture_layout_entry(115, TextureViewDimension::D2), // Terrain
             ],
         };
 
@@ -529,7 +1321,7 @@ impl FromWorld for UnitPipeline {
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct UnitsDataTextures {
     pub a: CachedTexture,
     pub b: CachedTexture,
@@ -539,20 +1331,34 @@ pub struct UnitsDataTextures {
     pub large_unit_b: CachedTexture,
 }
 
+impl UnitsDataTextures {
+    /// The textures as they were `ticks` ticks before the last one this frame, these are picked
+    /// for `SimulationState::frame` which is the last one.
+    pub fn rewind(&self, ticks: u32) -> Self {
+        let mut textures = self.clone();
+        if ticks % 2 == 1 {
+            std::mem::swap(&mut textures.attack_a, &mut textures.attack_b);
+            std::mem::swap(&mut textures.large_unit_a, &mut textures.large_unit_b);
+        }
+        textures
+    }
+}
+
 fn prepare_textures(
     mut commands: Commands,
     mut texture_cache: ResMut<TextureCache>,
     render_device: Res<RenderDevice>,
     views: Query<(Entity, &ExtractedCamera, &ExtractedView), With<UnitsPass>>,
-    frame_count: Res<FrameCount>,
+    simulation: Res<SimulationState>,
+    config: Res<SimulationConfig>,
 ) {
     for (_entity, _camera, _view) in &views {
         let mut texture_descriptor = TextureDescriptor {
             label: None,
             size: Extent3d {
                 depth_or_array_layers: 1,
-                width: UNITS_DATA_WIDTH,
-                height: UNITS_DATA_HEIGHT,
+                width: config.units_width,
+                height: config.units_height,
             },
             mip_level_count: 1,
             sample_count: 1,
@@ -560,6 +1366,7 @@ fn prepare_textures(
             format: UNITS_DATA_FORMAT,
             usage: TextureUsages::RENDER_ATTACHMENT
                 | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
                 | TextureUsages::COPY_DST,
             view_formats: &[],
         };
@@ -578,7 +1385,7 @@ fn prepare_textures(
         texture_descriptor.format = LARGE_UNITS_DATA_FORMAT;
         texture_descriptor.size = Extent3d {
             depth_or_array_layers: 1,
-            width: LARGE_UNITS_TEXTURE_WIDTH,
+            width: config.large_units_texture_width(),
             height: LARGE_UNITS_TEXTURE_HEIGHT,
         };
         texture_descriptor.label = Some("large_unit_data_a");
@@ -588,7 +1395,7 @@ fn prepare_textures(
         let large_unit_data_texture_b =
             texture_cache.get(&render_device, texture_descriptor.clone());
 
-        let textures = if frame_count.0 % 2 == 0 {
+        let textures = if simulation.frame % 2 == 0 {
             UnitsDataTextures {
                 a: unit_data_texture_a,
                 b: unit_data_texture_b,
@@ -612,6 +1419,208 @@ fn prepare_textures(
     }
 }
 
-fn clear_unit_command(mut unit_command: ResMut<UnitCommand>) {
-    *unit_command = UnitCommand::default();
+fn clear_team_commands(mut team_commands: ResMut<TeamCommands>) {
+    *team_commands = TeamCommands::default();
+}
+
+/// Queues this frame's orders and hands the oldest to the simulation if it ticks. With a tick rate
+/// below the frame rate most frames don't, and their orders would otherwise be overwritten.
+pub fn queue_team_commands(
+    mut team_commands: ResMut<TeamCommands>,
+    mut queued: ResMut<QueuedCommands>,
+    simulation: Res<SimulationState>,
+) {
+    // Orders given while paused are dropped, and a new match starts without any
+    if simulation.paused || simulation.reset {
+        *queued = QueuedCommands::default();
+    }
+    if simulation.paused {
+        *team_commands = TeamCommands::default();
+        return;
+    }
+    for (command, queue) in team_commands.teams.iter_mut().zip(&mut queued.teams) {
+        if command.is_pending() && !queue.back_mut().is_some_and(|last| last.merge(command)) {
+            queue.push_back(*command);
+        }
+        *command = if simulation.ticks > 0 {
+            queue.pop_front().unwrap_or_default()
+        } else {
+            UnitCommand::default()
+        };
+    }
+}
+
+fn advance_simulation(
+    mut simulation: ResMut<SimulationState>,
+    config: Res<SimulationConfig>,
+    time: Res<Time>,
+) {
+    simulation.reset = false;
+    simulation.ticks = 0;
+    if simulation.paused {
+        return;
+    }
+    if config.tick_rate == 0 {
+        simulation.ticks = 1;
+        simulation.tick_delta = time.delta_seconds();
+        simulation.tick_alpha = 0.0;
+    } else {
+        let tick_delta = 1.0 / config.tick_rate as f32;
+        simulation.tick_time += time.delta_seconds();
+        let ticks = (simulation.tick_time / tick_delta) as u32;
+        if ticks > MAX_TICKS_PER_FRAME {
+            simulation.ticks = MAX_TICKS_PER_FRAME;
+            simulation.tick_time = 0.0;
+        } else {
+            simulation.ticks = ticks;
+            simulation.tick_time -= ticks as f32 * tick_delta;
+        }
+        simulation.tick_delta = tick_delta;
+        simulation.tick_alpha = (simulation.tick_time / tick_delta).clamp(0.0, 1.0);
+    }
+    simulation.frame = simulation.frame.wrapping_add(simulation.ticks);
+}
+
+fn reset_simulation(
+    mut events: EventReader<ResetSimulation>,
+    mut simulation: ResMut<SimulationState>,
+    mut waypoints: ResMut<TeamWaypoints>,
+    config: Res<SimulationConfig>,
+) {
+    if events.read().count() > 0 {
+        *waypoints = TeamWaypoints::default();
+        simulation.frame = 0;
+        // Just the one tick on the cleared textures, like without a tick rate
+        simulation.ticks = simulation.ticks.min(1);
+        simulation.tick_time = 0.0;
+        simulation.seed = config.match_seed();
+        simulation.reset = true;
+        simulation.match_index = simulation.match_index.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f16_packing() {
+        assert_eq!(pack2x16float(vec2(1.0, -2.0)), 0xC000_3C00);
+        assert_eq!(pack2x16float(vec2(0.5, 65504.0)), 0x7BFF_3800);
+        // Ties round to even
+        assert_eq!(f32_to_f16(1.0 + 2.0f32.powi(-11)), 0x3C00);
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2.0f32.powi(-11)), 0x3C02);
+        assert_eq!(f32_to_f16(0.3), 0x34CD);
+        assert_eq!(f32_to_f16(2.0f32.powi(-24)), 0x0001);
+        assert_eq!(unpack2x16float(0xC000_3C00), vec2(1.0, -2.0));
+        assert_eq!(f16_to_f32(0x0001), 2.0f32.powi(-24));
+    }
+
+    #[test]
+    fn small_packing() {
+        assert_eq!(pack_2x4_to_8(uvec2(2, 11)), 0xB2);
+        assert_eq!(unpack_2x4_from_8(0xB2), uvec2(2, 11));
+        assert_eq!(pack_4x8_(uvec4(0x02, 0xB2, 200, 0x32)), 0x32C8_B202);
+        assert_eq!(unpack_4x8_(0x32C8_B202), uvec4(0x02, 0xB2, 200, 0x32));
+        assert_eq!(pack_2x16_(uvec2(300, 17)), 0x0011_012C);
+        assert_eq!(unpack_2x16_(0x0011_012C), uvec2(300, 17));
+    }
+
+    #[test]
+    fn unit_packing() {
+        let unit = Unit {
+            health: 200,
+            progress: 0.5,
+            step_dir: ivec2(1, -1),
+            dest: uvec2(300, 17),
+            mode: UNIT_MODE_MOVEING,
+            team: 3,
+            attacking_hydra: 7,
+            attacking_team: 2,
+            order: 3,
+            unit_type: UNIT_TYPE_SUPPORT,
+            selected: true,
+            group: 5,
+            id: 123456,
+        };
+        // a.y is attacking_hydra | order << 8 | unit_type << 12, d.y is attacking_team and
+        // selected | group << 1
+        let data = uvec4(0x2307_3800, 0x32C8_B202, 0x0011_012C, 123456);
+        assert_eq!(unit.pack(), data);
+        assert_eq!(Unit::unpack(data), unit);
+
+        let unit = Unit {
+            progress: 0.3,
+            ..default()
+        };
+        assert_eq!(unit.pack().x, 0x34CD);
+        assert_eq!(Unit::unpack(unit.pack()).progress, f16_to_f32(0x34CD));
+
+        for x in -1..=1 {
+            for y in -1..=1 {
+                let unit = Unit {
+                    step_dir: ivec2(x, y),
+                    ..default()
+                };
+                let nibbles = (x + 1) as u32 | (((y + 1) as u32) << 4);
+                assert_eq!(unit.pack().y, nibbles);
+                assert_eq!(Unit::unpack(unit.pack()).step_dir, ivec2(x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn queued_commands_merge() {
+        let mut select = UnitCommand::default();
+        select.select([Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y]);
+        let mut upgrade = UnitCommand::default();
+        upgrade.upgrade_attack_rate();
+        let move_to = |dest| UnitCommand {
+            command: COMMAND_MOVE,
+            dest,
+            ..default()
+        };
+
+        let mut queued = select;
+        assert!(queued.merge(&upgrade));
+        assert!(!queued.merge(&move_to(uvec2(3, 4))));
+        assert_eq!(queued.command, COMMAND_SELECT);
+        assert_eq!(queued.upgrade_request, 1 << 1);
+
+        let mut queued = move_to(uvec2(3, 4));
+        queued.upgrade_movment_rate();
+        assert!(queued.merge(&move_to(uvec2(5, 6))));
+        assert_eq!(queued.dest, uvec2(5, 6));
+        assert_eq!(queued.upgrade_request, 1);
+    }
+
+    #[test]
+    fn large_unit_packing() {
+        let unit = LargeUnit {
+            pos: vec2(128.25, 99.5),
+            dest: vec2(128.0, 100.0),
+            health: 25000,
+            mode: UNIT_MODE_MOVEING,
+            progress: 0.0,
+            team: 2,
+            dir_index: 5,
+            order: UNIT_ORDER_HOLD,
+        };
+        let data = uvec4(
+            128.25f32.to_bits(),
+            99.5f32.to_bits(),
+            0x0C80_1000,
+            0x0500_61A8 | (pack_2x4_to_8(uvec2(UNIT_MODE_MOVEING, UNIT_ORDER_HOLD)) << 16),
+        );
+        assert_eq!(unit.pack(), data);
+        assert_eq!(LargeUnit::unpack(data, uvec2(0, 1)), unit);
+
+        // Further than f16 of the old dest * 100 could hold, which a 1024 grid reaches
+        let far = LargeUnit {
+            dest: vec2(1000.0, 1000.0),
+            ..unit
+        };
+        assert_eq!(far.pack().z, 0x7D00_7D00);
+        assert_eq!(LargeUnit::unpack(far.pack(), uvec2(0, 1)), far);
+    }
 }
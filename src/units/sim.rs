@@ -0,0 +1,1328 @@
+// CPU reference implementation of the unit simulation.
+// Mirrors unit_evaluate.wgsl, unit_update.wgsl, large_unit_update.wgsl and the minimap summary
+// that large_unit_update.wgsl reads back, so the simulation can be stepped without a GPU.
+// Also mirrors flow_field.wgsl for the geese pathfinding.
+// Keep this in sync with the shaders.
+
+use bevy::math::*;
+
+use crate::balance::BalanceConfig;
+use crate::difficulty::AiSettings;
+use crate::flow_field::{
+    FlowFieldTargets, FLOW_BLOCKED, FLOW_FIELD_ITERATIONS, FLOW_STEP_COST, FLOW_UNREACHED,
+};
+use crate::nav_grid::NavGrid;
+use crate::units::{
+    command_order, f16_to_f32, f32_to_f16, hydra_start, in_select_region, pack_2x16_, unpack_2x16_,
+    unpack_4x8_, LargeUnit, SimulationConfig, TeamCommands, TeamWaypoints, Unit, ATTACK_RADIUS,
+    COMMAND_ASSIGN_GROUP, COMMAND_ATTACK_MOVE, COMMAND_HOLD, COMMAND_MOVE, COMMAND_PATROL,
+    COMMAND_RECALL_GROUP, COMMAND_SELECT, COMMAND_STOP, HATCH_REQUEST, LARGE_UNITS_TEXTURE_HEIGHT,
+    MAX_TEAMS, UNIT_MODE_ATTACK, UNIT_MODE_ATTACK_HYDRA, UNIT_MODE_IDLE, UNIT_MODE_MOVE,
+    UNIT_MODE_MOVEING, UNIT_ORDER_HOLD, UNIT_ORDER_MOVE, UNIT_ORDER_NONE, UNIT_ORDER_PATROL,
+    UNIT_TYPE_MELEE, UNIT_TYPE_RANGED, UNIT_TYPE_SUPPORT, WAYPOINT_RADIUS,
+};
+
+pub const LARGE_UNIT_SIZE: f32 = 4.0;
+
+// ---------------------------------------
+// Helpers, see sampling.wgsl and common.wgsl
+// ---------------------------------------
+
+pub fn uhash(a: u32, b: u32) -> u32 {
+    let mut x = a.wrapping_mul(1597334673) ^ b.wrapping_mul(3812015801);
+    // from https://nullprogram.com/blog/2018/07/31/
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^= x >> 16;
+    x
+}
+
+pub fn unormf(n: u32) -> f32 {
+    n as f32 * (1.0 / u32::MAX as f32)
+}
+
+pub fn hash_noise(ufrag_coord: UVec2, frame: u32) -> f32 {
+    unormf(uhash(
+        ufrag_coord.x,
+        (ufrag_coord.y << 11).wrapping_add(frame),
+    ))
+}
+
+pub fn sign2i(n: IVec2) -> IVec2 {
+    n.signum()
+}
+
+fn add_scalar(v: UVec2, s: u32) -> UVec2 {
+    uvec2(v.x.wrapping_add(s), v.y.wrapping_add(s))
+}
+
+/// The 8 cells around the center of a 3x3 square, `i` from 0 to 7
+pub fn neighbour_offset(i: u32) -> IVec2 {
+    let cell = i + (i >= 4) as u32;
+    ivec2((cell % 3) as i32 - 1, (cell / 3) as i32 - 1)
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UnitStats {
+    pub move_rate: f32,
+    pub attack_rate: f32,
+    pub attack_mult: f32,
+    pub large_move_rate: f32,
+    pub large_attack_rate: f32,
+    pub spawn_radius: f32,
+    pub spawn_rate: f32,
+    pub spawn_health: u32,
+    pub hydra_health: u32,
+}
+
+/// See `com::attack_damage`
+pub fn attack_damage(stats: &UnitStats, unit_type: u32, balance: &BalanceConfig) -> u32 {
+    let damage = balance.unit_type(unit_type).damage;
+    ((1 + stats.attack_mult as u32) as f32 * damage).ceil() as u32
+}
+
+/// See `com::spawn_mix`
+pub fn spawn_mix(
+    large_unit_tex: &SimTexture,
+    ludw: u32,
+    team: u32,
+    balance: &BalanceConfig,
+) -> Vec3 {
+    let picked = unpack_4x8_(
+        large_unit_tex
+            .load(ivec2(ludw as i32 + 1, team as i32 - 1))
+            .w,
+    );
+    if picked.w != 0 {
+        return picked.xyz().as_vec3();
+    }
+    balance.spawn_weights().as_vec3()
+}
+
+/// See `com::pick_unit_type`
+pub fn pick_unit_type(mix: Vec3, rng: f32) -> u32 {
+    let pick = rng * (mix.x + mix.y + mix.z);
+    if pick < mix.x {
+        UNIT_TYPE_RANGED
+    } else if pick < mix.x + mix.y {
+        UNIT_TYPE_MELEE
+    } else if mix.z > 0.0 {
+        UNIT_TYPE_SUPPORT
+    } else {
+        UNIT_TYPE_RANGED
+    }
+}
+
+// ---------------------------------------
+// Textures
+// ---------------------------------------
+
+/// A 2D `vec4<u32>` texture. Out of bounds loads return zero.
+#[derive(Clone, Debug)]
+pub struct SimTexture {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<UVec4>,
+}
+
+impl SimTexture {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![UVec4::ZERO; (width * height) as usize],
+        }
+    }
+
+    pub fn load(&self, coord: IVec2) -> UVec4 {
+        if coord.x < 0
+            || coord.y < 0
+            || coord.x >= self.width as i32
+            || coord.y >= self.height as i32
+        {
+            return UVec4::ZERO;
+        }
+        self.data[(coord.y as u32 * self.width + coord.x as u32) as usize]
+    }
+
+    pub fn store(&mut self, coord: UVec2, value: UVec4) {
+        self.data[(coord.y * self.width + coord.x) as usize] = value;
+    }
+
+    pub fn clear(&mut self) {
+        self.data.fill(UVec4::ZERO);
+    }
+
+    pub fn contains(&self, coord: IVec2) -> bool {
+        coord.x >= 0 && coord.y >= 0 && coord.x < self.width as i32 && coord.y < self.height as i32
+    }
+}
+
+// ---------------------------------------
+// Simulation
+// ---------------------------------------
+
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    /// Unit data a, the state at the end of each step.
+    pub units: SimTexture,
+    /// Unit data b, the output of the evaluate pass.
+    pub units_evaluated: SimTexture,
+    /// Attack map written by the last evaluate pass.
+    pub attack: SimTexture,
+    /// Large unit data written by the last large update pass.
+    pub large_units: SimTexture,
+    /// Sum of minimap_sm3_tex, one tick late just like on the GPU.
+    pub minimap_sum: MinimapSum,
+    pub frame_count: u32,
+    /// `SimulationState::seed`
+    pub seed: u32,
+    pub config: SimulationConfig,
+    pub balance: BalanceConfig,
+    pub ai: AiSettings,
+    pub waypoints: TeamWaypoints,
+    /// All open by default, set it to the baked grid before stepping
+    pub nav_grid: NavGrid,
+    pub flow_targets: FlowFieldTargets,
+    /// `FlowFieldTextures::a`, the flow fields after the last step.
+    pub flow_field: SimTexture,
+}
+
+impl Default for Simulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulation {
+    pub fn new() -> Self {
+        Self::with_config(SimulationConfig::default())
+    }
+
+    pub fn with_config(config: SimulationConfig) -> Self {
+        let (width, height) = (config.units_width, config.units_height);
+        Self {
+            units: SimTexture::new(width, height),
+            units_evaluated: SimTexture::new(width, height),
+            attack: SimTexture::new(width, height),
+            large_units: SimTexture::new(
+                config.large_units_texture_width(),
+                LARGE_UNITS_TEXTURE_HEIGHT,
+            ),
+            minimap_sum: MinimapSum::default(),
+            frame_count: 0,
+            seed: config.seed.unwrap_or_default(),
+            config,
+            balance: BalanceConfig::default(),
+            ai: AiSettings::default(),
+            waypoints: TeamWaypoints::default(),
+            nav_grid: NavGrid::new(uvec2(width, height)),
+            flow_targets: FlowFieldTargets::default(),
+            flow_field: SimTexture::new(width, height),
+        }
+    }
+
+    /// A frame with a single tick. Runs the flow field passes of `FlowFieldNode`, then the
+    /// evaluate, update and large update passes, then the minimap summary, in the same order as
+    /// `UnitsNode` and `MinimapNode`.
+    pub fn step(&mut self, commands: &TeamCommands, delta_time: f32) {
+        self.step_frame(commands, 1, delta_time);
+    }
+
+    /// One frame of `FlowFieldNode`, `UnitsNode` and `MinimapNode` with a tick rate, `ticks` steps
+    /// of `tick_delta` where only the first carries out `commands`. Like on the GPU the minimap is
+    /// rebuilt between ticks and once after the last one.
+    pub fn step_frame(&mut self, commands: &TeamCommands, ticks: u32, tick_delta: f32) {
+        // Flow Field
+        self.flow_targets.assign(commands);
+        for i in 0..FLOW_FIELD_ITERATIONS {
+            // Only the first pass starts fields over, the rest spread them
+            let targets = if i == 0 {
+                self.flow_targets
+            } else {
+                FlowFieldTargets {
+                    reset: 0,
+                    ..self.flow_targets
+                }
+            };
+            let prev_flow = self.flow_field.clone();
+            for y in 0..self.flow_field.height {
+                for x in 0..self.flow_field.width {
+                    let coord = uvec2(x, y);
+                    let flow = relax_flow_field(coord, &prev_flow, &self.nav_grid, &targets);
+                    self.flow_field.store(coord, flow);
+                }
+            }
+        }
+
+        for tick in 0..ticks {
+            if tick == 0 {
+                self.tick(commands, tick_delta);
+            } else {
+                self.minimap_sum = minimap_sum(&self.units);
+                self.tick(&TeamCommands::default(), tick_delta);
+            }
+        }
+        self.minimap_sum = minimap_sum(&self.units);
+    }
+
+    fn tick(&mut self, commands: &TeamCommands, delta_time: f32) {
+        // `advance_simulation` counts the frame before the node runs
+        self.frame_count = self.frame_count.wrapping_add(1);
+        let mut commands = *commands;
+        for command in &mut commands.teams {
+            command.delta_time = delta_time;
+            command.match_frame = self.frame_count;
+        }
+        commands.seed = self.seed;
+        let commands = &commands;
+
+        let prev_attack = self.attack.clone();
+        let prev_large_units = self.large_units.clone();
+
+        // Units Evaluate
+        for y in 0..self.units.height {
+            for x in 0..self.units.width {
+                let coord = uvec2(x, y);
+                let (unit_data, attack_data) =
+                    self.evaluate(coord, commands, delta_time, &prev_attack, &prev_large_units);
+                self.units_evaluated.store(coord, unit_data);
+                // Rgba8Uint
+                self.attack.store(coord, attack_data & 0xFF);
+            }
+        }
+
+        // Units Update
+        for y in 0..self.units.height {
+            for x in 0..self.units.width {
+                let coord = uvec2(x, y);
+                let unit_data = self.update(coord);
+                self.units.store(coord, unit_data);
+            }
+        }
+
+        // Large Units Update
+        for y in 0..self.large_units.height {
+            for x in 0..self.large_units.width {
+                let coord = uvec2(x, y);
+                let data = self.large_update(coord, commands, delta_time, &prev_large_units);
+                self.large_units.store(coord, data);
+            }
+        }
+    }
+
+    pub fn unit(&self, coord: UVec2) -> Unit {
+        Unit::unpack(self.units.load(coord.as_ivec2()))
+    }
+
+    pub fn hydra(&self, team: u32) -> LargeUnit {
+        let coord = uvec2(0, team - 1);
+        LargeUnit::unpack(self.large_units.load(coord.as_ivec2()), coord)
+    }
+
+    /// x: units lost, y: credits, z: bits of the f32 fraction of a credit earned so far
+    pub fn tracker(&self, team: u32) -> UVec4 {
+        self.large_units
+            .load(ivec2(self.config.large_units_width as i32, team as i32 - 1))
+    }
+
+    /// x: movement, y: attack, z: spawn
+    pub fn upgrades(&self, team: u32) -> UVec4 {
+        self.large_units.load(ivec2(
+            self.config.large_units_width as i32 + 1,
+            team as i32 - 1,
+        ))
+    }
+
+    pub fn unit_count(&self, team: u32) -> u32 {
+        self.units
+            .data
+            .iter()
+            .map(|data| Unit::unpack(*data))
+            .filter(|unit| unit.health > 0 && unit.id > 0 && unit.team == team)
+            .count() as u32
+    }
+
+    /// See `get_unit_stats`, `ludw` is `LARGE_UNITS_DATA_WIDTH`.
+    pub fn unit_stats(
+        large_unit_tex: &SimTexture,
+        ludw: u32,
+        team: u32,
+        balance: &BalanceConfig,
+    ) -> UnitStats {
+        let modifier = balance.team(team);
+        let upgrades = large_unit_tex.load(ivec2(ludw as i32 + 1, team.wrapping_sub(1) as i32));
+        let upgrades = vec4(
+            ((upgrades.x.wrapping_add(1)) as f32).sqrt(),
+            ((upgrades.y.wrapping_add(1)) as f32).sqrt(),
+            ((upgrades.z.wrapping_add(1)) as f32).sqrt(),
+            ((upgrades.w.wrapping_add(1)) as f32).sqrt(),
+        );
+        UnitStats {
+            move_rate: upgrades.x * balance.speed_move * modifier.move_rate,
+            attack_rate: upgrades.y * balance.speed_attack * modifier.attack_rate,
+            attack_mult: upgrades.y * 0.2,
+            large_move_rate: upgrades.x * balance.large_speed_move * modifier.move_rate,
+            large_attack_rate: upgrades.y * balance.large_speed_attack * modifier.attack_rate,
+            spawn_radius: upgrades.z * balance.spawn_radius,
+            spawn_rate: upgrades.z * balance.spawn_rate * modifier.spawn_rate,
+            spawn_health: (255.0 * modifier.health).min(255.0) as u32,
+            hydra_health: (balance.hydra_init_health as f32 * modifier.health) as u32,
+        }
+    }
+
+    /// See `com::rng_frame`
+    fn rng_frame(&self) -> u32 {
+        uhash(self.seed, self.frame_count)
+    }
+
+    /// See `hydra_hit` in large_unit_update.wgsl
+    fn hydra_hit(&self, progress: f32) -> bool {
+        if self.config.deterministic_attacks {
+            // Progress only reaches 1 on the last frame of each attack
+            progress >= 1.0
+        } else {
+            progress > 0.9
+        }
+    }
+
+    // See unit_evaluate.wgsl
+    fn evaluate(
+        &self,
+        ufrag_coord: UVec2,
+        commands: &TeamCommands,
+        delta_time: f32,
+        prev_attack: &SimTexture,
+        large_unit_tex: &SimTexture,
+    ) -> (UVec4, UVec4) {
+        let data_texture = &self.units;
+        let frame = self.rng_frame();
+        let ludw = self.config.large_units_width;
+        let frag_coord = ufrag_coord.as_vec2() + 0.5;
+        let ifrag_coord = ufrag_coord.as_ivec2();
+        let radius = ATTACK_RADIUS as i32;
+
+        let mut attack_data = prev_attack.load(ifrag_coord);
+
+        let mut unit = Unit::unpack(data_texture.load(ifrag_coord));
+
+        let unit_stats = Self::unit_stats(large_unit_tex, ludw, unit.team, &self.balance);
+        let type_stats = *self.balance.unit_type(unit.unit_type);
+
+        if unit.progress >= 1.0 {
+            unit.mode = UNIT_MODE_IDLE;
+            unit.attacking_hydra = 0;
+            unit.attacking_team = 0;
+        }
+
+        // Progress the attack had at the start of the frame
+        let attack_start = if unit.mode == UNIT_MODE_ATTACK {
+            unit.progress
+        } else {
+            -1.0
+        };
+
+        let step_mult = match unit.mode {
+            UNIT_MODE_MOVEING => {
+                // The terrain texture holds halfs
+                let water_depth = f16_to_f32(f32_to_f16(self.nav_grid.water_depth(ifrag_coord)));
+                unit_stats.move_rate
+                    * type_stats.move_rate
+                    * self.balance.terrain_move_rate(water_depth)
+            }
+            UNIT_MODE_ATTACK | UNIT_MODE_ATTACK_HYDRA => unit_stats.attack_rate,
+            _ => 0.0,
+        };
+
+        unit.progress += delta_time * step_mult;
+
+        // if there is living unit in this cell check if it moved to another cell last frame
+        if unit.health != 0 {
+            for x in -1..=1 {
+                for y in -1..=1 {
+                    let read_coord = ifrag_coord + ivec2(x, y);
+                    let other_unit = Unit::unpack(data_texture.load(read_coord));
+
+                    // If we're the same id and we're in the spot this unit came from, delete this one as it has moved
+                    if other_unit.id == unit.id && read_coord == ifrag_coord + other_unit.step_dir {
+                        return (UVec4::ZERO, UVec4::ZERO);
+                    }
+                }
+            }
+        }
+
+        // --- Spawn around large ---
+        let large_rng = hash_noise(ufrag_coord, frame.wrapping_add(45245));
+        let teams = self.config.teams;
+        let team_rng = ((hash_noise(ufrag_coord, frame.wrapping_add(647132)) * teams as f32)
+            as u32)
+            .min(teams - 1)
+            + 1;
+        let large_unit_frag_coord = ivec2(
+            (large_rng * self.config.large_units_width as f32) as i32,
+            team_rng as i32 - 1,
+        );
+        let large_unit = LargeUnit::unpack(
+            large_unit_tex.load(large_unit_frag_coord),
+            large_unit_frag_coord.as_uvec2(),
+        );
+
+        let spawn_unit_stats = Self::unit_stats(large_unit_tex, ludw, team_rng, &self.balance);
+
+        let rng = hash_noise(add_scalar(ufrag_coord, frame), frame.wrapping_add(34121));
+
+        // Nothing spawns inside buildings
+        let spawn_blocked = self.flow_field.load(ifrag_coord).x == FLOW_BLOCKED;
+        if large_unit.health > 0
+            && !spawn_blocked
+            && large_unit.pos.distance(frag_coord) < spawn_unit_stats.spawn_radius
+            && unit.health == 0
+            && (rng - 0.5).abs() < spawn_unit_stats.spawn_rate * delta_time
+        {
+            let mix = spawn_mix(large_unit_tex, ludw, team_rng, &self.balance);
+            let type_rng = hash_noise(ufrag_coord, frame.wrapping_add(81233));
+            let mut unit = Unit::unpack(UVec4::ZERO);
+            unit.unit_type = pick_unit_type(mix, type_rng);
+            let spawn_health = spawn_unit_stats.spawn_health as f32
+                * self.balance.unit_type(unit.unit_type).health;
+            unit.health = (spawn_health as u32).clamp(1, 255);
+            unit.id =
+                ((hash_noise(ufrag_coord, frame.wrapping_add(96421)) * (u32::MAX - 10) as f32)
+                    as u32)
+                    .wrapping_add(5);
+            unit.dest = ufrag_coord;
+            unit.team = team_rng;
+            return (unit.pack(), UVec4::ZERO);
+        }
+
+        // -----------------------
+
+        // If there is no unit here, return none
+        if unit.health == 0 || unit.id <= 4 {
+            return (UVec4::ZERO, UVec4::ZERO);
+        }
+        let command = commands.team(unit.team.clamp(1, MAX_TEAMS));
+        if command.command == COMMAND_SELECT {
+            unit.selected = in_select_region(command.select_region, ufrag_coord.as_vec2());
+        }
+        if command.command == COMMAND_ASSIGN_GROUP {
+            if unit.selected {
+                unit.group = command.control_group;
+            } else if unit.group == command.control_group {
+                unit.group = 0;
+            }
+        }
+        if command.command == COMMAND_RECALL_GROUP {
+            unit.selected = unit.group == command.control_group;
+        }
+        let ordered = command.unit_group == 1 && (command.selected_only == 0 || unit.selected);
+        if ordered
+            && matches!(
+                command.command,
+                COMMAND_MOVE | COMMAND_ATTACK_MOVE | COMMAND_PATROL
+            )
+        {
+            unit.dest = command.dest;
+            unit.order = command_order(command.command);
+            if unit.mode != UNIT_MODE_MOVEING {
+                unit.mode = UNIT_MODE_MOVE;
+                unit.progress = 0.0;
+            }
+        }
+        if ordered && matches!(command.command, COMMAND_STOP | COMMAND_HOLD) {
+            unit.dest = ufrag_coord;
+            unit.order = command_order(command.command);
+            if unit.mode == UNIT_MODE_MOVE {
+                unit.mode = UNIT_MODE_IDLE;
+            }
+        }
+        // Once close enough to a queued waypoint head for the next one
+        if unit.order != UNIT_ORDER_HOLD
+            && ufrag_coord.as_vec2().distance(unit.dest.as_vec2()) < WAYPOINT_RADIUS
+        {
+            let paths = self.waypoints.team(unit.team.clamp(1, MAX_TEAMS));
+            let next = paths.next(unit.dest.as_vec2()).as_uvec2();
+            if next == unit.dest && unit.order != UNIT_ORDER_PATROL {
+                // End of the path
+                unit.order = UNIT_ORDER_NONE;
+            }
+            unit.dest = next;
+        }
+        if unit.team != 1 {
+            let player_hydra = LargeUnit::unpack(large_unit_tex.load(IVec2::ZERO), UVec2::ZERO);
+            // Geese that get close enough go for the hydra
+            if player_hydra.health > 0
+                && player_hydra.pos.distance(frag_coord) < self.ai.hydra_aggro_radius
+            {
+                unit.dest = player_hydra.pos.as_uvec2();
+            }
+        }
+
+        let mut clear_attack_data = true;
+        if self.config.deterministic_attacks {
+            if unit.order != UNIT_ORDER_MOVE
+                && (unit.mode == UNIT_MODE_IDLE || unit.mode == UNIT_MODE_ATTACK)
+            {
+                if let Some(offset) =
+                    nearest_enemy(data_texture, &unit, ifrag_coord, type_stats.range)
+                {
+                    let damage = attack_damage(&unit_stats, unit.unit_type, &self.balance) as f32;
+                    let mut hit = 0;
+                    if attack_start >= 0.0 {
+                        // As stored, so the next frame starts where this one ended
+                        let end = f16_to_f32(f32_to_f16(unit.progress));
+                        hit = (damage * end.min(1.0)).floor() as u32
+                            - (damage * attack_start).floor() as u32;
+                        // Chain straight into the next attack
+                        if end >= 1.0 {
+                            unit.progress = end.fract();
+                            hit += (damage * unit.progress).floor() as u32;
+                        }
+                    } else {
+                        unit.mode = UNIT_MODE_ATTACK;
+                        unit.progress = 0.0;
+                    }
+                    let attack_vector = (offset + radius).as_uvec2();
+                    attack_data = uvec4(attack_vector.x, attack_vector.y, hit.min(255), 0);
+                    clear_attack_data = false;
+                } else if unit.mode == UNIT_MODE_IDLE {
+                    let hydra = nearest_enemy_hydra(
+                        large_unit_tex,
+                        &self.config,
+                        &unit,
+                        ufrag_coord.as_vec2(),
+                        type_stats.range,
+                    );
+                    if hydra.x > 0 {
+                        unit.attacking_hydra = hydra.x;
+                        unit.attacking_team = hydra.y;
+                        unit.mode = UNIT_MODE_ATTACK_HYDRA;
+                        unit.progress = 0.0;
+                    }
+                } else {
+                    unit.mode = UNIT_MODE_IDLE;
+                }
+            }
+        } else {
+            if unit.health > 0
+                && unit.order != UNIT_ORDER_MOVE
+                && large_unit.health > 0
+                && large_unit.team != unit.team
+                && large_unit.pos.distance(ufrag_coord.as_vec2()) < type_stats.range - 1.0
+            {
+                unit.attacking_hydra = large_unit_frag_coord.x as u32 + 1;
+                unit.attacking_team = large_unit.team;
+                unit.mode = UNIT_MODE_ATTACK_HYDRA;
+                unit.progress = 0.0;
+            }
+
+            if unit.mode == UNIT_MODE_IDLE && unit.order != UNIT_ORDER_MOVE {
+                // First check if the unit we were shooting at is still there and use that one first otherwise find a new one
+                let prev_attack_data = prev_attack.load(ifrag_coord);
+                let prev_attack_vector = prev_attack_data.xy().as_ivec2() - radius;
+
+                let other_unit = Unit::unpack(data_texture.load(ifrag_coord + prev_attack_vector));
+                if other_unit.id != unit.id
+                    && other_unit.health != 0
+                    && other_unit.team > 0
+                    && unit.team != other_unit.team
+                {
+                    unit.mode = UNIT_MODE_ATTACK;
+                    unit.progress = 0.0;
+                    clear_attack_data = false;
+                } else {
+                    let noise = vec2(
+                        hash_noise(add_scalar(ufrag_coord, frame), frame.wrapping_add(4563)),
+                        hash_noise(add_scalar(ufrag_coord, frame), frame.wrapping_add(2564)),
+                    ) * 2.0
+                        - 1.0;
+                    let attack_offset = (noise * type_stats.range)
+                        .as_ivec2()
+                        .clamp(IVec2::splat(-radius), IVec2::splat(radius));
+                    let attack_coord = attack_offset + ifrag_coord;
+
+                    let other_unit = Unit::unpack(data_texture.load(attack_coord));
+                    let attack_damage = attack_damage(&unit_stats, unit.unit_type, &self.balance);
+
+                    if other_unit.id != unit.id
+                        && other_unit.health > 0
+                        && other_unit.team > 0
+                        && unit.team != other_unit.team
+                    {
+                        let attack_vector = (attack_offset + radius).as_uvec2();
+                        attack_data = uvec4(attack_vector.x, attack_vector.y, attack_damage, 0);
+                        unit.mode = UNIT_MODE_ATTACK;
+                        unit.progress = 0.0;
+                        clear_attack_data = false;
+                    }
+                }
+            }
+        }
+        if unit.mode != UNIT_MODE_ATTACK && clear_attack_data {
+            attack_data = UVec4::ZERO;
+        }
+
+        if unit.order != UNIT_ORDER_HOLD
+            && (unit.mode == UNIT_MODE_IDLE
+                || (unit.mode == UNIT_MODE_MOVE && ufrag_coord != unit.dest))
+        {
+            let f_to_dest = unit.dest.as_vec2() - ufrag_coord.as_vec2();
+
+            let mut dir_noise = vec2(
+                hash_noise(ufrag_coord, frame.wrapping_add(74856)),
+                hash_noise(ufrag_coord, frame.wrapping_add(36422)),
+            ) * 2.0
+                - 1.0;
+            dir_noise *= f_to_dest.length();
+
+            let mut step_dir = sign2i((f_to_dest + dir_noise).as_ivec2());
+            // Once the flow field has spread this far step to the neighbour closest to dest along it
+            if self
+                .flow_targets
+                .has_field(unit.team, pack_2x16_(unit.dest))
+            {
+                let channel = (unit.team.clamp(1, MAX_TEAMS) - 1) as usize;
+                let mut best = self.flow_field.load(ifrag_coord)[channel];
+                // Start somewhere random so ties don't all go the same way
+                let start = (hash_noise(ufrag_coord, frame.wrapping_add(51277)) * 8.0) as u32;
+                for i in 0..8 {
+                    let offset = neighbour_offset((start + i) % 8);
+                    let coord = ifrag_coord + offset;
+                    if !self.flow_field.contains(coord) {
+                        continue;
+                    }
+                    let dist = self.flow_field.load(coord)[channel];
+                    if best < FLOW_UNREACHED && dist < best {
+                        best = dist;
+                        step_dir = offset;
+                    }
+                }
+            }
+            // Never walk into a building, but let geese stuck in one out
+            if self.flow_field.load(ifrag_coord + step_dir).x == FLOW_BLOCKED
+                && self.flow_field.load(ifrag_coord).x != FLOW_BLOCKED
+            {
+                step_dir = IVec2::ZERO;
+            }
+            if step_dir != IVec2::ZERO {
+                unit.step_dir = step_dir;
+                unit.mode = UNIT_MODE_MOVE;
+                attack_data = UVec4::ZERO;
+                unit.progress = 0.0;
+            }
+        }
+
+        (unit.pack(), attack_data)
+    }
+
+    // See unit_update.wgsl
+    fn update(&self, ufrag_coord: UVec2) -> UVec4 {
+        let data_texture = &self.units_evaluated;
+        let attack_texture = &self.attack;
+        let frame = self.rng_frame();
+        let ifrag_coord = ufrag_coord.as_ivec2();
+
+        let mut unit = Unit::unpack(data_texture.load(ifrag_coord));
+
+        let shuffle_x = ((hash_noise(ufrag_coord, frame.wrapping_add(83746)) * 3.0)
+            .round_ties_even() as i32)
+            .max(0);
+        let shuffle_y = ((hash_noise(ufrag_coord, frame.wrapping_add(12339)) * 3.0)
+            .round_ties_even() as i32)
+            .max(0);
+
+        // if there is not living unit in this cell, we can allow another unit to take this spot
+        if unit.health == 0 {
+            // Check to see if any of the surrounding tiles want to move into this one and pick one
+            for x in 0..3 {
+                for y in 0..3 {
+                    let offset = ivec2((x + shuffle_x) % 3 - 1, (y + shuffle_y) % 3 - 1);
+                    if offset == IVec2::ZERO {
+                        continue;
+                    }
+
+                    let read_coord = ifrag_coord + offset;
+
+                    let mut other_unit = Unit::unpack(data_texture.load(read_coord));
+                    if other_unit.mode == UNIT_MODE_MOVE
+                        && other_unit.health > 0
+                        && read_coord + other_unit.step_dir == ifrag_coord
+                        && other_unit.progress == 0.0
+                    {
+                        other_unit.mode = UNIT_MODE_MOVEING;
+                        return other_unit.pack();
+                    }
+                }
+            }
+        } else {
+            let radius = ATTACK_RADIUS as i32;
+            // Check if a unit attacked us
+            for x in -radius..=radius {
+                for y in -radius..=radius {
+                    let offset = ivec2(x, y);
+                    if offset == IVec2::ZERO {
+                        continue;
+                    }
+                    let read_coord = ifrag_coord + offset;
+                    let attack_data = attack_texture.load(read_coord);
+                    let attack_vector = attack_data.xy().as_ivec2() - radius;
+                    let attack_damage = attack_data.z;
+
+                    if attack_damage > 0 && read_coord + attack_vector == ifrag_coord {
+                        let health = unit.health as i32 - attack_damage as i32;
+                        unit.health = health.max(0) as u32;
+                        if unit.health == 0 {
+                            let mut dead_unit = Unit::unpack(UVec4::ZERO);
+                            dead_unit.id = unit.team;
+                            return dead_unit.pack();
+                        }
+                    }
+                }
+            }
+        }
+
+        unit.pack()
+    }
+
+    /// See `team_purchases` in large_unit_update.wgsl, `row` is team - 1
+    fn team_purchases(
+        &self,
+        row: u32,
+        commands: &TeamCommands,
+        large_unit_tex: &SimTexture,
+    ) -> Purchases {
+        let ludw = self.config.large_units_width;
+        let mut out = Purchases {
+            upgrades: UVec4::ZERO,
+            credits: 0,
+            hatch_slot: ludw,
+            hatch_pos: Vec2::ZERO,
+        };
+        let prev_tracker = large_unit_tex.load(ivec2(ludw as i32, row as i32));
+        let prev_upgrade = large_unit_tex.load(ivec2(ludw as i32 + 1, row as i32));
+        let mut credits = prev_tracker.y;
+        // The player buys in a fixed order, the AI in the order the difficulty picks
+        let upgrade_order = if row != 0 {
+            self.ai.upgrade_order.xyz()
+        } else {
+            uvec3(1, 2, 0)
+        };
+        let command = &commands.teams[row as usize];
+        for upgrade in upgrade_order.to_array() {
+            let upgrade = upgrade as usize;
+            let upgrade_request = (command.upgrade_request & (1 << upgrade)) > 0;
+            let upgrade_cost = self.balance.upgrade_cost(prev_upgrade[upgrade], row + 1);
+            if upgrade_request && credits > upgrade_cost {
+                credits -= upgrade_cost;
+                out.upgrades[upgrade] += 1;
+            }
+        }
+
+        // Hatches next to the hydra hydra orders go to if it's alive, the first living one
+        // otherwise
+        if command.upgrade_request & HATCH_REQUEST != 0 && credits > self.balance.hatch_cost {
+            let hydra = |slot: u32| {
+                let coord = uvec2(slot, row);
+                LargeUnit::unpack(large_unit_tex.load(coord.as_ivec2()), coord)
+            };
+            let free_slot = (0..ludw).find(|slot| hydra(*slot).health == 0);
+            let mut living = (0..ludw).filter(|slot| hydra(*slot).health > 0);
+            let parent_slot = living
+                .clone()
+                .find(|slot| slot + 1 == command.hydra)
+                .or_else(|| living.next());
+            if let (Some(free_slot), Some(parent_slot)) = (free_slot, parent_slot) {
+                credits -= self.balance.hatch_cost;
+                out.hatch_slot = free_slot;
+                out.hatch_pos = hydra(parent_slot).pos;
+            }
+        }
+        out.credits = credits;
+        out
+    }
+
+    // See large_unit_update.wgsl
+    fn large_update(
+        &self,
+        ufrag_coord: UVec2,
+        commands: &TeamCommands,
+        delta_time: f32,
+        large_unit_tex: &SimTexture,
+    ) -> UVec4 {
+        let data_texture = &self.units;
+        let frame = self.rng_frame();
+        let ifrag_coord = ufrag_coord.as_ivec2();
+        let ludw = self.config.large_units_width;
+
+        let purchases = self.team_purchases(ufrag_coord.y, commands, large_unit_tex);
+
+        if ufrag_coord.x >= ludw {
+            // Process players
+            let team = ufrag_coord.y as usize;
+            if ufrag_coord.x == ludw + 1 {
+                let prev_upgrade = large_unit_tex.load(ivec2(ludw as i32 + 1, ifrag_coord.y));
+                let mut upgrade = prev_upgrade + purchases.upgrades;
+                // w is the spawn mix the team picked, see spawn_mix
+                let spawn_mix = commands.teams[team].spawn_mix;
+                if spawn_mix != 0 {
+                    upgrade.w = spawn_mix;
+                }
+                return upgrade;
+            }
+            let mut out = UVec4::ZERO;
+            let prev_tracker = large_unit_tex.load(ivec2(ludw as i32, ifrag_coord.y));
+            // Died tracker
+            let died = self.minimap_sum.died;
+            out.x = died[team].wrapping_add(prev_tracker.x);
+            // Credits tracker, kills aren't tracked so each enemy death is split between the
+            // other teams
+            let enemies_died = died.to_array().iter().sum::<u32>().wrapping_sub(died[team]);
+            let income = if team != 0 { self.ai.income } else { 1.0 };
+            let earned = enemies_died as f32 * 3.0 / (self.config.teams - 1) as f32 * income
+                + f32::from_bits(prev_tracker.z);
+            let whole = earned.floor();
+            out.y = (whole as u32).wrapping_add(purchases.credits);
+            // Fractions of a credit carry over to the next tick
+            out.z = (earned - whole).to_bits();
+            return out;
+        }
+
+        let mut unit = LargeUnit::unpack(large_unit_tex.load(ifrag_coord), ufrag_coord);
+        let unit_stats = Self::unit_stats(large_unit_tex, ludw, unit.team, &self.balance);
+
+        // --- Hatch ---
+        if ufrag_coord.x == purchases.hatch_slot {
+            unit = LargeUnit::unpack(UVec4::ZERO, ufrag_coord);
+            unit.health = unit_stats.hydra_health;
+            let angle = hash_noise(ufrag_coord, frame.wrapping_add(28411)) * std::f32::consts::TAU;
+            unit.pos = purchases.hatch_pos + Vec2::from_angle(angle) * LARGE_UNIT_SIZE;
+            unit.dest = unit.pos;
+            return unit.pack();
+        }
+
+        let command = commands.team(unit.team.clamp(1, MAX_TEAMS));
+
+        // --- Random spawn ---
+        if unit.health == 0
+            && ufrag_coord.x == 0
+            && unit.team <= self.config.teams
+            && command.match_frame < 5000
+        {
+            unit = LargeUnit::unpack(UVec4::ZERO, ufrag_coord);
+            unit.health = unit_stats.hydra_health;
+            unit.pos = hydra_start(unit.team)
+                * uvec2(self.config.units_width, self.config.units_height).as_vec2();
+            unit.dest = unit.pos;
+            return unit.pack();
+        }
+
+        let ordered = unit.health > 0
+            && command.unit_group == 0
+            && (command.hydra == 0 || command.hydra == ufrag_coord.x + 1);
+        if ordered
+            && matches!(
+                command.command,
+                COMMAND_MOVE | COMMAND_ATTACK_MOVE | COMMAND_PATROL
+            )
+        {
+            unit.dest = command.dest.as_vec2();
+            unit.order = command_order(command.command);
+            if unit.mode != UNIT_MODE_MOVEING {
+                unit.mode = UNIT_MODE_MOVEING;
+                unit.progress = 0.0;
+            }
+        }
+        if ordered && matches!(command.command, COMMAND_STOP | COMMAND_HOLD) {
+            unit.dest = unit.pos;
+            unit.order = command_order(command.command);
+            unit.mode = UNIT_MODE_IDLE;
+        }
+
+        if unit.mode == UNIT_MODE_MOVEING {
+            if unit.dest.distance(unit.pos) > 0.1 {
+                unit.pos += (unit.dest - unit.pos)
+                    .normalize()
+                    .clamp(Vec2::NEG_ONE, Vec2::ONE)
+                    * unit_stats.large_move_rate
+                    * delta_time;
+            } else {
+                unit.mode = UNIT_MODE_IDLE;
+            }
+        }
+
+        // Arrived, carry on along the queued path if there is one
+        if unit.health > 0 && unit.mode == UNIT_MODE_IDLE && unit.order != UNIT_ORDER_HOLD {
+            let next = self
+                .waypoints
+                .team(unit.team.clamp(1, MAX_TEAMS))
+                .next(unit.dest);
+            if next != unit.dest {
+                unit.dest = next;
+                unit.mode = UNIT_MODE_MOVEING;
+            }
+        }
+
+        // See if there's any other large units in close proximity and if so move away a bit
+        let other_rng = hash_noise(ufrag_coord, frame.wrapping_add(45245));
+        let other_unit_frag_coord = ivec2((other_rng * ludw as f32) as i32, ifrag_coord.y);
+        let other_unit = LargeUnit::unpack(
+            large_unit_tex.load(other_unit_frag_coord),
+            other_unit_frag_coord.as_uvec2(),
+        );
+        // Holding hydras don't make way
+        if unit.mode == UNIT_MODE_IDLE
+            && unit.order != UNIT_ORDER_HOLD
+            && other_unit.health > 0
+            && other_unit_frag_coord.x != ifrag_coord.x
+            && other_unit.pos.distance(unit.pos) < LARGE_UNIT_SIZE
+        {
+            let roam_rng = vec2(
+                hash_noise(ufrag_coord, frame.wrapping_add(67821)),
+                hash_noise(ufrag_coord, frame.wrapping_add(15348)),
+            ) * 2.0
+                - 1.0;
+            unit.dest += roam_rng * LARGE_UNIT_SIZE;
+            unit.mode = UNIT_MODE_MOVEING;
+        }
+
+        if unit.mode == UNIT_MODE_MOVEING {
+            let step_dir = sign2i((unit.dest - unit.pos).as_ivec2());
+            unit.dir_index = match (step_dir.x, step_dir.y) {
+                (1, 0) => 0,
+                (1, -1) => 1,
+                (0, -1) => 2,
+                (-1, -1) => 3,
+                (-1, 0) => 4,
+                (-1, 1) => 5,
+                (0, 1) => 6,
+                (1, 1) => 7,
+                _ => unit.dir_index,
+            };
+        }
+
+        let radius = ATTACK_RADIUS as i32;
+        // Check if a unit attacked us
+        for x in -radius..=radius {
+            for y in -radius..=radius {
+                let read_coord = unit.pos.as_ivec2() + ivec2(x, y);
+
+                let other_unit = Unit::unpack(data_texture.load(read_coord));
+                let other_unit_stats =
+                    Self::unit_stats(large_unit_tex, ludw, other_unit.team, &self.balance);
+                let attack_damage =
+                    attack_damage(&other_unit_stats, other_unit.unit_type, &self.balance);
+
+                if attack_damage > 0
+                    && other_unit.attacking_hydra > 0
+                    && other_unit.attacking_hydra - 1 == ufrag_coord.x
+                    && other_unit.attacking_team == unit.team
+                    && self.hydra_hit(other_unit.progress)
+                {
+                    let health = unit.health as i32 - attack_damage as i32;
+                    unit.health = health.max(0) as u32;
+                    if unit.health == 0 {
+                        let dead_unit = LargeUnit::unpack(UVec4::ZERO, ufrag_coord);
+                        return dead_unit.pack();
+                    }
+                }
+            }
+        }
+
+        unit.pack()
+    }
+}
+
+/// See `nearest_enemy` in unit_evaluate.wgsl, the offset to the closest enemy goose in range
+fn nearest_enemy(
+    data_texture: &SimTexture,
+    unit: &Unit,
+    ifrag_coord: IVec2,
+    range: f32,
+) -> Option<IVec2> {
+    let radius = ATTACK_RADIUS as i32;
+    let mut best: Option<(IVec2, i32, u32)> = None;
+    for x in -radius..=radius {
+        for y in -radius..=radius {
+            let offset = ivec2(x, y);
+            let dist = x * x + y * y;
+            if dist as f32 > range * range {
+                continue;
+            }
+            let other_unit = Unit::unpack(data_texture.load(ifrag_coord + offset));
+            if other_unit.id == unit.id
+                || other_unit.health == 0
+                || other_unit.team == 0
+                || other_unit.team == unit.team
+            {
+                continue;
+            }
+            let closer = best.is_none_or(|(_, best_dist, best_id)| {
+                dist < best_dist || (dist == best_dist && other_unit.id < best_id)
+            });
+            if closer {
+                best = Some((offset, dist, other_unit.id));
+            }
+        }
+    }
+    best.map(|(offset, _, _)| offset)
+}
+
+/// See `nearest_enemy_hydra` in unit_evaluate.wgsl
+fn nearest_enemy_hydra(
+    large_unit_tex: &SimTexture,
+    config: &SimulationConfig,
+    unit: &Unit,
+    pos: Vec2,
+    range: f32,
+) -> UVec2 {
+    let mut out = UVec2::ZERO;
+    let mut best_dist = range - 1.0;
+    for team in 1..=config.teams {
+        if team == unit.team {
+            continue;
+        }
+        for slot in 0..config.large_units_width {
+            let coord = uvec2(slot, team - 1);
+            let large_unit = LargeUnit::unpack(large_unit_tex.load(coord.as_ivec2()), coord);
+            let dist = large_unit.pos.distance(pos);
+            if large_unit.health > 0 && dist < best_dist {
+                out = uvec2(slot + 1, team);
+                best_dist = dist;
+            }
+        }
+    }
+    out
+}
+
+/// See `Purchases` in large_unit_update.wgsl
+struct Purchases {
+    /// Levels bought, indexed like the upgrade column
+    upgrades: UVec4,
+    /// Left over
+    credits: u32,
+    /// Free slot the new hydra goes in, `large_units_width` if none hatches
+    hatch_slot: u32,
+    /// Of the hydra it hatches next to
+    hatch_pos: Vec2,
+}
+
+/// See flow_field.wgsl
+fn relax_flow_field(
+    ufrag_coord: UVec2,
+    prev_flow: &SimTexture,
+    nav_grid: &NavGrid,
+    targets: &FlowFieldTargets,
+) -> UVec4 {
+    let ifrag_coord = ufrag_coord.as_ivec2();
+    if nav_grid.is_blocked(ifrag_coord) {
+        return UVec4::splat(FLOW_BLOCKED);
+    }
+
+    let prev = prev_flow.load(ifrag_coord);
+    let mut flow = UVec4::ZERO;
+    for channel in 0..MAX_TEAMS as usize {
+        let in_use = (targets.in_use >> channel) & 1 != 0;
+        let reset = (targets.reset >> channel) & 1 != 0;
+        if !in_use {
+            flow[channel] = FLOW_UNREACHED;
+            continue;
+        }
+        if unpack_2x16_(targets.dests[channel]) == ufrag_coord {
+            flow[channel] = 0;
+            continue;
+        }
+        flow[channel] = if reset {
+            FLOW_UNREACHED
+        } else {
+            prev[channel].min(FLOW_UNREACHED)
+        };
+        if reset {
+            continue;
+        }
+        for i in 0..8 {
+            let offset = neighbour_offset(i);
+            let coord = ifrag_coord + offset;
+            if !prev_flow.contains(coord) {
+                continue;
+            }
+            let neighbour = prev_flow.load(coord)[channel];
+            let step = if offset.x == 0 || offset.y == 0 {
+                FLOW_STEP_COST.x
+            } else {
+                FLOW_STEP_COST.y
+            };
+            if neighbour < FLOW_UNREACHED {
+                flow[channel] = flow[channel].min(neighbour + step);
+            }
+        }
+    }
+    flow
+}
+
+/// Geese alive and died this tick per team, indexed by team - 1
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MinimapSum {
+    pub alive: UVec4,
+    pub died: UVec4,
+}
+
+// See minimap_update.wgsl and minimap_downsample.wgsl
+fn minimap_sum(data_texture: &SimTexture) -> MinimapSum {
+    let mut out = MinimapSum::default();
+    for data in &data_texture.data {
+        let unit = Unit::unpack(*data);
+        if unit.health > 0 && unit.id > 0 && unit.team > 0 && unit.team <= MAX_TEAMS {
+            out.alive[unit.team as usize - 1] += 1;
+        }
+        // Dead units leave their team in the id for a frame
+        if unit.health == 0 && unit.id > 0 && unit.id <= MAX_TEAMS {
+            out.died[unit.id as usize - 1] += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    };
+
+    use super::*;
+
+    fn run(seed: u32, steps: u32) -> Simulation {
+        let config = SimulationConfig::with_grid_size(256).with_seed(seed);
+        let mut sim = Simulation::with_config(config);
+        for _ in 0..steps {
+            sim.step(&TeamCommands::default(), 0.1);
+        }
+        sim
+    }
+
+    fn state_hash(sim: &Simulation) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for texture in [&sim.units, &sim.attack, &sim.large_units] {
+            for data in &texture.data {
+                data.to_array().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn hydras_spawn_geese() {
+        let sim = run(1, 100);
+        for team in 1..=sim.config.teams {
+            assert_eq!(sim.hydra(team).health, sim.balance.hydra_init_health);
+            assert!(sim.unit_count(team) > 0, "team {team} has no geese");
+        }
+    }
+
+    #[test]
+    fn same_seed_same_match() {
+        let a = run(7, 100);
+        let b = run(7, 100);
+        for team in 1..=a.config.teams {
+            assert_eq!(a.unit_count(team), b.unit_count(team));
+        }
+        assert_eq!(state_hash(&a), state_hash(&b));
+        assert_ne!(state_hash(&a), state_hash(&run(8, 100)));
+    }
+
+    /// Two rows of ranged geese holding 4 cells apart, team 2 within reach of the player's hydra.
+    /// Hydras don't spawn geese so only the fight changes the counts.
+    fn fight() -> Simulation {
+        let config = SimulationConfig::with_grid_size(256)
+            .with_seed(3)
+            .with_deterministic_attacks(true);
+        let mut sim = Simulation::with_config(config);
+        sim.balance.spawn_rate = 0.0;
+        for (team, y, health) in [(1, 107, 1), (2, 103, 2)] {
+            for x in 120..136 {
+                let unit = Unit {
+                    health,
+                    dest: uvec2(x, y),
+                    team,
+                    order: UNIT_ORDER_HOLD,
+                    unit_type: UNIT_TYPE_RANGED,
+                    id: team * 100 + x,
+                    ..Default::default()
+                };
+                sim.units.store(uvec2(x, y), unit.pack());
+            }
+        }
+        sim
+    }
+
+    fn play(mut sim: Simulation, seconds: f32, delta_time: f32) -> Simulation {
+        for _ in 0..(seconds / delta_time).round() as u32 {
+            sim.step(&TeamCommands::default(), delta_time);
+        }
+        sim
+    }
+
+    #[test]
+    fn fights_play_out_the_same_at_any_frame_rate() {
+        let slow = play(fight(), 2.5, 1.0 / 30.0);
+        let fast = play(fight(), 2.5, 1.0 / 144.0);
+
+        // Team 2 wins the exchange of hits, then goes for the hydra
+        assert_eq!(fast.unit_count(1), 0);
+        assert_eq!(fast.unit_count(2), 16);
+        let init_health = fast.balance.hydra_init_health;
+        let damage = init_health - fast.hydra(1).health;
+        assert!(damage > 0, "the hydra was never hit");
+
+        // A kill can land a tick apart, which is worth at most one goose either way. Hydra
+        // attacks restart a tick after they land so the hydra can be off by a tenth.
+        for team in 1..=2 {
+            assert!(slow.unit_count(team).abs_diff(fast.unit_count(team)) <= 1);
+        }
+        let slow_damage = init_health - slow.hydra(1).health;
+        assert!(slow_damage.abs_diff(damage) <= damage / 10 + 1);
+        assert_eq!(slow.hydra(2).health, fast.hydra(2).health);
+    }
+
+    #[test]
+    fn deaths_are_tracked_once_at_any_tick_rate() {
+        let mut sim = fight();
+        for _ in 0..150 {
+            sim.step_frame(&TeamCommands::default(), 4, 1.0 / 240.0);
+            // The geese that died on the last tick are in the minimap, the tracker has the rest
+            let counted = sim.tracker(1).x + sim.minimap_sum.died[0];
+            assert_eq!(counted, 16 - sim.unit_count(1));
+        }
+        assert_eq!(sim.unit_count(1), 0);
+        assert_eq!(sim.tracker(1).x, 16);
+        assert_eq!(sim.tracker(2).x, 0);
+    }
+
+    #[test]
+    fn credits_keep_fractions() {
+        let config = SimulationConfig::with_grid_size(256).with_teams(3);
+        let mut sim = Simulation::with_config(config);
+        sim.balance.spawn_rate = 0.0;
+        // A goose of team 2 dies, worth 3 credits split between the 2 other teams
+        for credits in [1, 3] {
+            sim.minimap_sum.died = uvec4(0, 1, 0, 0);
+            sim.step(&TeamCommands::default(), 0.1);
+            assert_eq!(sim.tracker(1).y, credits);
+        }
+    }
+
+    #[test]
+    fn hydras_only_respawn_early_in_the_match() {
+        let mut sim = run(1, 1);
+        sim.large_units.store(uvec2(0, 0), UVec4::ZERO);
+        sim.step(&TeamCommands::default(), 0.1);
+        assert!(sim.hydra(1).health > 0);
+
+        sim.large_units.store(uvec2(0, 0), UVec4::ZERO);
+        sim.frame_count = 5000;
+        sim.step(&TeamCommands::default(), 0.1);
+        assert_eq!(sim.hydra(1).health, 0);
+    }
+}